@@ -0,0 +1,63 @@
+use std::path::{Component, Path, PathBuf};
+use crate::LibError;
+
+/// Resolves the name of an archive entry to a path inside the directory it is extracted to.
+///
+/// Archive entry names are untrusted: a crafted entry like `../../etc/passwd` or `/etc/passwd`
+/// would otherwise be written outside the output directory. Names with `..` components, roots
+/// or drive prefixes are rejected, while harmless `.` components are dropped. Backslashes are
+/// treated as separators, as some archivers write them.
+///
+/// # Parameters
+///
+/// - `name`: The entry name as stored in the archive.
+///
+/// # Returns
+///
+/// The relative path of the entry, or [`StaticPreprocessingError::UnsafeArchiveEntry`](crate::StaticPreprocessingError::UnsafeArchiveEntry)
+/// if it would escape the output directory or is empty.
+///
+/// # Examples
+///
+/// ```
+/// # use std::path::PathBuf;
+/// # use static_preprocessing::archive::safe_entry_path;
+/// assert_eq!(safe_entry_path("css/./main.css").unwrap(), PathBuf::from("css/main.css"));
+/// assert!(safe_entry_path("../../etc/passwd").is_err());
+/// ```
+pub fn safe_entry_path(name: &str) -> Result<PathBuf, LibError> {
+    let unsafe_entry = || LibError::UnsafeArchiveEntry(name.to_string());
+
+    let normalized = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(unsafe_entry())
+        }
+    }
+
+    // A drive letter only parses as a prefix on Windows, so it is checked by hand
+    let has_drive = normalized.as_bytes().get(1) == Some(&b':');
+    if path.as_os_str().is_empty() || has_drive {
+        return Err(unsafe_entry());
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_entry_path_blocks_traversal() {
+        for name in ["../../etc/passwd", "css/../../outside.css", "/etc/passwd", "..\\..\\windows\\win.ini", "C:/windows/win.ini", "", "./"] {
+            assert!(matches!(safe_entry_path(name), Err(LibError::UnsafeArchiveEntry(entry)) if entry == name), "{name}");
+        }
+
+        assert_eq!(safe_entry_path("img/logo.png").unwrap(), PathBuf::from("img").join("logo.png"));
+        assert_eq!(safe_entry_path("img\\logo.png").unwrap(), PathBuf::from("img").join("logo.png"));
+        assert_eq!(safe_entry_path("./main..css").unwrap(), PathBuf::from("main..css"));
+    }
+}
\ No newline at end of file
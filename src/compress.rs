@@ -0,0 +1,156 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+use flate2::{write::GzEncoder, Compression};
+use crate::LibError;
+
+/// Compresses bytes with gzip at the highest compression level.
+pub fn gzip(contents: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(contents)?;
+    encoder.finish()
+}
+
+/// Compresses bytes with brotli at the highest quality.
+pub fn brotli(contents: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut compressed = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+        encoder.write_all(contents)?;
+    }
+    Ok(compressed)
+}
+
+/// Writes gzip and brotli compressed siblings of a file, as `<file>.gz` and `<file>.br`.
+///
+/// # Parameters
+///
+/// - `path`: The path of the file to compress.
+///
+/// # Returns
+///
+/// The paths of the written siblings, or an error if reading the file or writing a sibling fails.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::compress::write_compressed_siblings;
+/// #
+/// let dir = tempdir().unwrap();
+/// let path = dir.path().join("main.css");
+/// fs::write(&path, "body { margin: 0; }").unwrap();
+///
+/// write_compressed_siblings(&path).unwrap();
+///
+/// assert!(dir.path().join("main.css.gz").exists());
+/// assert!(dir.path().join("main.css.br").exists());
+/// ```
+pub fn write_compressed_siblings(path: &Path) -> Result<Vec<PathBuf>, LibError> {
+    write_worthwhile_siblings(path, None)
+}
+
+/// Writes the gzip and brotli compressed siblings of a file that save enough space.
+///
+/// A sibling is only written if `compressed_size <= original_size * (1 - min_ratio)`, so with a
+/// `min_ratio` of `0.1` each encoding must save at least 10%. Without a `min_ratio`, both
+/// siblings are always written, like [`write_compressed_siblings`] does.
+///
+/// # Parameters
+///
+/// - `path`: The path of the file to compress.
+/// - `min_ratio`: The fraction of the original size a sibling must save.
+///
+/// # Returns
+///
+/// The paths of the written siblings, or an error if reading the file or writing a sibling fails.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::compress::write_worthwhile_siblings;
+/// #
+/// let dir = tempdir().unwrap();
+/// let path = dir.path().join("main.css");
+/// fs::write(&path, "body { margin: 0; }".repeat(20)).unwrap();
+///
+/// let siblings = write_worthwhile_siblings(&path, Some(0.5)).unwrap();
+///
+/// assert_eq!(siblings.len(), 2);
+/// ```
+pub fn write_worthwhile_siblings(path: &Path, min_ratio: Option<f64>) -> Result<Vec<PathBuf>, LibError> {
+    let contents = fs::read(path)?;
+    let max_size = min_ratio.map(|ratio| contents.len() as f64 * (1.0 - ratio));
+
+    let mut siblings = Vec::new();
+    for (ext, compressed) in [("gz", gzip(&contents)?), ("br", brotli(&contents)?)] {
+        if max_size.is_some_and(|max_size| compressed.len() as f64 > max_size) {
+            continue;
+        }
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(ext);
+        let sibling = PathBuf::from(sibling);
+        fs::write(&sibling, compressed)?;
+        siblings.push(sibling);
+    }
+
+    Ok(siblings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gzip() {
+        use std::io::Read;
+        use flate2::read::GzDecoder;
+
+        let contents = b"body { margin: 0; } body { margin: 0; } body { margin: 0; }";
+        let compressed = gzip(contents).unwrap();
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, contents);
+    }
+
+    #[test]
+    fn test_write_worthwhile_siblings() {
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let random_path = dir.path().join("random.bin");
+        let text_path = dir.path().join("main.css");
+        let mut random = vec![0; 4096];
+        blake3::Hasher::new().finalize_xof().fill(&mut random);
+        fs::write(&random_path, &random).unwrap();
+        fs::write(&text_path, "body { margin: 0; }".repeat(50)).unwrap();
+
+        // Random bytes don't compress, so neither sibling saves 10%
+        assert!(write_worthwhile_siblings(&random_path, Some(0.1)).unwrap().is_empty());
+        assert!(!dir.path().join("random.bin.gz").exists());
+        assert!(!dir.path().join("random.bin.br").exists());
+
+        assert_eq!(write_worthwhile_siblings(&text_path, Some(0.1)).unwrap().len(), 2);
+        assert!(dir.path().join("main.css.gz").exists());
+        assert!(dir.path().join("main.css.br").exists());
+    }
+
+    #[test]
+    fn test_brotli() {
+        use std::io::Read;
+
+        let contents = b"body { margin: 0; } body { margin: 0; } body { margin: 0; }";
+        let compressed = brotli(contents).unwrap();
+
+        let mut decompressed = Vec::new();
+        ::brotli::Decompressor::new(compressed.as_slice(), 4096).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, contents);
+    }
+}
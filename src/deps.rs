@@ -0,0 +1,215 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf}
+};
+use lightningcss::{
+    rules::CssRule,
+    stylesheet::{ParserOptions, StyleSheet},
+    values::url::Url,
+    visit_types,
+    visitor::{Visit, VisitTypes, Visitor}
+};
+use crate::{detect_file_type, normalize_path, FileType, LibError};
+
+/// Finds the local files a stylesheet or script depends on.
+///
+/// Stylesheets depend on the files of their `@import` rules and relative `url()`s, and scripts
+/// on the relative (`./` or `../`) specifiers of their static `import`s, `export … from`s and
+/// dynamic `import()`s. Remote URLs, bare module specifiers and references to missing files are
+/// left out, as are the dependencies of files of any other type.
+///
+/// # Parameters
+///
+/// - `path`: The path of the stylesheet or script.
+///
+/// # Returns
+///
+/// The lexically normalized paths of the existing dependencies, in the order they are referenced,
+/// or an error if the file can't be read or a stylesheet can't be parsed.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::deps::dependencies;
+/// #
+/// let dir = tempdir().unwrap();
+/// fs::write(dir.path().join("main.js"), "import { helper } from './util.js';\nimport 'lodash';").unwrap();
+/// fs::write(dir.path().join("util.js"), "export const helper = 1;").unwrap();
+///
+/// assert_eq!(dependencies(&dir.path().join("main.js")).unwrap(), [dir.path().join("util.js")]);
+/// ```
+pub fn dependencies(path: &Path) -> Result<Vec<PathBuf>, LibError> {
+    let file_type = path.extension().and_then(|ext| ext.to_str()).map_or(FileType::Other, detect_file_type);
+    let specifiers = match file_type {
+        FileType::CSS => css_references(&fs::read_to_string(path)?)?,
+        FileType::JS => js_imports(&fs::read_to_string(path)?)
+            .into_iter()
+            .filter(|specifier| specifier.starts_with("./") || specifier.starts_with("../"))
+            .map(str::to_string)
+            .collect(),
+        _ => return Ok(Vec::new())
+    };
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut dependencies = Vec::new();
+    for specifier in specifiers {
+        let file = &specifier[..specifier.find(['?', '#']).unwrap_or(specifier.len())];
+        let dependency = normalize_path(&dir.join(file));
+        if !file.is_empty() && dependency.is_file() && !dependencies.contains(&dependency) {
+            dependencies.push(dependency);
+        }
+    }
+    Ok(dependencies)
+}
+
+/// Collects entrypoints and every file they depend on, directly or through other dependencies.
+///
+/// # Parameters
+///
+/// - `entries`: The entrypoints to start from.
+///
+/// # Returns
+///
+/// The entrypoints followed by their dependencies, each once, in the order they are discovered,
+/// or the first error finding the [`dependencies`] of a file.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::deps::reachable_files;
+/// #
+/// let dir = tempdir().unwrap();
+/// fs::write(dir.path().join("main.css"), "@import 'base.css';").unwrap();
+/// fs::write(dir.path().join("base.css"), "body { background: url(bg.png); }").unwrap();
+/// fs::write(dir.path().join("bg.png"), b"").unwrap();
+///
+/// let files = reachable_files(&[dir.path().join("main.css")]).unwrap();
+///
+/// assert_eq!(files, [dir.path().join("main.css"), dir.path().join("base.css"), dir.path().join("bg.png")]);
+/// ```
+pub fn reachable_files(entries: &[PathBuf]) -> Result<Vec<PathBuf>, LibError> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    let mut pending: VecDeque<PathBuf> = entries.iter().map(|entry| normalize_path(entry)).collect();
+    while let Some(path) = pending.pop_front() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        pending.extend(dependencies(&path)?);
+        files.push(path);
+    }
+    Ok(files)
+}
+
+/// Returns the URLs of the `@import` rules and relative `url()`s of a stylesheet.
+fn css_references(css: &str) -> Result<Vec<String>, LibError> {
+    let mut ss = StyleSheet::parse(css, ParserOptions::default())
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let mut references: Vec<String> = ss.rules.0
+        .iter()
+        .filter_map(|rule| match rule {
+            CssRule::Import(import) => Some(import.url.to_string()),
+            _ => None
+        })
+        .collect();
+
+    let mut collector = UrlCollector(Vec::new());
+    let Ok(()) = ss.visit(&mut collector);
+    references.extend(collector.0);
+
+    Ok(references
+        .into_iter()
+        .filter(|url| !url.starts_with(['/', '#']) && !url.split('/').next().is_some_and(|first| first.contains(':')))
+        .collect())
+}
+
+/// Collects the `url()`s of a stylesheet.
+struct UrlCollector(Vec<String>);
+
+impl<'i> Visitor<'i> for UrlCollector {
+    type Error = std::convert::Infallible;
+
+    fn visit_types(&self) -> VisitTypes {
+        visit_types!(URLS)
+    }
+
+    fn visit_url(&mut self, url: &mut Url<'i>) -> Result<(), Self::Error> {
+        self.0.push(url.url.to_string());
+        Ok(())
+    }
+}
+
+/// Returns the specifiers of the imports and re-exports of a script, skipping comments and other strings.
+fn js_imports(source: &str) -> Vec<&str> {
+    let mut specifiers = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(['"', '\'', '`', '/']) {
+        let (head, tail) = rest.split_at(start);
+        if tail.starts_with("//") {
+            rest = tail.find('\n').map_or("", |end| &tail[end..]);
+            continue;
+        }
+        if tail.starts_with("/*") {
+            rest = tail.find("*/").map_or("", |end| &tail[end + 2..]);
+            continue;
+        }
+        let quote = tail.chars().next().unwrap_or_default();
+        if quote == '/' {
+            rest = &tail[1..];
+            continue;
+        }
+
+        // Find the end of the string, including escaped quotes
+        let mut escaped = false;
+        let Some(end) = tail[1..].find(|c| {
+            let closes = c == quote && !escaped;
+            escaped = c == '\\' && !escaped;
+            closes
+        }) else { break };
+
+        let before = head.trim_end();
+        let imported = quote != '`'
+            && (ends_with_keyword(before, "from") || ends_with_keyword(before, "import")
+                || before.strip_suffix('(').is_some_and(|before| ends_with_keyword(before.trim_end(), "import")));
+        if imported {
+            specifiers.push(&tail[1..end + 1]);
+        }
+        rest = &tail[end + 2..];
+    }
+    specifiers
+}
+
+/// Returns whether `code` ends with `keyword` as a whole word.
+fn ends_with_keyword(code: &str, keyword: &str) -> bool {
+    code.strip_suffix(keyword)
+        .is_some_and(|before| !before.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$' || c == '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_imports() {
+        let source = r#"
+            import a from "./a.js";
+            import { b } from './b.js';
+            import './c.js';
+            export * from "./d.js";
+            const e = await import('./e.js');
+            // import "./commented.js";
+            /* import "./block.js"; */
+            const text = "import './string.js'";
+            const from = 1; console.log(from, "./not-imported.js");
+            const t = `${x} from "./template.js"`;
+        "#;
+
+        assert_eq!(js_imports(source), ["./a.js", "./b.js", "./c.js", "./d.js", "./e.js"]);
+    }
+}
@@ -1,6 +1,160 @@
-use std::io;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
 use crate::File;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// The size of each of the samples hashed for files above [`HashNaming::sample_threshold`].
+pub const HASH_SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Counter keeping the temporary files of concurrent [`hash_copy_file`] calls apart.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How the hash digest is spelled in hashed filenames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashEncoding {
+    /// Lowercase hexadecimal, 64 characters for the default 32-byte digest.
+    #[default]
+    Hex,
+    /// Lowercase base32 (RFC 4648 alphabet without padding), 52 characters for the default
+    /// 32-byte digest. Safe in URLs and
+    /// on case-insensitive file systems.
+    Base32,
+    /// Base62 (`0-9A-Za-z`), 43 characters for the default 32-byte digest. Safe in URLs, but names differing only in case may
+    /// clash on case-insensitive file systems, however unlikely that is.
+    Base62
+}
+
+impl HashEncoding {
+    /// Encodes a digest of any length. The encoded length depends only on the digest length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use static_preprocessing::hash::HashEncoding;
+    /// let digest = blake3::hash(b"body { margin: 0; }");
+    ///
+    /// assert_eq!(HashEncoding::Hex.encode(digest.as_bytes()), digest.to_hex().as_str());
+    /// assert_eq!(HashEncoding::Base32.encode(digest.as_bytes()).len(), 52);
+    /// assert_eq!(HashEncoding::Base62.encode(digest.as_bytes()).len(), 43);
+    /// ```
+    pub fn encode(&self, digest: &[u8]) -> String {
+        match self {
+            HashEncoding::Hex => digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            HashEncoding::Base32 => {
+                const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+                let mut encoded = String::with_capacity((digest.len() * 8).div_ceil(5));
+                let (mut buffer, mut bits) = (0u32, 0);
+                for byte in digest {
+                    buffer = (buffer << 8) | u32::from(*byte);
+                    bits += 8;
+                    while bits >= 5 {
+                        bits -= 5;
+                        encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+                    }
+                }
+                if bits > 0 {
+                    encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+                }
+                encoded
+            },
+            HashEncoding::Base62 => {
+                const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+                // Long division of the big-endian digest, padded to the width of the largest one
+                let width = (digest.len() as f64 * 8.0 / 62f64.log2()).ceil() as usize;
+                let mut number = digest.to_vec();
+                let mut digits = Vec::with_capacity(width);
+                for _ in 0..width {
+                    let mut remainder = 0u32;
+                    for byte in number.iter_mut() {
+                        let value = (remainder << 8) | u32::from(*byte);
+                        *byte = (value / 62) as u8;
+                        remainder = value % 62;
+                    }
+                    digits.push(ALPHABET[remainder as usize] as char);
+                }
+                digits.iter().rev().collect()
+            }
+        }
+    }
+}
+
+/// How hashed filenames are derived from file contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HashNaming {
+    /// How the hash is spelled.
+    pub encoding: HashEncoding,
+    /// Whether the extension is hashed along with the contents, so files with identical bytes
+    /// but different extensions get different stems, e.g. for caches keyed on the stem alone.
+    pub include_extension: bool,
+    /// When set, the name is derived from this many bytes of BLAKE3's extendable output
+    /// instead of the standard 32-byte digest, e.g. 48 bytes for more entropy or 16 for shorter
+    /// names.
+    pub digest_length: Option<NonZeroUsize>,
+    /// When set, files of at least this many bytes are named after their size and three
+    /// [`HASH_SAMPLE_SIZE`] samples from their start, middle and end rather than their whole
+    /// contents. Only the naming is sampled: the file is still read in full wherever it is
+    /// copied or transformed, so this saves hashing time rather than I/O. Two files that only
+    /// differ outside the samples get the same name.
+    pub sample_threshold: Option<u64>
+}
+
+impl HashNaming {
+    /// Returns a hasher primed for a file with the given extension, ready for its contents.
+    pub fn hasher(&self, ext: &str) -> blake3::Hasher {
+        let mut hasher = blake3::Hasher::new();
+        if self.include_extension {
+            hasher.update(ext.as_bytes());
+            hasher.update(&[0]);
+        }
+        hasher
+    }
+
+    /// Returns a hasher for a file with the given extension that has been fed its contents, or
+    /// samples of them if configured to.
+    pub fn content_hasher(&self, ext: &str, contents: &[u8]) -> blake3::Hasher {
+        let mut hasher = self.hasher(ext);
+        if self.samples(contents.len() as u64) {
+            // Reading from memory can't fail
+            let _ = hash_samples(&mut hasher, &mut io::Cursor::new(contents), contents.len() as u64);
+        } else {
+            hasher.update(contents);
+        }
+        hasher
+    }
+
+    /// Returns whether a file of `len` bytes is hashed by samples.
+    pub(crate) fn samples(&self, len: u64) -> bool {
+        self.sample_threshold.is_some_and(|threshold| len >= threshold)
+    }
+
+    /// Returns the encoded filename stem for a hasher that has been fed the file's contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::num::NonZeroUsize;
+    /// # use static_preprocessing::hash::{HashEncoding, HashNaming};
+    /// let naming = HashNaming { digest_length: NonZeroUsize::new(48), ..HashNaming::default() };
+    /// let mut hasher = naming.hasher("css");
+    /// hasher.update(b"body { margin: 0; }");
+    ///
+    /// assert_eq!(naming.stem(&hasher).len(), 96);
+    /// ```
+    pub fn stem(&self, hasher: &blake3::Hasher) -> String {
+        match self.digest_length {
+            Some(length) => {
+                let mut digest = vec![0; length.get()];
+                hasher.finalize_xof().fill(&mut digest);
+                self.encoding.encode(&digest)
+            },
+            None => self.encoding.encode(hasher.finalize().as_bytes())
+        }
+    }
+}
 
 /// Renames a [`File`] based on the BLAKE3 hash of its contents.
 ///
@@ -15,8 +169,8 @@ use std::path::Path;
 ///
 /// # Returns
 ///
-/// A new [`File`] with an updated `filename`, or an [`io::Error`] if the file's
-/// extension is invalid.
+/// A new [`File`] with an updated `filename` and its `hash` set to the raw digest, or an
+/// [`io::Error`] if the file's extension is invalid.
 ///
 /// # Examples
 ///
@@ -28,25 +182,271 @@ use std::path::Path;
 ///     filename: "main.css".to_string(),
 ///     file_type: FileType::CSS,
 ///     contents: b"body { margin: 0; }".to_vec(),
+///     hash: None,
 /// };
 ///
 /// let renamed = hash_file_rename(file).unwrap();
 /// assert!(renamed.filename.ends_with(".css"));
 /// assert!(renamed.filename.len() > ".css".len());
+/// assert!(renamed.hash.is_some());
 /// ```
 pub fn hash_file_rename(file: File) -> Result<File, io::Error> {
-    let hash = blake3::hash(file.contents.as_slice());
+    hash_file_rename_with(file, HashNaming::default())
+}
+
+/// Renames a [`File`] like [`hash_file_rename`], naming it as configured by a [`HashNaming`].
+///
+/// The attached `hash` is the 32-byte digest the name is derived from, which includes the
+/// extension if configured to. A longer or shorter `digest_length` extends or truncates it.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{File, FileType};
+/// # use static_preprocessing::hash::{hash_file_rename_with, HashEncoding, HashNaming};
+/// #
+/// let file = File {
+///     filename: "main.css".to_string(),
+///     file_type: FileType::CSS,
+///     contents: b"body { margin: 0; }".to_vec(),
+///     hash: None,
+/// };
+///
+/// let naming = HashNaming { encoding: HashEncoding::Base62, ..HashNaming::default() };
+/// let renamed = hash_file_rename_with(file, naming).unwrap();
+/// assert_eq!(renamed.filename.len(), 43 + ".css".len());
+/// ```
+pub fn hash_file_rename_with(file: File, naming: HashNaming) -> Result<File, io::Error> {
     let ext = Path::new(&file.filename)
         .extension()
         .and_then(|ext| ext.to_str())
         .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file extension."))?;
-    let new_name = format!("{}.{}", hash.to_string(), ext);
+    let hasher = naming.content_hasher(ext, &file.contents);
+    let hash = hasher.finalize();
+    let new_name = format!("{}.{}", naming.stem(&hasher), ext);
     Ok(File {
         filename: new_name,
+        hash: Some(*hash.as_bytes()),
         ..file
     })
 }
 
+/// Copies a file into a directory while hashing it, without loading it into memory.
+///
+/// The file is streamed through a BLAKE3 hasher into a temporary file in `dir`. The returned
+/// filename is the one [`hash_file_rename_with`] would produce for the same contents, so the
+/// caller can rename the temporary file into place.
+///
+/// # Parameters
+///
+/// - `source`: The path of the file to copy.
+/// - `dir`: The directory to write the temporary copy into.
+/// - `naming`: How the filename is derived from the hash.
+///
+/// # Returns
+///
+/// The path of the temporary copy and the hashed filename, or an [`io::Error`] if the file's
+/// extension is invalid or copying fails.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::hash::{hash_copy_file, HashNaming};
+/// #
+/// let dir = tempdir().unwrap();
+/// let source = dir.path().join("logo.png");
+/// fs::write(&source, b"not really a png").unwrap();
+///
+/// let (temp_path, filename) = hash_copy_file(&source, dir.path(), HashNaming::default()).unwrap();
+/// fs::rename(&temp_path, dir.path().join(&filename)).unwrap();
+///
+/// assert!(filename.ends_with(".png"));
+/// assert_eq!(fs::read(dir.path().join(&filename)).unwrap(), b"not really a png");
+/// ```
+pub fn hash_copy_file(source: &Path, dir: &Path, naming: HashNaming) -> Result<(PathBuf, String), io::Error> {
+    let ext = source
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file extension."))?;
+
+    let temp_path = dir.join(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let copy = || -> Result<blake3::Hasher, io::Error> {
+        let mut reader = fs::File::open(source)?;
+        let len = reader.metadata()?.len();
+        if naming.samples(len) {
+            let mut writer = io::BufWriter::new(fs::File::create(&temp_path)?);
+            io::copy(&mut reader, &mut writer)?;
+            io::Write::flush(&mut writer)?;
+            let mut hasher = naming.hasher(ext);
+            hash_samples(&mut hasher, &mut reader, len)?;
+            return Ok(hasher);
+        }
+        let mut writer = HashingWriter {
+            inner: io::BufWriter::new(fs::File::create(&temp_path)?),
+            hasher: naming.hasher(ext)
+        };
+        io::copy(&mut reader, &mut writer)?;
+        io::Write::flush(&mut writer)?;
+        Ok(writer.hasher)
+    };
+
+    match copy() {
+        Ok(hasher) => Ok((temp_path, format!("{}.{}", naming.stem(&hasher), ext))),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+/// Computes the hashed filename of a file on disk, without loading it into memory.
+///
+/// The returned filename is the one [`hash_file_rename_with`] would produce for the same contents.
+///
+/// # Parameters
+///
+/// - `source`: The path of the file to hash.
+/// - `naming`: How the filename is derived from the hash.
+///
+/// # Returns
+///
+/// The hashed filename, or an [`io::Error`] if the file's extension is invalid or reading fails.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::hash::{hash_copy_file, hashed_filename, HashNaming};
+/// #
+/// let dir = tempdir().unwrap();
+/// let source = dir.path().join("logo.png");
+/// fs::write(&source, b"not really a png").unwrap();
+///
+/// let (_, copied_filename) = hash_copy_file(&source, dir.path(), HashNaming::default()).unwrap();
+/// assert_eq!(hashed_filename(&source, HashNaming::default()).unwrap(), copied_filename);
+/// ```
+pub fn hashed_filename(source: &Path, naming: HashNaming) -> Result<String, io::Error> {
+    let ext = source
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file extension."))?;
+
+    let mut file = fs::File::open(source)?;
+    let len = file.metadata()?.len();
+    let mut hasher = naming.hasher(ext);
+    if naming.samples(len) {
+        hash_samples(&mut hasher, &mut file, len)?;
+    } else {
+        hasher.update_reader(file)?;
+    }
+    Ok(format!("{}.{}", naming.stem(&hasher), ext))
+}
+
+/// Writes a `<file>.sha256` sidecar with the SHA-256 checksum of a file.
+///
+/// The sidecar uses the `sha256sum` format, `<hex digest>  <filename>`, so it can be checked
+/// with `sha256sum -c`.
+///
+/// # Parameters
+///
+/// - `path`: The path of the file to checksum.
+///
+/// # Returns
+///
+/// The path of the written sidecar, or an [`io::Error`] if reading the file or writing the
+/// sidecar fails.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::hash::write_sha256_sidecar;
+/// #
+/// let dir = tempdir().unwrap();
+/// let path = dir.path().join("main.css");
+/// fs::write(&path, "").unwrap();
+///
+/// let sidecar = write_sha256_sidecar(&path).unwrap();
+///
+/// assert_eq!(
+///     fs::read_to_string(sidecar).unwrap(),
+///     "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  main.css\n"
+/// );
+/// ```
+pub fn write_sha256_sidecar(path: &Path) -> Result<PathBuf, io::Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    let sidecar = PathBuf::from(sidecar);
+    fs::write(&sidecar, format!("{:x}  {}\n", hasher.finalize(), filename))?;
+
+    Ok(sidecar)
+}
+
+/// Computes the Content Security Policy hash source of an inline script or stylesheet.
+///
+/// # Parameters
+///
+/// - `contents`: The exact contents of the inline `<script>` or `<style>` element.
+///
+/// # Returns
+///
+/// The hash as `sha256-<base64 digest>`, to be quoted in a `script-src` or `style-src` directive.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::hash::csp_hash;
+/// assert_eq!(csp_hash(b"alert('Hello, world.');"), "sha256-qznLcsROx4GACP2dm0UCKCzCG+HiZ1guq6ZZDob/Tng=");
+/// ```
+pub fn csp_hash(contents: &[u8]) -> String {
+    format!("sha256-{}", base64::engine::general_purpose::STANDARD.encode(Sha256::digest(contents)))
+}
+
+/// Feeds the length of a `len`-byte file and the samples at its start, middle and end into a hasher.
+fn hash_samples<R: Read + Seek>(hasher: &mut blake3::Hasher, reader: &mut R, len: u64) -> Result<(), io::Error> {
+    hasher.update(&len.to_le_bytes());
+    let last = len.saturating_sub(HASH_SAMPLE_SIZE);
+    let mut sample = Vec::new();
+    for offset in [0, last / 2, last] {
+        reader.seek(SeekFrom::Start(offset))?;
+        sample.clear();
+        reader.by_ref().take(HASH_SAMPLE_SIZE).read_to_end(&mut sample)?;
+        hasher.update(&sample);
+    }
+    Ok(())
+}
+
+/// A writer that feeds everything written through it into a hasher.
+struct HashingWriter<W: io::Write> {
+    inner: W,
+    hasher: blake3::Hasher
+}
+
+impl<W: io::Write> io::Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -59,6 +459,7 @@ mod tests {
             filename: "example.css".to_string(),
             file_type: FileType::CSS,
             contents: b"body { margin: 0; }".to_vec(),
+            hash: None,
         };
 
         let renamed = hash_file_rename(file).unwrap();
@@ -73,4 +474,191 @@ mod tests {
         assert_eq!(renamed.file_type, FileType::CSS);
         assert_eq!(renamed.contents, b"body { margin: 0; }");
     }
+
+    #[test]
+    fn test_hash_file_rename_attaches_digest() {
+        use crate::File;
+        use crate::FileType;
+
+        let file = File {
+            filename: "example.css".to_string(),
+            file_type: FileType::CSS,
+            contents: b"body { margin: 0; }".to_vec(),
+            hash: None,
+        };
+
+        let renamed = hash_file_rename(file).unwrap();
+
+        // Verify the attached digest is the BLAKE3 hash of the contents
+        let expected = blake3::hash(b"body { margin: 0; }");
+        assert_eq!(renamed.hash, Some(*expected.as_bytes()));
+        assert!(renamed.filename.starts_with(&expected.to_hex().to_string()));
+    }
+
+    #[test]
+    fn test_hash_copy_file() {
+        use crate::{File, FileType};
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("data.bin");
+        let contents: Vec<u8> = (0..=255).cycle().take(50_000).collect();
+        fs::write(&source, &contents).unwrap();
+
+        let (temp_path, filename) = hash_copy_file(&source, dir.path(), HashNaming::default()).unwrap();
+
+        // The copy is identical and named like the in-memory rename
+        let renamed = hash_file_rename(File {
+            filename: "data.bin".to_string(),
+            file_type: FileType::Other,
+            contents: contents.clone(),
+            hash: None,
+        }).unwrap();
+        assert_eq!(filename, renamed.filename);
+        assert_eq!(fs::read(&temp_path).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_hash_copy_file_multi_megabyte() {
+        use crate::{File, FileType};
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("video.mp4");
+        // Non-repeating bytes, so every chunk of the stream hashes differently
+        let mut state = 0x2545_f491_u32;
+        let contents: Vec<u8> = (0..5 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        fs::write(&source, &contents).unwrap();
+
+        for naming in [HashNaming::default(), HashNaming { encoding: HashEncoding::Base62, include_extension: true, ..HashNaming::default() }] {
+            let (temp_path, filename) = hash_copy_file(&source, dir.path(), naming).unwrap();
+            let buffered = hash_file_rename_with(File {
+                filename: "video.mp4".to_string(),
+                file_type: FileType::Video,
+                contents: contents.clone(),
+                hash: None,
+            }, naming).unwrap();
+
+            assert_eq!(filename, buffered.filename);
+            assert_eq!(fs::read(&temp_path).unwrap(), buffered.contents);
+            fs::remove_file(temp_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_hash_encodings() {
+        use crate::{File, FileType};
+
+        let rename = |encoding| hash_file_rename_with(File {
+            filename: "main.css".to_string(),
+            file_type: FileType::CSS,
+            contents: b"body { margin: 0; }".to_vec(),
+            hash: None,
+        }, HashNaming { encoding, ..HashNaming::default() }).unwrap().filename;
+
+        // Stable across runs, distinct from hex and filename-safe
+        let base62 = rename(HashEncoding::Base62);
+        assert_eq!(base62, rename(HashEncoding::Base62));
+        assert_ne!(base62, rename(HashEncoding::Hex));
+        assert!(base62.trim_end_matches(".css").chars().all(|c| c.is_ascii_alphanumeric()));
+        let base32 = rename(HashEncoding::Base32);
+        assert!(base32.trim_end_matches(".css").chars().all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)));
+
+        // Known values: all zeros and a single set low bit
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        assert_eq!(HashEncoding::Base62.encode(&[0; 32]), "0".repeat(43));
+        assert_eq!(HashEncoding::Base62.encode(&one), format!("{}1", "0".repeat(42)));
+        assert_eq!(HashEncoding::Base32.encode(&one), format!("{}q", "a".repeat(51)));
+        assert_eq!(HashEncoding::Hex.encode(&one), format!("{}01", "0".repeat(62)));
+    }
+
+    #[test]
+    fn test_sampled_hashing() {
+        use crate::{File, FileType};
+        use tempfile::tempdir;
+
+        let naming = HashNaming { sample_threshold: Some(1024 * 1024), ..HashNaming::default() };
+        let rename = |contents: &[u8]| hash_file_rename_with(File {
+            filename: "movie.mp4".to_string(),
+            file_type: FileType::Video,
+            contents: contents.to_vec(),
+            hash: None,
+        }, naming).unwrap().filename;
+
+        let video: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(rename(&video), rename(&video.clone()));
+
+        // A byte in the middle sample changes the name, one between the samples doesn't
+        let mut middle = video.clone();
+        middle[video.len() / 2] ^= 1;
+        assert_ne!(rename(&middle), rename(&video));
+        let mut unsampled = video.clone();
+        unsampled[video.len() / 4] ^= 1;
+        assert_eq!(rename(&unsampled), rename(&video));
+
+        // Streaming from disk gives the same names as hashing in memory
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("movie.mp4");
+        fs::write(&path, &middle).unwrap();
+        assert_eq!(hashed_filename(&path, naming).unwrap(), rename(&middle));
+        assert_eq!(hash_copy_file(&path, dir.path(), naming).unwrap().1, rename(&middle));
+    }
+
+    #[test]
+    fn test_hash_file_rename_digest_length() {
+        use crate::{File, FileType};
+
+        let rename = |encoding, digest_length: Option<usize>| hash_file_rename_with(File {
+            filename: "main.css".to_string(),
+            file_type: FileType::CSS,
+            contents: b"body { margin: 0; }".to_vec(),
+            hash: None,
+        }, HashNaming { encoding, digest_length: digest_length.and_then(NonZeroUsize::new), ..HashNaming::default() }).unwrap().filename;
+
+        // 48 bytes are 96 hex, 77 base32 and 65 base62 characters, the same on every run
+        let hex = rename(HashEncoding::Hex, Some(48));
+        assert_eq!(hex.len(), 96 + ".css".len());
+        assert_eq!(hex, rename(HashEncoding::Hex, Some(48)));
+        assert_eq!(rename(HashEncoding::Base32, Some(48)).len(), 77 + ".css".len());
+        assert_eq!(rename(HashEncoding::Base62, Some(48)).len(), 65 + ".css".len());
+
+        // The extended output starts with the standard digest
+        let standard = rename(HashEncoding::Hex, None);
+        assert!(hex.starts_with(standard.trim_end_matches(".css")));
+        assert_eq!(rename(HashEncoding::Hex, Some(32)), standard);
+    }
+
+    #[test]
+    fn test_hash_includes_extension() {
+        use crate::{File, FileType};
+        use tempfile::tempdir;
+
+        let stem = |filename: &str, include_extension| {
+            let renamed = hash_file_rename_with(File {
+                filename: filename.to_string(),
+                file_type: FileType::Other,
+                contents: b"identical".to_vec(),
+                hash: None,
+            }, HashNaming { include_extension, ..HashNaming::default() }).unwrap();
+            renamed.filename.split('.').next().unwrap().to_string()
+        };
+
+        assert_eq!(stem("a.css", false), stem("a.js", false));
+        assert_ne!(stem("a.css", true), stem("a.js", true));
+
+        // Streaming agrees with the in-memory rename
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("a.js");
+        fs::write(&source, b"identical").unwrap();
+        let naming = HashNaming { include_extension: true, ..HashNaming::default() };
+        assert_eq!(hashed_filename(&source, naming).unwrap(), format!("{}.js", stem("a.js", true)));
+    }
 }
\ No newline at end of file
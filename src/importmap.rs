@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use crate::{manifest::ManifestEntry, Warning};
+
+/// Options controlling the browser import map.
+#[derive(Debug, Clone)]
+pub struct ImportMapOptions {
+    /// Bare module specifiers (e.g. `app`) mapped to the source paths of their JavaScript
+    /// modules, as they appear in the manifest.
+    pub imports: HashMap<String, String>,
+    /// The filename of the import map within the output directory.
+    pub filename: String,
+    /// The URL the output directory is served from, prepended to every hashed filename.
+    pub base_url: String
+}
+
+impl Default for ImportMapOptions {
+    fn default() -> Self {
+        ImportMapOptions {
+            imports: HashMap::new(),
+            filename: "importmap.json".to_string(),
+            base_url: "/".to_string()
+        }
+    }
+}
+
+/// The JSON structure of an import map.
+#[derive(Serialize)]
+struct ImportMap<'a> {
+    imports: BTreeMap<&'a str, String>
+}
+
+/// Renders the import map for the specifiers whose modules are in the manifest, reporting the others to `warn`.
+pub(crate) fn render_import_map(
+    manifest: &HashMap<String, ManifestEntry>,
+    options: &ImportMapOptions,
+    mut warn: impl FnMut(Warning)
+) -> Result<String, serde_json::Error> {
+    let mut imports = BTreeMap::new();
+    for (specifier, source) in &options.imports {
+        match manifest.get(source) {
+            Some(entry) if entry.hashed.ends_with(".js") || entry.hashed.ends_with(".mjs") => {
+                imports.insert(specifier.as_str(), format!("{}{}", options.base_url, entry.hashed));
+            },
+            _ => warn(Warning::UnresolvedImport {
+                specifier: specifier.clone(),
+                source: source.clone()
+            })
+        }
+    }
+
+    serde_json::to_string_pretty(&ImportMap { imports })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_map() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::{process_directory_with_options, ProcessOptions};
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let js_path = input_dir.path().join("app.js");
+        fs::write(&js_path, "export const answer = 42;").unwrap();
+
+        let options = ProcessOptions {
+            import_map: Some(ImportMapOptions {
+                imports: HashMap::from([("app".to_string(), js_path.to_str().unwrap().to_string())]),
+                base_url: "/static/".to_string(),
+                ..ImportMapOptions::default()
+            }),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let import_map = fs::read_to_string(output_dir.path().join("importmap.json")).unwrap();
+        let import_map: serde_json::Value = serde_json::from_str(&import_map).unwrap();
+
+        // The bare specifier resolves to the hashed module
+        let hashed_filename = &manifest[js_path.to_str().unwrap()];
+        assert_eq!(import_map["imports"]["app"], format!("/static/{}", hashed_filename));
+    }
+}
\ No newline at end of file
@@ -1,12 +1,38 @@
 use std::{
-    io,
+    borrow::Cow,
+    io::{self, Read, Write},
     fs,
-    path::Path,
-    collections::HashMap
+    path::{Component, Path, PathBuf},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime}
 };
-use hash::hash_file_rename;
+use base64::Engine;
+use filetime::FileTime;
+use globset::{Glob, GlobBuilder, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use image::ImageDecoder;
+use hash::{csp_hash, hash_copy_file, hash_file_rename_with, hashed_filename, write_sha256_sidecar, HashEncoding, HashNaming};
+use manifest::ManifestEntry;
+use importmap::ImportMapOptions;
+use nginx::NginxMapOptions;
+use preload::PreloadOptions;
 use lightningcss::{
-    printer::PrinterOptions,
+    bundler::{Bundler, FileProvider},
+    printer::{PrinterOptions, PseudoClasses},
+    properties::{
+        custom::{CustomPropertyName, Variable},
+        Property
+    },
+    rules::CssRule,
+    targets::{Features, Targets},
+    traits::ToCss,
+    values::url::Url,
+    visit_types,
+    visitor::{Visit, VisitTypes, Visitor},
     stylesheet::{
         MinifyOptions,
         ParserOptions,
@@ -15,7 +41,15 @@ use lightningcss::{
 };
 use thiserror::Error;
 
+pub mod archive;
+pub mod compress;
+pub mod deps;
 pub mod hash;
+pub mod importmap;
+pub mod manifest;
+pub mod nginx;
+pub mod preload;
+pub mod watch;
 
 #[derive(Error, Debug)]
 pub enum StaticPreprocessingError {
@@ -28,19 +62,127 @@ pub enum StaticPreprocessingError {
     #[error("There was an error during hashing: {0}")]
     HashError(String),
     #[error("There was an error during Image processing: {0}")]
-    ImageProcessingError(String)
+    ImageProcessingError(String),
+    #[error("There was an error watching for changes: {0}")]
+    WatchError(String),
+    #[error("Encountered a file of unknown type: {0}")]
+    UnknownFileType(String),
+    #[error("{} has the unrecognized extension {extension:?}", path.display())]
+    UnrecognizedExtension {
+        path: PathBuf,
+        extension: String
+    },
+    #[error("{file_type:?} files are not supported here: {detail}")]
+    UnsupportedOperation {
+        file_type: FileType,
+        detail: String
+    },
+    #[error("The path {} is not valid UTF-8", .0.display())]
+    NonUtf8Path(PathBuf),
+    #[error("The file extension says {expected:?}, but the contents look like {found:?}")]
+    FileTypeMismatch {
+        expected: FileType,
+        found: FileType
+    },
+    #[error("The output directory {} does not exist", .0.display())]
+    MissingOutputDir(PathBuf),
+    #[error("{} would be written with the disallowed extension {extension:?}", path.display())]
+    DisallowedOutputExtension {
+        path: PathBuf,
+        extension: String
+    },
+    #[error("The archive entry {0:?} would be extracted outside the output directory")]
+    UnsafeArchiveEntry(String),
+    #[error("No files were processed, so the manifest would be empty; check the input path and filters")]
+    EmptyManifest,
+    #[error("{} contains {directories} directories but no files, so the manifest would be empty", path.display())]
+    EmptyDirectories {
+        path: PathBuf,
+        directories: usize
+    },
+    #[error("{} has transparency, which {format} can't store", path.display())]
+    TransparencyLoss {
+        path: PathBuf,
+        format: String
+    },
+    #[error("{} was already written with different contents in this run", .0.display())]
+    ConflictingOutput(PathBuf),
+    #[error("The manifest metadata of {} uses the reserved key {key:?}", path.display())]
+    ReservedMetadataKey {
+        path: PathBuf,
+        key: String
+    },
+    #[error("There was an error processing {}: {source}", path.display())]
+    FileError {
+        path: PathBuf,
+        source: Box<StaticPreprocessingError>
+    }
 }
 
 type LibError = StaticPreprocessingError;
 
-#[derive(Debug, PartialEq)]
+/// The filename the manifest is written to unless configured otherwise.
+pub const DEFAULT_MANIFEST_FILENAME: &str = "manifest.json";
+
+/// The name of the gitignore-style files excluding input files unless configured otherwise.
+pub const DEFAULT_IGNORE_FILENAME: &str = ".spignore";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FileType {
     Image,
     CSS,
     JS,
+    HTML,
+    Markdown,
+    JSON,
+    SVG,
+    Font,
+    Wasm,
+    Video,
+    Scss,
     Other
 }
 
+impl FileType {
+    /// Returns the lowercase name of the type, e.g. `"css"` or `"image"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::Image => "image",
+            FileType::CSS => "css",
+            FileType::JS => "js",
+            FileType::HTML => "html",
+            FileType::Markdown => "markdown",
+            FileType::JSON => "json",
+            FileType::SVG => "svg",
+            FileType::Font => "font",
+            FileType::Wasm => "wasm",
+            FileType::Video => "video",
+            FileType::Scss => "scss",
+            FileType::Other => "other"
+        }
+    }
+
+    /// Returns whether files of this type are copied to the output without being transformed.
+    pub fn is_passthrough(&self) -> bool {
+        !matches!(self, FileType::CSS | FileType::Markdown | FileType::Scss)
+    }
+
+    /// Returns whether files of this type are text once processed.
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            FileType::CSS | FileType::JS | FileType::HTML | FileType::Markdown | FileType::JSON | FileType::SVG | FileType::Scss
+        )
+    }
+
+    /// Returns whether files of this type get compressed siblings when compression is enabled
+    /// without an explicit set of types. Text compresses well, while images, fonts, videos and
+    /// WebAssembly are left alone.
+    pub fn is_compressible(&self) -> bool {
+        self.is_text()
+    }
+}
+
 #[derive(Debug)]
 pub struct File {
     /// The file's name (not including any directory).
@@ -48,7 +190,829 @@ pub struct File {
     /// The detected type of the file.
     pub file_type: FileType,
     /// The raw file contents.
-    pub contents: Vec<u8>
+    pub contents: Vec<u8>,
+    /// The BLAKE3 digest of the contents, set once the file has been hashed.
+    pub hash: Option<[u8; 32]>
+}
+
+/// What happened to a single input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// The contents were changed, e.g. minified or rendered, before being hashed and saved.
+    Transformed,
+    /// The contents were hashed and saved unchanged.
+    Copied,
+    /// The file was left out of the output, e.g. by [`UnknownFilePolicy::Skip`].
+    Skipped,
+    /// The file is meant to be inlined, so only its CSP hash was recorded in the manifest.
+    Inlined
+}
+
+/// The result of processing a single input file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileResult {
+    /// The path of the input file.
+    pub input: PathBuf,
+    /// The output file, relative to the output directory, or `None` if the file was skipped.
+    pub output: Option<String>,
+    /// The type of the output file, which differs from the input for rendered Markdown.
+    pub file_type: FileType,
+    /// The size of the input file in bytes.
+    pub input_size: u64,
+    /// The size of the output file in bytes, or `0` if the file was skipped.
+    pub output_size: u64,
+    /// What happened to the file.
+    pub outcome: FileOutcome,
+    /// The wall-clock time spent processing the file, if `record_timings` is enabled. Files in
+    /// verbatim directories are not timed.
+    pub duration: Option<Duration>
+}
+
+/// What to do with files whose type is [`FileType::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnknownFilePolicy {
+    /// Hash and copy the file to the output directory.
+    #[default]
+    Copy,
+    /// Leave the file out of the output directory and the manifest.
+    Skip,
+    /// Fail with [`StaticPreprocessingError::UnknownFileType`].
+    Error
+}
+
+/// A format images are additionally converted to by [`ProcessOptions::image_variants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageVariant {
+    /// JPEG at quality 90, which can't store transparency; see [`ProcessOptions::image_transparency`].
+    Jpeg,
+    /// Lossless PNG.
+    Png,
+    /// Lossless WebP.
+    WebP
+}
+
+impl ImageVariant {
+    /// Returns the extension of files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageVariant::Jpeg => "jpg",
+            ImageVariant::Png => "png",
+            ImageVariant::WebP => "webp"
+        }
+    }
+}
+
+/// What to do when an image with transparency would be converted to a format without it, like JPEG.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TransparencyPolicy {
+    /// Convert it to WebP instead, with a [`Warning::TransparencyFallback`].
+    #[default]
+    WebP,
+    /// Fail with [`StaticPreprocessingError::TransparencyLoss`].
+    Error,
+    /// Convert it anyway, flattening the transparent areas.
+    Discard
+}
+
+/// What to do with files whose output extension is not in [`ProcessOptions::allowed_output_extensions`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DisallowedOutputPolicy {
+    /// Fail with [`StaticPreprocessingError::DisallowedOutputExtension`].
+    #[default]
+    Error,
+    /// Leave the file out of the output directory and the manifest.
+    Skip
+}
+
+/// Class names that replace user-action pseudo-classes in minified stylesheets, e.g. so
+/// `:hover` styles can be forced with a `.hover` class in snapshot tests.
+///
+/// Each pseudo-class left as `None` is kept as it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PseudoClassReplacements {
+    /// The class name to replace `:hover` with.
+    pub hover: Option<String>,
+    /// The class name to replace `:active` with.
+    pub active: Option<String>,
+    /// The class name to replace `:focus` with.
+    pub focus: Option<String>,
+    /// The class name to replace `:focus-visible` with.
+    pub focus_visible: Option<String>,
+    /// The class name to replace `:focus-within` with.
+    pub focus_within: Option<String>
+}
+
+impl PseudoClassReplacements {
+    /// Returns the replacements in the form the lightningcss printer takes.
+    fn printer_options(&self) -> PseudoClasses<'_> {
+        PseudoClasses {
+            hover: self.hover.as_deref(),
+            active: self.active.as_deref(),
+            focus: self.focus.as_deref(),
+            focus_visible: self.focus_visible.as_deref(),
+            focus_within: self.focus_within.as_deref()
+        }
+    }
+}
+
+/// The browsers the colors of minified stylesheets must work in.
+///
+/// This is a compatibility switch, not a choice of notation: opaque sRGB colors are always
+/// written in their shortest form, hex or named, which every browser understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CssColorTargets {
+    /// Recent browsers: colors take their shortest form, e.g. `red`, `#f00` or `#ff000080`,
+    /// and syntaxes like `lab()` are kept as they are.
+    #[default]
+    Modern,
+    /// Every browser: translucent colors are written as `rgba()` rather than `#rrggbbaa`, and
+    /// colors like `lab()` get an sRGB fallback declaration.
+    Legacy
+}
+
+impl CssColorTargets {
+    /// Returns the lightningcss targets for these browsers.
+    fn targets(&self) -> Targets {
+        match self {
+            CssColorTargets::Modern => Targets::default(),
+            CssColorTargets::Legacy => Targets { include: Features::Colors, ..Targets::default() }
+        }
+    }
+}
+
+/// The function behind a [`FontSubsetter`].
+pub type SubsetFn = dyn Fn(File, &HashSet<char>) -> Result<File, StaticPreprocessingError> + Send + Sync;
+
+/// A hook that shrinks font files down to the glyphs that are actually used.
+///
+/// The hook receives every [`FileType::Font`] file before it is hashed, together with the
+/// glyphs provided by the caller, and returns the possibly smaller font.
+#[derive(Clone)]
+pub struct FontSubsetter {
+    /// The characters whose glyphs must be kept.
+    pub glyphs: HashSet<char>,
+    /// The subsetting function.
+    pub subset: Arc<SubsetFn>
+}
+
+impl FontSubsetter {
+    /// Creates a [`FontSubsetter`] keeping `glyphs` using the function `subset`.
+    pub fn new<F>(glyphs: HashSet<char>, subset: F) -> Self
+    where
+        F: Fn(File, &HashSet<char>) -> Result<File, StaticPreprocessingError> + Send + Sync + 'static
+    {
+        FontSubsetter { glyphs, subset: Arc::new(subset) }
+    }
+}
+
+impl fmt::Debug for FontSubsetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FontSubsetter")
+            .field("glyphs", &self.glyphs)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A problem that doesn't stop processing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Two input files share a basename but have different contents, so the basename alone
+    /// doesn't identify the asset.
+    AmbiguousBasename {
+        first: PathBuf,
+        second: PathBuf
+    },
+    /// A specifier of the import map points at a source that isn't a processed JavaScript module.
+    UnresolvedImport {
+        specifier: String,
+        source: String
+    },
+    /// A stylesheet couldn't be minified, so it was passed through as it is.
+    UnminifiedStylesheet {
+        filename: String,
+        error: String
+    },
+    /// An image with transparency was converted to WebP instead of a format that can't store it.
+    TransparencyFallback {
+        filename: String,
+        format: String
+    },
+    /// A stylesheet's URL points at an existing file that hadn't been processed yet, e.g. one in
+    /// a later batch, so the URL was left as it is.
+    UnprocessedReference {
+        stylesheet: PathBuf,
+        url: String
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::AmbiguousBasename { first, second } => write!(
+                f,
+                "{} and {} share a name but have different contents",
+                first.display(),
+                second.display()
+            ),
+            Warning::UnresolvedImport { specifier, source } => write!(
+                f,
+                "the import map specifier {} points at {}, which is not a processed JavaScript module",
+                specifier,
+                source
+            ),
+            Warning::UnminifiedStylesheet { filename, error } => write!(
+                f,
+                "{} was passed through unminified: {}",
+                filename,
+                error
+            ),
+            Warning::TransparencyFallback { filename, format } => write!(
+                f,
+                "{} has transparency, which {} can't store, so it was converted to WebP instead",
+                filename,
+                format
+            ),
+            Warning::UnprocessedReference { stylesheet, url } => write!(
+                f,
+                "{} references {}, which hadn't been processed yet, so the URL was left as it is",
+                stylesheet.display(),
+                url
+            )
+        }
+    }
+}
+
+/// The number of leading bytes of a file passed to [`TypeDetector::detect`].
+pub const TYPE_DETECTOR_HEAD_SIZE: usize = 512;
+
+/// Classifies files by custom rules, e.g. content heuristics for formats of your own.
+///
+/// The detector is consulted before [`detect_file_type`] and can defer to it by returning `None`.
+pub trait TypeDetector: fmt::Debug + Send + Sync {
+    /// Returns the type of the file at `path`, whose first bytes (at most
+    /// [`TYPE_DETECTOR_HEAD_SIZE`]) are `head`, or `None` to fall back to its extension.
+    fn detect(&self, path: &Path, head: &[u8]) -> Option<FileType>;
+}
+
+/// A function supplied in the [`ProcessOptions`], such as a [`WarningHandler`] or a [`WriteHook`].
+///
+/// Callbacks are shared between the threads processing files, so they must be [`Fn`] rather
+/// than [`FnMut`]; use interior mutability for any state. Cloning a callback shares the function.
+pub struct Callback<F: ?Sized>(pub Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Callback(Arc::clone(&self.0))
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Callback").finish_non_exhaustive()
+    }
+}
+
+/// Receives the [`Warning`]s raised while processing.
+pub type WarningHandler = Callback<dyn Fn(&Warning) + Send + Sync>;
+
+impl WarningHandler {
+    /// Creates a [`WarningHandler`] from a function receiving each warning.
+    pub fn new<F: Fn(&Warning) + Send + Sync + 'static>(handler: F) -> Self {
+        Callback(Arc::new(handler))
+    }
+}
+
+/// Derives the manifest key of a processed file from its input path.
+pub type ManifestKey = Callback<dyn Fn(&Path) -> String + Send + Sync>;
+
+impl ManifestKey {
+    /// Creates a [`ManifestKey`] from a function mapping input paths to manifest keys.
+    pub fn new<F: Fn(&Path) -> String + Send + Sync + 'static>(key: F) -> Self {
+        Callback(Arc::new(key))
+    }
+}
+
+/// Selects the stylesheets and scripts that are inlined into pages rather than served as files.
+pub type InlineSelector = Callback<dyn Fn(&Path) -> bool + Send + Sync>;
+
+impl InlineSelector {
+    /// Creates an [`InlineSelector`] from a function returning whether an input path is inlined.
+    pub fn new<F: Fn(&Path) -> bool + Send + Sync + 'static>(select: F) -> Self {
+        Callback(Arc::new(select))
+    }
+}
+
+/// The function behind a [`MetadataProvider`].
+pub type MetadataFn = dyn Fn(&Path) -> BTreeMap<String, serde_json::Value> + Send + Sync;
+
+/// Provides custom metadata for the manifest entry of an input file, e.g. `"group": "critical"`.
+pub type MetadataProvider = Callback<MetadataFn>;
+
+impl MetadataProvider {
+    /// Creates a [`MetadataProvider`] from a function mapping input paths to their metadata.
+    pub fn new<F: Fn(&Path) -> BTreeMap<String, serde_json::Value> + Send + Sync + 'static>(provide: F) -> Self {
+        Callback(Arc::new(provide))
+    }
+}
+
+/// An output file passed to a [`WriteHook`] right after it was written.
+///
+/// The contents aren't included, as large files are streamed to the output without ever being
+/// held in memory; read them from `path` if needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrittenFile {
+    /// The path the file was written to.
+    pub path: PathBuf,
+    /// The file's name (not including any directory).
+    pub filename: String,
+    /// The type of the file. Compressed siblings and checksums get the type of the file they belong to.
+    pub file_type: FileType
+}
+
+/// The function behind a [`WriteHook`].
+pub type WrittenFn = dyn Fn(&WrittenFile) -> Result<(), StaticPreprocessingError> + Send + Sync;
+
+/// A callback invoked after each output file is written, e.g. to upload it right away. An error
+/// aborts processing.
+pub type WriteHook = Callback<WrittenFn>;
+
+impl WriteHook {
+    /// Creates a [`WriteHook`] from a function receiving each written output file.
+    pub fn new<F: Fn(&WrittenFile) -> Result<(), StaticPreprocessingError> + Send + Sync + 'static>(on_written: F) -> Self {
+        Callback(Arc::new(on_written))
+    }
+}
+
+/// Options controlling how files are processed.
+///
+/// The [`Default`] options match the behavior of [`process_directory`].
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// The number of leading hash characters used as a subdirectory for each output file,
+    /// e.g. `2` saves `ab12….css` as `ab/ab12….css`. `0` disables sharding.
+    pub shard_depth: usize,
+    /// When set, this many trailing directories of each input file's path are kept in its
+    /// output path, so with `1`, `styles/vendor/css/main.css` is saved as `css/<hash>.css`.
+    /// Paths are taken relative to the input directory when processing one. `None` or `0`
+    /// writes every file directly into the output directory.
+    pub flatten_to_depth: Option<usize>,
+    /// Whether to write the manifest to the output directory.
+    pub write_manifest: bool,
+    /// The filename of the manifest within the output directory.
+    pub manifest_filename: String,
+    /// When set, the manifest records the provenance of the build under the reserved
+    /// `__meta__` key: this crate's version, the UTC time and this caller-supplied build id.
+    /// Reproducible builds record the time in `SOURCE_DATE_EPOCH` instead, or none if it isn't
+    /// set. See [`manifest::BuildMetadata`].
+    pub manifest_build_id: Option<String>,
+    /// Whether to record a `short` alias (the first 7 characters of the hash) for each manifest
+    /// entry. This switches the manifest to detailed entries.
+    pub manifest_short_hash: bool,
+    /// When set, a `manifest.js` assigning the manifest to this global (e.g. `__ASSETS__` for
+    /// `window.__ASSETS__`) is written next to the manifest. The file is named after
+    /// `manifest_filename` with a `.js` extension.
+    pub manifest_js_global: Option<String>,
+    /// What to do with files of an unknown type.
+    pub unknown_file_policy: UnknownFilePolicy,
+    /// The number of threads processing images, separately from all other files. At least one
+    /// thread is always used.
+    pub image_threads: usize,
+    /// When set, [`process_directory_with_options`] walks the input in sorted order and
+    /// processes it in batches of this many files as they're found, instead of collecting every
+    /// path before processing any. The results and the manifest still hold every file. CSS URL
+    /// rewriting then only sees the files of earlier batches and its own, and URLs of files
+    /// in later batches are left as they are and reported as [`Warning::UnprocessedReference`].
+    pub batch_size: Option<usize>,
+    /// Formats each JPEG, PNG or WebP image is additionally converted to, written next to it
+    /// under the same hash stem, e.g. `<hash>.webp` and `<hash>.jpg` for `<hash>.png`, and
+    /// listed in the manifest entry's `formats`. Formats matching the image's own are skipped.
+    pub image_variants: Vec<ImageVariant>,
+    /// What to do when an image with transparent pixels would be converted to a variant format
+    /// without transparency, i.e. JPEG.
+    pub image_transparency: TransparencyPolicy,
+    /// Whether to copy each source file's modification time onto its output file.
+    pub preserve_mtime: bool,
+    /// Controls the newlines at the end of text outputs: `Some(true)` ensures exactly one
+    /// trailing newline, `Some(false)` strips all of them and `None` leaves them as they are.
+    pub trailing_newline: Option<bool>,
+    /// Whether to write gzip (`.gz`) and brotli (`.br`) compressed siblings next to output files.
+    pub compression: bool,
+    /// The types to compress when `compression` is enabled. When `None`, the types for which
+    /// [`FileType::is_compressible`] holds are compressed, along with plain text files of no
+    /// recognized type, such as `.txt`, `.xml` or `.map` files. [`FileType::Video`] is never
+    /// compressed, as videos are already compressed and usually large.
+    pub compress_types: Option<HashSet<FileType>>,
+    /// Whether to stream files that are never transformed straight to the output while hashing
+    /// them, instead of loading them into memory first.
+    pub fast_passthrough: bool,
+    /// Whether to check the extension-derived [`FileType`] against the file's magic bytes and
+    /// fail with [`StaticPreprocessingError::FileTypeMismatch`] when they disagree.
+    pub strict_file_types: bool,
+    /// Whether to fail with [`StaticPreprocessingError::UnrecognizedExtension`] on files whose
+    /// extension [`detect_file_type`] doesn't recognize, including files without one, instead
+    /// of handling them per `unknown_file_policy`. A custom `type_detector` doesn't make an
+    /// extension recognized.
+    pub strict_extensions: bool,
+    /// The hook applied to font files before hashing. When `None`, fonts are copied unchanged.
+    pub font_subsetter: Option<FontSubsetter>,
+    /// A directory where minified stylesheets are cached by the hash of their source, so
+    /// unchanged stylesheets are not minified again on later runs. Entries are never evicted.
+    pub cache_dir: Option<PathBuf>,
+    /// When set, a file of `<link rel="preload">` hints for the selected outputs is written
+    /// next to the manifest.
+    pub preload: Option<PreloadOptions>,
+    /// Whether `/*! ... */` license comments survive CSS minification. They are moved to the top
+    /// of the minified stylesheet.
+    pub preserve_license_comments: bool,
+    /// Whether to write a `<file>.sha256` sidecar with the SHA-256 checksum of each output file,
+    /// for deployment pipelines that verify artifacts.
+    pub sha256_sidecars: bool,
+    /// Derives the manifest key from the input path. When `None`, the input path itself is the
+    /// key.
+    pub manifest_key: Option<ManifestKey>,
+    /// Whether to inline the stylesheets imported with `@import` into the importing stylesheet,
+    /// resolved relative to it. The imported stylesheets are still processed on their own.
+    pub bundle_css_imports: bool,
+    /// When set, only files with one of these extensions (without the dot, matched
+    /// case-insensitively) are processed; all others are skipped before type detection.
+    pub only_extensions: Option<HashSet<String>>,
+    /// The name of the ignore files, in gitignore syntax, whose patterns exclude files from the
+    /// input directory. Each ignore file applies to the directory it's in and everything below,
+    /// with deeper ones taking precedence. The ignore files themselves are never processed.
+    /// `None` disables them.
+    pub ignore_filename: Option<String>,
+    /// Globs matched against the directories of the input, relative to the input directory
+    /// (e.g. `vendor` or `**/third-party`). Files below a matching directory are copied without
+    /// any transforms under their original relative path, and recorded in the manifest as
+    /// identity entries. Only applies when processing a whole directory.
+    pub verbatim_dirs: Vec<String>,
+    /// Receives the [`Warning`]s raised while processing. When `None`, they are logged at
+    /// [`log::Level::Warn`], subject to `verbosity`.
+    pub on_warning: Option<WarningHandler>,
+    /// Routes files to their own output directories by the type detected from their extension,
+    /// e.g. images to a directory served from a separate CDN. Types without an entry go to the
+    /// regular output directory. Routing switches the manifest to detailed entries, which record
+    /// the directory of every routed file.
+    pub type_output_dirs: HashMap<FileType, PathBuf>,
+    /// Whether to rewrite relative `url()`s in stylesheets that point at processed files to the
+    /// relative paths of their outputs. Stylesheets are then processed after all other files.
+    /// When watching, a stylesheet only picks up new names of referenced files when it is
+    /// rebuilt itself.
+    pub rewrite_css_urls: bool,
+    /// When set, a browser import map mapping bare specifiers to hashed JavaScript modules is
+    /// written next to the manifest.
+    pub import_map: Option<ImportMapOptions>,
+    /// Files smaller than this many bytes are copied without transforms under their original
+    /// name, which is also their manifest entry. `0` hashes every file.
+    pub min_hash_size: u64,
+    /// Whether to remove custom properties declared in top-level `:root` rules that no `var()`
+    /// in the same stylesheet references. Properties only read from scripts are removed too.
+    pub remove_unused_custom_properties: bool,
+    /// When set, minified stylesheets have the configured pseudo-classes replaced with class
+    /// selectors, e.g. `a:hover` with `a.hover`.
+    pub css_pseudo_classes: Option<PseudoClassReplacements>,
+    /// The browsers the colors of minified stylesheets must work in. See [`CssColorTargets`].
+    pub css_color_targets: CssColorTargets,
+    /// When set, stylesheets and scripts larger than this many bytes are also split into
+    /// numbered chunks of about this size, `<hash>.0.css`, `<hash>.1.css` and so on, listed in
+    /// load order in the manifest entry's `chunks`. Stylesheets are split between top-level
+    /// rules, and scripts after a top-level `;` or a top-level `}` ending its line and not
+    /// continued by `else`, `catch`, `finally` or `while`, so scripts must not rely on being
+    /// a single module. The unsplit file is still written.
+    pub chunk_size: Option<usize>,
+    /// Whether to hard-link files that are never transformed into the output instead of copying
+    /// them, so they take up no extra space. Falls back to copying when the output directory is
+    /// on another filesystem. Note that editing a linked source in place also changes its output.
+    pub hard_link_passthrough: bool,
+    /// Whether to make the output a pure function of the input files, for bit-for-bit
+    /// reproducible builds. Files are processed in sorted order, `preserve_mtime` is ignored and,
+    /// unless a `manifest_key` is set, manifest keys are relative to the input directory and use
+    /// forward slashes on every platform. The manifest itself is always sorted by key.
+    pub reproducible: bool,
+    /// Whether to record the `width` and `height` of each image in its manifest entry, read
+    /// from the image header. This switches the manifest to detailed entries.
+    pub manifest_image_dimensions: bool,
+    /// Whether stylesheets that fail to parse or minify are passed through unminified, with a
+    /// [`Warning::UnminifiedStylesheet`], instead of failing the build.
+    pub lenient_css: bool,
+    /// Whether to record the `original` filename of each file, without its directory, in its
+    /// manifest entry. This switches the manifest to detailed entries.
+    pub manifest_original_name: bool,
+    /// Whether to record a suggested `cache_control` header in each manifest entry:
+    /// [`IMMUTABLE_CACHE_CONTROL`](manifest::IMMUTABLE_CACHE_CONTROL) for hashed files, and
+    /// [`SHORT_CACHE_CONTROL`](manifest::SHORT_CACHE_CONTROL) for files that keep their name,
+    /// like those below `min_hash_size` or in `verbatim_dirs`. This switches the manifest to
+    /// detailed entries.
+    pub manifest_cache_control: bool,
+    /// Whether to record the `content_type` of each output in its manifest entry, looked up in
+    /// `mime_overrides` and then with [`mime_type`]. This switches the manifest to detailed entries.
+    pub manifest_content_type: bool,
+    /// MIME types by extension, without the dot, that take precedence over the built-in ones
+    /// of [`mime_type`] in the manifest, e.g. for niche types that would otherwise be served as
+    /// `application/octet-stream`.
+    pub mime_overrides: HashMap<String, String>,
+    /// Whether to create the output directory if it doesn't exist. When `false`, a missing
+    /// output directory fails with [`StaticPreprocessingError::MissingOutputDir`], so a
+    /// mistyped path isn't silently created.
+    pub create_output: bool,
+    /// Whether to measure the time spent on each file, reported as [`FileResult::duration`] and
+    /// summed per type by [`durations_by_type`].
+    pub record_timings: bool,
+    /// Whether to fail with [`StaticPreprocessingError::EmptyManifest`] when no file ends up in
+    /// the manifest, which usually means a wrong input path or overly strict filters. An input
+    /// directory holding only empty subdirectories fails with the more specific
+    /// [`StaticPreprocessingError::EmptyDirectories`] instead; without this option, that case is
+    /// logged as a warning.
+    pub error_on_empty: bool,
+    /// When set, base64 `data:` image URIs in stylesheets that decode to more than this many
+    /// bytes are extracted into hashed image files, so they can be cached separately, and
+    /// replaced with relative `url()`s. The extracted files are not added to the manifest.
+    pub extract_data_uris: Option<usize>,
+    /// When set, only outputs with one of these extensions (without the dot, compared
+    /// case-insensitively) may be written, e.g. to make sure no stray `.psd` is shipped. The
+    /// extension is the final one, so Markdown is checked as `html` and Sass as `css`. Files in
+    /// verbatim directories are copied unchecked.
+    pub allowed_output_extensions: Option<HashSet<String>>,
+    /// What to do with files whose output extension is not allowed.
+    pub disallowed_output_policy: DisallowedOutputPolicy,
+    /// When set, a compressed sibling is only kept if it saves at least this fraction of the
+    /// original size, e.g. `0.1` for 10%. Files that don't compress well enough get no sibling.
+    pub min_compression_ratio: Option<f64>,
+    /// A custom [`TypeDetector`] classifying files before their extension is looked at.
+    pub type_detector: Option<Arc<dyn TypeDetector>>,
+    /// When set, [`process_directory_with_options`] only processes files last modified after
+    /// this time and merges them into the existing manifest, keeping the other entries.
+    pub modified_since: Option<SystemTime>,
+    /// How the hash is spelled in output filenames. Base32 and base62 give shorter names than
+    /// the default hex for the same hash.
+    pub hash_encoding: HashEncoding,
+    /// Whether to also write one manifest per output type, like `manifest.css.json`, named
+    /// after `manifest_filename`, so consumers can load only the entries they need. The
+    /// combined manifest is still written unless `write_manifest` is disabled.
+    pub split_manifest_by_type: bool,
+    /// Whether to hash the extension along with the contents, so files with identical bytes but
+    /// different extensions, like a `.css` and a `.js`, get different stems.
+    pub hash_includes_extension: bool,
+    /// When set, output filenames are derived from this many bytes of BLAKE3's extendable
+    /// output instead of the standard 32-byte digest, so the name's entropy can be chosen
+    /// independently of the encoding.
+    pub hash_digest_length: Option<NonZeroUsize>,
+    /// When set, files of at least this many bytes, typically large videos, are named after
+    /// their size and samples from their start, middle and end instead of their whole contents.
+    /// Only hashing gets faster: files are still read in full wherever they are copied or
+    /// transformed. Two such files differing only outside the samples get the same name, so
+    /// passthrough outputs of that size are rewritten on every run rather than trusted to be
+    /// current, and this is best kept well above the size of any stylesheet or script.
+    pub hash_sample_threshold: Option<u64>,
+    /// When set, a listing of every file the run wrote, including compressed siblings, sidecars
+    /// and manifests, is written under this name next to the manifest, so deployment tooling
+    /// doesn't have to walk the directory. Files left over from earlier runs aren't listed, and
+    /// a watch rebuild only lists what it rewrote. The listing holds one forward-slash path per
+    /// line, relative to the output directory or whole for files routed outside it, or a JSON
+    /// array if the name ends in `.json`, and leaves itself out.
+    pub file_listing: Option<String>,
+    /// When set, a reverse manifest mapping each hashed filename back to its original path is
+    /// also written under this name next to the manifest, e.g. `reverse-manifest.json`, to trace
+    /// cached outputs back to their sources.
+    pub reverse_manifest: Option<String>,
+    /// Whether to bake the EXIF orientation of JPEG, PNG and WebP images into their pixels with
+    /// [`normalize_orientation`], so they display the same everywhere. Rotated images are
+    /// re-encoded without their EXIF metadata.
+    pub normalize_orientation: bool,
+    /// The most verbose [`log`] level the crate emits records at, independent of the global
+    /// logger's configuration. Every processed file is logged at [`log::Level::Info`] and every
+    /// failed one at [`log::Level::Error`], so [`log::LevelFilter::Error`] quiets the per-file
+    /// chatter while still reporting failures.
+    pub verbosity: log::LevelFilter,
+    /// When set, the CSS and JavaScript files it selects are meant to be inlined: instead of
+    /// writing them, the [`csp_hash`](hash::csp_hash) of their contents is recorded as the `csp`
+    /// of their manifest entry, for the page's Content Security Policy. The contents are hashed
+    /// as they are, so they must be inlined unchanged. This switches the manifest to detailed
+    /// entries.
+    pub csp_inline: Option<InlineSelector>,
+    /// Whether to remove every `@charset` rule from stylesheets, including those that aren't
+    /// minified, like `*.min.css` files, and then add a single `@charset "UTF-8";` at the top of
+    /// those holding non-ASCII text. Stylesheets are always written as UTF-8, so this leaves one
+    /// correct declaration where it matters and none where it doesn't.
+    pub normalize_charset: bool,
+    /// Whether to write a pretty-printed `<hash>.debug.css` copy next to every stylesheet, for
+    /// debugging in production, and record it as the `debug` of its manifest entry. Copies of
+    /// stylesheets that can't be parsed, which only pass with `lenient_css`, are left out. This
+    /// switches the manifest to detailed entries.
+    pub debug_css: bool,
+    /// Whether to record the `size` of each output in its manifest entry, along with the
+    /// `gzip_size` and `brotli_size` of its compressed siblings when `compression` writes them.
+    /// This switches the manifest to detailed entries.
+    pub manifest_sizes: bool,
+    /// When set, the metadata it provides for an input file is merged into the file's manifest
+    /// entry as extra fields, for downstream tooling. A key reusing one of the entry's own fields,
+    /// listed in [`manifest::RESERVED_KEYS`], fails the file with
+    /// [`StaticPreprocessingError::ReservedMetadataKey`]. This switches the manifest to detailed entries.
+    pub manifest_metadata: Option<MetadataProvider>,
+    /// When set, the manifest is also written as an nginx `map` block next to the manifest,
+    /// mapping original paths to hashed URLs. Disable `write_manifest` to write it instead of
+    /// the JSON manifest.
+    pub nginx_map: Option<NginxMapOptions>,
+    /// When set, called with every file written to the output right after it's written:
+    /// processed files, verbatim copies, extracted data URIs, debug stylesheets, image variants,
+    /// chunks, compressed siblings and checksums. Manifests and other generated files aren't
+    /// passed to it.
+    pub on_written: Option<WriteHook>
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            shard_depth: 0,
+            flatten_to_depth: None,
+            write_manifest: true,
+            manifest_filename: DEFAULT_MANIFEST_FILENAME.to_string(),
+            manifest_build_id: None,
+            manifest_short_hash: false,
+            manifest_js_global: None,
+            unknown_file_policy: UnknownFilePolicy::default(),
+            image_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            image_variants: Vec::new(),
+            image_transparency: TransparencyPolicy::WebP,
+            batch_size: None,
+            preserve_mtime: false,
+            trailing_newline: None,
+            compression: false,
+            compress_types: None,
+            fast_passthrough: false,
+            strict_file_types: false,
+            strict_extensions: false,
+            font_subsetter: None,
+            cache_dir: None,
+            preload: None,
+            preserve_license_comments: true,
+            sha256_sidecars: false,
+            manifest_key: None,
+            bundle_css_imports: false,
+            only_extensions: None,
+            ignore_filename: Some(DEFAULT_IGNORE_FILENAME.to_string()),
+            verbatim_dirs: Vec::new(),
+            on_warning: None,
+            type_output_dirs: HashMap::new(),
+            rewrite_css_urls: false,
+            import_map: None,
+            min_hash_size: 0,
+            remove_unused_custom_properties: false,
+            css_pseudo_classes: None,
+            css_color_targets: CssColorTargets::Modern,
+            chunk_size: None,
+            hard_link_passthrough: false,
+            reproducible: false,
+            manifest_image_dimensions: false,
+            lenient_css: false,
+            manifest_original_name: false,
+            manifest_cache_control: false,
+            manifest_content_type: false,
+            mime_overrides: HashMap::new(),
+            create_output: true,
+            record_timings: false,
+            error_on_empty: false,
+            extract_data_uris: None,
+            allowed_output_extensions: None,
+            disallowed_output_policy: DisallowedOutputPolicy::Error,
+            min_compression_ratio: None,
+            type_detector: None,
+            modified_since: None,
+            hash_encoding: HashEncoding::Hex,
+            split_manifest_by_type: false,
+            hash_includes_extension: false,
+            hash_digest_length: None,
+            hash_sample_threshold: None,
+            file_listing: None,
+            reverse_manifest: None,
+            normalize_orientation: false,
+            verbosity: log::LevelFilter::Trace,
+            csp_inline: None,
+            normalize_charset: false,
+            debug_css: false,
+            manifest_sizes: false,
+            manifest_metadata: None,
+            nginx_map: None,
+            on_written: None
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// Reports a [`Warning`] to the handler, or logs it if there is none.
+    fn warn(&self, warning: Warning) {
+        match &self.on_warning {
+            Some(Callback(handler)) => handler(&warning),
+            None => self.log(log::Level::Warn, format_args!("{}", warning))
+        }
+    }
+
+    /// Passes an output file that was just written to the `on_written` hook, if any.
+    fn written(&self, path: &Path, file_type: FileType) -> Result<(), LibError> {
+        match &self.on_written {
+            Some(Callback(on_written)) => on_written(&WrittenFile {
+                path: path.to_path_buf(),
+                filename: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                file_type
+            }),
+            None => Ok(())
+        }
+    }
+
+    /// Emits a [`log`] record, unless its level is more verbose than `verbosity`.
+    fn log(&self, level: log::Level, args: fmt::Arguments) {
+        if level <= self.verbosity {
+            log::log!(level, "{}", args);
+        }
+    }
+
+    /// Returns whether manifest entries are written as objects rather than plain hashed filenames,
+    /// because the options ask for extra fields or an entry, e.g. one kept from an earlier build, has some.
+    fn detailed_manifest(&self, manifest: &HashMap<String, ManifestEntry>) -> bool {
+        self.manifest_short_hash
+            || self.manifest_image_dimensions
+            || self.manifest_original_name
+            || self.manifest_cache_control
+            || self.manifest_content_type
+            || self.csp_inline.is_some()
+            || self.debug_css
+            || self.manifest_sizes
+            || self.manifest_metadata.is_some()
+            || !self.image_variants.is_empty()
+            || self.chunk_size.is_some()
+            || !self.type_output_dirs.is_empty()
+            || manifest.values().any(ManifestEntry::is_detailed)
+    }
+
+    /// Returns the options to process the files under `input_dir` with, applying `reproducible`.
+    fn resolve(&self, input_dir: Option<&Path>) -> Cow<'_, ProcessOptions> {
+        if !self.reproducible {
+            return Cow::Borrowed(self);
+        }
+
+        let input_dir = input_dir.map(Path::to_path_buf);
+        Cow::Owned(ProcessOptions {
+            preserve_mtime: false,
+            manifest_key: self.manifest_key.clone().or_else(|| Some(ManifestKey::new(move |path| {
+                let relative = input_dir.as_deref().and_then(|dir| path.strip_prefix(dir).ok()).unwrap_or(path);
+                relative.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+            }))),
+            ..self.clone()
+        })
+    }
+
+    /// Returns the `Cache-Control` value to record for a file, if enabled.
+    fn cache_control(&self, hashed: bool) -> Option<String> {
+        let cache_control = if hashed { manifest::IMMUTABLE_CACHE_CONTROL } else { manifest::SHORT_CACHE_CONTROL };
+        self.manifest_cache_control.then(|| cache_control.to_string())
+    }
+
+    /// Returns the MIME type to record for an output file, if enabled.
+    fn content_type(&self, output: &str) -> Option<String> {
+        if !self.manifest_content_type {
+            return None;
+        }
+        let ext = Path::new(output).extension().map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase());
+        let overridden = self.mime_overrides.iter().find(|(override_ext, _)| override_ext.eq_ignore_ascii_case(&ext));
+        Some(overridden.map_or_else(|| mime_type(&ext).to_string(), |(_, mime)| mime.clone()))
+    }
+
+    /// Returns the custom metadata to record for an input file, failing if a key would overwrite a field of its entry.
+    fn metadata(&self, path: &Path) -> Result<BTreeMap<String, serde_json::Value>, LibError> {
+        let metadata = self.manifest_metadata.as_ref().map_or_else(BTreeMap::new, |Callback(provide)| provide(path));
+        match metadata.keys().find(|key| manifest::RESERVED_KEYS.contains(&key.as_str())) {
+            Some(key) => Err(LibError::ReservedMetadataKey { path: path.to_path_buf(), key: key.clone() }),
+            None => Ok(metadata)
+        }
+    }
+
+    /// Returns how output filenames are derived from hashes.
+    fn hash_naming(&self) -> HashNaming {
+        HashNaming {
+            encoding: self.hash_encoding,
+            include_extension: self.hash_includes_extension,
+            digest_length: self.hash_digest_length,
+            sample_threshold: self.hash_sample_threshold
+        }
+    }
+
+    /// Creates the output directory, or checks that it exists if it shouldn't be created.
+    fn prepare_output_dir(&self, output_dir: &Path) -> Result<(), LibError> {
+        if self.create_output {
+            fs::create_dir_all(output_dir)?;
+        } else if !output_dir.is_dir() {
+            return Err(LibError::MissingOutputDir(output_dir.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the output at `path` of the given type gets compressed siblings, provided compression is enabled.
+    fn compresses(&self, file_type: FileType, path: &Path) -> bool {
+        file_type != FileType::Video && match &self.compress_types {
+            Some(types) => types.contains(&file_type),
+            None => file_type.is_compressible() || (file_type == FileType::Other && is_text_extension(path))
+        }
+    }
 }
 
 /// Loads a file from disk and constructs a [`File`] with its metadata and contents.
@@ -89,19 +1053,27 @@ pub struct File {
 /// ```
 pub fn load_file(path: &Path) -> Result<File, LibError> {
     Ok(File {
-        filename: path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name."))?.to_string_lossy().to_string(),
+        filename: path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name."))?
+            .to_str()
+            .ok_or_else(|| LibError::NonUtf8Path(path.to_path_buf()))?
+            .to_string(),
         file_type: path
             .extension()
             .and_then(|ext| ext.to_str())
             .ok_or_else(|| LibError::IOError(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file extension.")))
             .map(detect_file_type)?,
-        contents: fs::read(path)?
+        contents: fs::read(path)?,
+        hash: None
     })
 }
 
 /// Writes the contents of a [`File`] to disk in the specified output directory.
 ///
-/// The file will be saved as `output_dir/filename`. If the file already exists, it will be overwritten.
+/// The file will be saved as `output_dir/filename`. If the file already exists, it will be overwritten,
+/// unless it already has the same contents, in which case it is left untouched so its
+/// modification time doesn't change.
 ///
 /// # Parameters
 ///
@@ -125,6 +1097,7 @@ pub fn load_file(path: &Path) -> Result<File, LibError> {
 ///     filename: "hello.txt".into(),
 ///     file_type: FileType::Other,
 ///     contents: b"Hello, world!".to_vec(),
+///     hash: None,
 /// };
 ///
 /// save_file(dir.path(), &file).unwrap();
@@ -133,7 +1106,30 @@ pub fn load_file(path: &Path) -> Result<File, LibError> {
 /// assert_eq!(written, "Hello, world!");
 /// ```
 pub fn save_file(output_dir: &Path, file: &File) -> Result<(), LibError> {
-    fs::write(output_dir.join(&file.filename), &file.contents).map_err(|err| LibError::IOError(err))
+    let path = output_dir.join(&file.filename);
+    if is_unchanged(&path, &file.contents) {
+        return Ok(());
+    }
+    fs::write(path, &file.contents).map_err(LibError::IOError)
+}
+
+/// Returns whether the file at `path` exists with exactly `contents`, comparing sizes before reading it.
+fn is_unchanged(path: &Path, contents: &[u8]) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.len() == contents.len() as u64)
+        && fs::read(path).is_ok_and(|existing| existing == contents)
+}
+
+/// Returns the BLAKE3 hash of the whole contents of a file, streamed from disk.
+fn content_hash(path: &Path) -> Result<blake3::Hash, io::Error> {
+    Ok(blake3::Hasher::new().update_reader(fs::File::open(path)?)?.finalize())
+}
+
+/// Hard-links `destination` to `source`, falling back to copying when linking fails, e.g. across filesystems.
+fn link_or_copy(source: &Path, destination: &Path) -> Result<(), io::Error> {
+    if fs::hard_link(source, destination).is_err() {
+        fs::copy(source, destination)?;
+    }
+    Ok(())
 }
 
 /// Processes all files in a directory tree and writes them to an output directory with hashed filenames.
@@ -147,17 +1143,20 @@ pub fn save_file(output_dir: &Path, file: &File) -> Result<(), LibError> {
 ///
 /// # Parameters
 ///
-/// - `input_dir`: The root input directory to scan recursively.
+/// - `input_dir`: The root input directory to scan recursively. A single file is processed on
+///   its own, subject to the same filters as the files of a directory.
 /// - `output_dir`: The root output directory where processed files are saved.
 ///
 /// # Returns
 ///
-/// [`Ok`] if all files were processed successfully, or an [`io::Error`] if any file or directory operation fails.
+/// [`Ok`] containing a [`FileResult`] per input file, or an [`io::Error`] if any file or directory operation fails.
 ///
 /// # Manifest File
 ///
 /// The `manifest.json` file contains a JSON object where each key is the original full path
 /// of a file (as a string), and the value is the hashed filename (relative to `output_dir`).
+/// Options that record extra information per file, like [`ProcessOptions::manifest_short_hash`],
+/// turn the values into [`ManifestEntry`] objects instead.
 ///
 /// # Examples
 ///
@@ -184,164 +1183,2716 @@ pub fn save_file(output_dir: &Path, file: &File) -> Result<(), LibError> {
 ///
 /// assert!(!entries.is_empty());
 /// ```
-pub fn process_directory(input_dir: &Path, output_dir: &Path) -> Result<(), LibError> {
-    fs::create_dir_all(output_dir)?;
-
-    let mut manifest = HashMap::new();
-
-    for_each_file(input_dir, &mut |path| {
-        process_file(path, output_dir, &mut manifest)
-    })?;
-
-    write_manifest(output_dir, &manifest)?;
-
-    Ok(())
-}
-
-/// Processes a single file: loads it, hashes its name, and saves it to the output directory.
-fn process_file(
-    path: &Path,
-    output_dir: &Path,
-    manifest: &mut HashMap<String, String>,
-) -> Result<(), LibError> {
-    let input_file = load_file(path)?;
-
-    let minified_css = minify_css(input_file);
-    
-    let hashed_file = hash_file_rename(minified_css?)?;
-
-    manifest.insert(
-        path.to_string_lossy().to_string(),
-        hashed_file.filename.clone(),
-    );
-
-    save_file(output_dir, &hashed_file)
-}
-
-fn minify_css(f: File) ->  Result<File, LibError> {
-    if f.file_type != FileType::CSS {
-        return Ok(f);
-    }
-    
-    let contents = std::str::from_utf8(&f.contents)
-        .map_err(|err| LibError::ParsingError(err.to_string()))?;
-
-    let mut ss = StyleSheet::parse(contents, ParserOptions::default())
-        .map_err(|err| LibError::ParsingError(err.to_string()))?;
-
-    ss.minify(MinifyOptions::default())
-        .map_err(|err| LibError::MinificationError(err.to_string()))?;
-
-    let minified_contents = ss.to_css(PrinterOptions { minify: true, ..PrinterOptions::default() })
-        .map_err(|err| LibError::MinificationError(err.to_string()))?
-        .code
-        .into_bytes();
-    
-    Ok(File {
-        contents: minified_contents,
-        ..f
-    })
-}
-
-/// Writes the manifest file to the output directory as pretty-printed JSON.
-fn write_manifest(output_dir: &Path, manifest: &HashMap<String, String>) -> Result<(), LibError> {
-    let manifest_path = output_dir.join("manifest.json");
-    let json = serde_json::to_string_pretty(manifest)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    fs::write(manifest_path, json).map_err(|err| LibError::IOError(err))
+pub fn process_directory(input_dir: &Path, output_dir: &Path) -> Result<Vec<FileResult>, LibError> {
+    process_directory_with_options(input_dir, output_dir, &ProcessOptions::default())
 }
 
-/// Recursively traverses a directory tree, applying a function to each file found.
-///
-/// This function walks through the directory at `path` and all its subdirectories,
-/// calling the provided closure `f` on every file encountered. If `path` itself is a
-/// file, the closure is applied directly.
+/// Processes all files in a directory tree like [`process_directory`], using the given [`ProcessOptions`].
 ///
 /// # Parameters
 ///
-/// - `path`: The starting directory or file path to traverse.
-/// - `f`: A mutable closure that takes a reference to a [`Path`] and returns an [`io::Result<()>`].
+/// - `input_dir`: The root input directory to scan recursively. A single file is processed on
+///   its own, subject to the same filters as the files of a directory.
+/// - `output_dir`: The root output directory where processed files are saved.
+/// - `options`: The [`ProcessOptions`] to use.
 ///
 /// # Returns
 ///
-/// [`Ok(())`] if all files were processed successfully, or the first [`io::Error`] encountered.
+/// [`Ok`] containing a [`FileResult`] per input file, or the first error encountered.
 ///
 /// # Examples
 ///
 /// ```
-/// # use std::fs::{self, File as FsFile};
-/// # use std::io::Write;
+/// # use std::fs;
 /// # use tempfile::tempdir;
-/// # use static_preprocessing::for_each_file;
+/// # use static_preprocessing::{process_directory_with_options, ProcessOptions};
 /// #
-/// let temp = tempdir().unwrap();
-/// let file_path = temp.path().join("foo.txt");
-/// let mut file = FsFile::create(&file_path).unwrap();
-/// writeln!(file, "Hello").unwrap();
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+/// fs::write(input_dir.path().join("main.css"), "body { margin: 0; }").unwrap();
 ///
-/// let mut count = 0;
-/// for_each_file(temp.path(), &mut |path| {
-///     if path.is_file() {
-///         count += 1;
-///     }
-///     Ok(())
-/// }).unwrap();
+/// let options = ProcessOptions { shard_depth: 2, ..ProcessOptions::default() };
+/// process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 ///
-/// assert_eq!(count, 1);
+/// // The hashed file is saved in a subdirectory named after the first two hash characters
+/// let shards: Vec<_> = fs::read_dir(output_dir.path())
+///     .unwrap()
+///     .flat_map(|res| res.ok())
+///     .filter(|entry| entry.path().is_dir())
+///     .collect();
+/// assert_eq!(shards.len(), 1);
 /// ```
-pub fn for_each_file<F: FnMut(&Path) -> Result<(), LibError>>(path: &Path, f: &mut F) -> Result<(), LibError> {
-    if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            for_each_file(&path, f)?;
-        }
-        Ok(())
-    } else {
-        f(path)
-    }
+pub fn process_directory_with_options(input_dir: &Path, output_dir: &Path, options: &ProcessOptions) -> Result<Vec<FileResult>, LibError> {
+    let root = if input_dir.is_dir() { input_dir } else { input_dir.parent().unwrap_or(input_dir) };
+    let options = &*options.resolve(Some(root));
+
+    let mut outputs = OutputPaths::with_root(input_dir);
+    let (results, manifest) = process_directory_entries(input_dir, output_dir, options, &mut outputs)?;
+    emit_manifests(output_dir, options, &manifest, &outputs)?;
+
+    Ok(results)
 }
 
-/// Determines the [`FileType`] based on the file extension.
+/// Processes all files in a directory tree like [`process_directory_with_options`], returning the
+/// manifest instead of writing it.
 ///
-/// This function maps common file extensions to specific [`FileType`] variants.
-/// It recognizes `"css"`, `"js"`, and common image formats like `"webp"`, `"jpg"`, `"jpeg"`, `"png"`, and `"avif"`.
-/// All other extensions are classified as [`FileType::Other`].
+/// This is for callers that store the manifest themselves, e.g. in a database. None of the
+/// manifest files enabled in the options are written, but the processed files are.
 ///
 /// # Parameters
 ///
-/// - `ext`: A string slice representing the file extension (without the dot).
+/// - `input_dir`: The root input directory to scan recursively.
+/// - `output_dir`: The root output directory where processed files are saved.
+/// - `options`: The [`ProcessOptions`] to use.
 ///
 /// # Returns
 ///
-/// A corresponding variant of [`FileType`] based on the extension.
+/// [`Ok`] containing a [`FileResult`] per input file and the [`Manifest`](manifest::Manifest)
+/// of the build, or the first error encountered.
 ///
 /// # Examples
 ///
 /// ```
-/// # use static_preprocessing::detect_file_type;
-/// # use static_preprocessing::FileType;
-/// assert!(matches!(detect_file_type("css"), FileType::CSS));
-/// assert!(matches!(detect_file_type("png"), FileType::Image));
-/// assert!(matches!(detect_file_type("txt"), FileType::Other));
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::{process_directory_to_manifest, ProcessOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+/// let css_path = input_dir.path().join("main.css");
+/// fs::write(&css_path, "body { margin: 0; }").unwrap();
+///
+/// let (_, manifest) = process_directory_to_manifest(input_dir.path(), output_dir.path(), &ProcessOptions::default()).unwrap();
+///
+/// assert!(manifest.get(css_path.to_str().unwrap()).is_some());
+/// assert!(!output_dir.path().join("manifest.json").exists());
 /// ```
-pub fn detect_file_type(ext: &str) -> FileType {
-    match ext {
-        "css" => FileType::CSS,
-        "js" => FileType::JS,
-        "webp" | "jpg" | "jpeg" | "png" | "avif" => FileType::Image,
-        _ => FileType::Other,
-    }
+pub fn process_directory_to_manifest(input_dir: &Path, output_dir: &Path, options: &ProcessOptions) -> Result<(Vec<FileResult>, manifest::Manifest), LibError> {
+    let root = if input_dir.is_dir() { input_dir } else { input_dir.parent().unwrap_or(input_dir) };
+    let options = &*options.resolve(Some(root));
+
+    let (results, manifest) = process_directory_entries(input_dir, output_dir, options, &mut OutputPaths::with_root(input_dir))?;
+    Ok((results, manifest::Manifest::from_entries(manifest)))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Processes the files of a directory tree with resolved options, recording their outputs in `outputs` and returning the manifest entries without writing them.
+fn process_directory_entries(
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    outputs: &mut OutputPaths,
+) -> Result<(Vec<FileResult>, HashMap<String, ManifestEntry>), LibError> {
+    options.prepare_output_dir(output_dir)?;
 
-    #[test]
-    fn test_detect_file_type() {
+    let mut manifest = match options.modified_since {
+        Some(_) => existing_manifest(output_dir, options)?,
+        None => HashMap::new()
+    };
+    let mut results = Vec::new();
+    let found = match options.batch_size {
+        Some(batch_size) => {
+            let mut ignore_rules = IgnoreRules::new(input_dir, options);
+            let mut batch = Vec::new();
+            let mut found = 0;
+            walk_files(input_dir, true, &mut |path| {
+                if is_input(input_dir, path, options) && !ignore_rules.is_ignored(path) {
+                    batch.push(path.to_path_buf());
+                    found += 1;
+                }
+                if batch.len() >= batch_size.max(1) {
+                    results.extend(process_batch(input_dir, std::mem::take(&mut batch), output_dir, options, &mut manifest, outputs)?);
+                }
+                Ok(())
+            })?;
+            results.extend(process_batch(input_dir, batch, output_dir, options, &mut manifest, outputs)?);
+            found
+        },
+        None => {
+            let paths = collect_files(input_dir, options)?;
+            let found = paths.len();
+            results = process_batch(input_dir, paths, output_dir, options, &mut manifest, outputs)?;
+            found
+        }
+    };
+
+    if found == 0 && input_dir.is_dir()
+        && let Some(directories) = empty_subdirectories(input_dir)? {
+        if options.error_on_empty {
+            return Err(LibError::EmptyDirectories { path: input_dir.to_path_buf(), directories });
+        }
+        options.log(log::Level::Warn, format_args!("{} contains {} directories but no files", input_dir.display(), directories));
+    }
+    warn_ambiguous_basenames(&results, options);
+
+    Ok((results, manifest))
+}
+
+/// Processes a batch of the files found in `input_dir`, leaving out those not modified since `modified_since`, if set.
+fn process_batch(
+    input_dir: &Path,
+    mut paths: Vec<PathBuf>,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &mut OutputPaths,
+) -> Result<Vec<FileResult>, LibError> {
+    if let Some(since) = options.modified_since {
+        paths.retain(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).is_ok_and(|modified| modified > since));
+    }
+    process_tree(input_dir, &paths, output_dir, options, manifest, outputs)
+}
+
+/// Processes a list of files and writes them to an output directory with hashed filenames.
+///
+/// This is the incremental counterpart to [`process_directory`]: only the given paths are
+/// loaded, hashed and saved. Unless disabled in the options, the processed files are merged into
+/// the `manifest.json` in `output_dir`, keeping the entries of files not processed in this run.
+///
+/// # Parameters
+///
+/// - `paths`: The files to process.
+/// - `output_dir`: The root output directory where processed files are saved.
+/// - `options`: The [`ProcessOptions`] to use.
+///
+/// # Returns
+///
+/// [`Ok`] containing a [`FileResult`] per path, in the same order, or the first error encountered.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::{process_files, ProcessOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+///
+/// let file_path = input_dir.path().join("main.css");
+/// fs::write(&file_path, "body { margin: 0; }").unwrap();
+///
+/// process_files(&[file_path], output_dir.path(), &ProcessOptions::default()).unwrap();
+///
+/// assert!(output_dir.path().join("manifest.json").exists());
+/// ```
+pub fn process_files(paths: &[PathBuf], output_dir: &Path, options: &ProcessOptions) -> Result<Vec<FileResult>, LibError> {
+    options.prepare_output_dir(output_dir)?;
+
+    let options = &*options.resolve(None);
+
+    let mut manifest = existing_manifest(output_dir, options)?;
+
+    let mut outputs = OutputPaths::default();
+    let results = process_paths(paths, output_dir, options, &mut manifest, &mut outputs)?;
+    warn_ambiguous_basenames(&results, options);
+
+    emit_manifests(output_dir, options, &manifest, &outputs)?;
+
+    Ok(results)
+}
+
+/// Processes entrypoints and the files they depend on, leaving out everything else.
+///
+/// Starting from each entry, the `@import`s and `url()`s of stylesheets and the relative
+/// imports of scripts are followed with [`deps::reachable_files`], and the files found are
+/// processed like [`process_files`] does, so orphaned files never make it to the output.
+///
+/// # Parameters
+///
+/// - `entries`: The entrypoints to build.
+/// - `output_dir`: The root output directory where processed files are saved.
+/// - `options`: The [`ProcessOptions`] to use.
+///
+/// # Returns
+///
+/// [`Ok`] containing a [`FileResult`] per reachable file, entrypoints first, or the first error
+/// encountered.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::{process_entrypoints, ProcessOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+/// let entry = input_dir.path().join("main.js");
+/// fs::write(&entry, "import './util.js';").unwrap();
+/// fs::write(input_dir.path().join("util.js"), "console.log(1);").unwrap();
+///
+/// let results = process_entrypoints(&[entry], output_dir.path(), &ProcessOptions::default()).unwrap();
+///
+/// assert_eq!(results.len(), 2);
+/// ```
+pub fn process_entrypoints(entries: &[PathBuf], output_dir: &Path, options: &ProcessOptions) -> Result<Vec<FileResult>, LibError> {
+    process_files(&deps::reachable_files(entries)?, output_dir, options)
+}
+
+/// Processes files one at a time, assembling their manifest until [`Processor::finalize`] is called.
+///
+/// This splits [`process_files`] into its steps, so custom logic can run between processing
+/// files and writing the manifest. Like [`process_files`], the processed files are merged into
+/// the manifest already in the output directory.
+#[derive(Debug)]
+pub struct Processor {
+    /// The root output directory
+    output_dir: PathBuf,
+    /// The resolved options
+    options: ProcessOptions,
+    /// The manifest assembled so far
+    manifest: HashMap<String, ManifestEntry>,
+    /// The outputs of the files processed so far, for rewriting CSS URLs
+    outputs: OutputPaths,
+    /// The results of the files processed so far
+    results: Vec<FileResult>
+}
+
+impl Processor {
+    /// Creates a processor writing to `output_dir`.
+    ///
+    /// # Parameters
+    ///
+    /// - `output_dir`: The root output directory where processed files are saved.
+    /// - `options`: The [`ProcessOptions`] to use.
+    ///
+    /// # Returns
+    ///
+    /// [`Ok`] containing the [`Processor`], or an error if the output directory or its existing
+    /// manifest can't be used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tempfile::tempdir;
+    /// # use static_preprocessing::{ProcessOptions, Processor};
+    /// #
+    /// let output_dir = tempdir().unwrap();
+    /// let processor = Processor::new(output_dir.path(), &ProcessOptions::default()).unwrap();
+    ///
+    /// assert!(processor.finalize().unwrap().get("main.css").is_none());
+    /// ```
+    pub fn new(output_dir: &Path, options: &ProcessOptions) -> Result<Processor, LibError> {
+        options.prepare_output_dir(output_dir)?;
+        let options = options.resolve(None).into_owned();
+        Ok(Processor {
+            output_dir: output_dir.to_path_buf(),
+            manifest: existing_manifest(output_dir, &options)?,
+            options,
+            outputs: OutputPaths::default(),
+            results: Vec::new()
+        })
+    }
+
+    /// Processes a single file and adds it to the manifest.
+    ///
+    /// Stylesheets can only have their URLs rewritten to files processed before them.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: The file to process.
+    ///
+    /// # Returns
+    ///
+    /// [`Ok`] containing the [`FileResult`] of the file, or an error if it couldn't be processed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::fs;
+    /// # use tempfile::tempdir;
+    /// # use static_preprocessing::{ProcessOptions, Processor};
+    /// #
+    /// let input_dir = tempdir().unwrap();
+    /// let output_dir = tempdir().unwrap();
+    /// let path = input_dir.path().join("main.css");
+    /// fs::write(&path, "body { margin: 0; }").unwrap();
+    ///
+    /// let mut processor = Processor::new(output_dir.path(), &ProcessOptions::default()).unwrap();
+    /// let result = processor.process(&path).unwrap();
+    ///
+    /// assert!(output_dir.path().join(result.output.unwrap()).exists());
+    /// ```
+    pub fn process(&mut self, path: &Path) -> Result<FileResult, LibError> {
+        let mut results = process_paths(&[path.to_path_buf()], &self.output_dir, &self.options, &mut self.manifest, &mut self.outputs)?;
+        let result = results.remove(0);
+        self.results.push(result.clone());
+        Ok(result)
+    }
+
+    /// Writes the manifest files enabled in the options and returns the assembled manifest.
+    ///
+    /// # Returns
+    ///
+    /// [`Ok`] containing the [`Manifest`] of every processed file, along with the entries already
+    /// in the output directory, or an error if the manifest files couldn't be written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::fs;
+    /// # use tempfile::tempdir;
+    /// # use static_preprocessing::{ProcessOptions, Processor};
+    /// #
+    /// let input_dir = tempdir().unwrap();
+    /// let output_dir = tempdir().unwrap();
+    /// let path = input_dir.path().join("main.css");
+    /// fs::write(&path, "body { margin: 0; }").unwrap();
+    ///
+    /// let mut processor = Processor::new(output_dir.path(), &ProcessOptions::default()).unwrap();
+    /// let result = processor.process(&path).unwrap();
+    /// let manifest = processor.finalize().unwrap();
+    ///
+    /// assert_eq!(manifest.get(path.to_str().unwrap()), result.output.as_deref());
+    /// assert!(output_dir.path().join("manifest.json").exists());
+    /// ```
+    pub fn finalize(self) -> Result<manifest::Manifest, LibError> {
+        warn_ambiguous_basenames(&self.results, &self.options);
+        emit_manifests(&self.output_dir, &self.options, &self.manifest, &self.outputs)?;
+        Ok(manifest::Manifest::from_entries(self.manifest))
+    }
+}
+
+/// Sums the recorded processing time of files per output type.
+///
+/// Together with [`ProcessOptions::record_timings`], this shows which kind of file dominates a build.
+///
+/// # Parameters
+///
+/// - `results`: The [`FileResult`]s of a build.
+///
+/// # Returns
+///
+/// The total duration per [`FileType`]. Types without timed files are left out.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::{durations_by_type, process_directory_with_options, FileType, ProcessOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+/// fs::write(input_dir.path().join("main.css"), "body { margin: 0; }").unwrap();
+///
+/// let options = ProcessOptions { record_timings: true, ..ProcessOptions::default() };
+/// let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+///
+/// assert!(durations_by_type(&results).contains_key(&FileType::CSS));
+/// ```
+pub fn durations_by_type(results: &[FileResult]) -> HashMap<FileType, Duration> {
+    let mut durations = HashMap::new();
+    for result in results {
+        if let Some(duration) = result.duration {
+            *durations.entry(result.file_type).or_default() += duration;
+        }
+    }
+    durations
+}
+
+/// Runs raw bytes of a known type through the processing pipeline.
+///
+/// This is the lowest-level entry point, for content that is generated on the fly rather than
+/// read from disk. Nothing is written; the transformed bytes and the hashed filename are returned.
+///
+/// # Parameters
+///
+/// - `contents`: The raw contents.
+/// - `file_type`: The [`FileType`] of the contents.
+/// - `ext`: The file extension (without the dot) the hashed filename should get.
+///
+/// # Returns
+///
+/// [`Ok`] containing the transformed bytes and the hashed filename, or the error of the first
+/// failing transform.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{process_bytes, FileType};
+/// #
+/// let (contents, filename) = process_bytes(b"body { margin: 0; }".to_vec(), FileType::CSS, "css").unwrap();
+///
+/// assert_eq!(contents, b"body{margin:0}");
+/// assert!(filename.ends_with(".css"));
+/// ```
+pub fn process_bytes(contents: Vec<u8>, file_type: FileType, ext: &str) -> Result<(Vec<u8>, String), LibError> {
+    let file = File {
+        filename: format!("input.{}", ext),
+        file_type,
+        contents,
+        hash: None
+    };
+
+    let processed = transform(file, &ProcessOptions::default())?;
+
+    Ok((processed.contents, processed.filename))
+}
+
+/// Runs raw bytes of a known type through the processing pipeline like [`process_bytes`], but
+/// names the output explicitly instead of hashing it.
+///
+/// This is for outputs with a fixed name, e.g. a minified `main.min.css` next to its source.
+/// The contents are transformed according to `file_type` alone, so a name marking them as
+/// already minified doesn't keep them from being minified.
+///
+/// # Parameters
+///
+/// - `contents`: The raw contents.
+/// - `file_type`: The [`FileType`] of the contents.
+/// - `filename`: The output filename, used as it is, even for Markdown rendered to HTML.
+/// - `options`: The [`ProcessOptions`] to transform the contents with.
+///
+/// # Returns
+///
+/// [`Ok`] containing the transformed bytes and `filename`, or the error of the first failing
+/// transform.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{process_bytes_as, FileType, ProcessOptions};
+/// #
+/// let (contents, filename) = process_bytes_as(b"body { margin: 0; }".to_vec(), FileType::CSS, "site.min.css", &ProcessOptions::default()).unwrap();
+///
+/// assert_eq!(contents, b"body{margin:0}");
+/// assert_eq!(filename, "site.min.css");
+/// ```
+pub fn process_bytes_as(contents: Vec<u8>, file_type: FileType, filename: &str, options: &ProcessOptions) -> Result<(Vec<u8>, String), LibError> {
+    // The caller's name could mark the contents as minified, so they're transformed under a neutral one
+    let ext = SUPPORTED_EXTENSIONS.iter().find(|(_, supported)| *supported == file_type).map_or("bin", |(ext, _)| ext);
+    let file = File {
+        filename: format!("input.{}", ext),
+        file_type,
+        contents,
+        hash: None
+    };
+
+    let processed = transform_contents(file, options)?;
+
+    Ok((processed.contents, filename.to_string()))
+}
+
+/// Reads contents of a known type from a reader, processes them, and writes the result to a writer.
+///
+/// This is [`process_bytes`] for streams, e.g. to run the pipeline on stdin in a shell pipeline.
+///
+/// # Parameters
+///
+/// - `file_type`: The [`FileType`] of the contents.
+/// - `ext`: The file extension (without the dot) the hashed filename should get.
+/// - `reader`: The source of the raw contents, read to the end.
+/// - `writer`: The destination of the transformed contents.
+///
+/// # Returns
+///
+/// [`Ok`] containing the hashed filename, or an error if reading, processing or writing fails.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{process_stdin, FileType};
+/// #
+/// let mut output = Vec::new();
+/// let filename = process_stdin(FileType::CSS, "css", &b"body { margin: 0; }"[..], &mut output).unwrap();
+///
+/// assert_eq!(output, b"body{margin:0}");
+/// assert!(filename.ends_with(".css"));
+/// ```
+pub fn process_stdin<R: Read, W: Write>(file_type: FileType, ext: &str, mut reader: R, mut writer: W) -> Result<String, LibError> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+
+    let (processed, filename) = process_bytes(contents, file_type, ext)?;
+
+    writer.write_all(&processed)?;
+    writer.flush()?;
+
+    Ok(filename)
+}
+
+/// Computes the output name a file would get, without writing any output.
+///
+/// The file is loaded, transformed and hashed in memory exactly as it would be when processed
+/// with the same options, so templates can reference hashed URLs without running a build.
+///
+/// # Parameters
+///
+/// - `path`: The path to the input file.
+/// - `options`: The [`ProcessOptions`] the file would be processed with.
+///
+/// # Returns
+///
+/// [`Ok`] containing the output name relative to the output directory, as it would be recorded
+/// in the manifest (including any shard directory), or the error processing would fail with.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::{expected_output_name, process_files, ProcessOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+/// let path = input_dir.path().join("main.css");
+/// fs::write(&path, "body { margin: 0; }").unwrap();
+///
+/// let options = ProcessOptions::default();
+/// let expected = expected_output_name(&path, &options).unwrap();
+///
+/// let results = process_files(&[path], output_dir.path(), &options).unwrap();
+/// assert_eq!(results[0].output.as_deref(), Some(expected.as_str()));
+/// ```
+pub fn expected_output_name(path: &Path, options: &ProcessOptions) -> Result<String, LibError> {
+    let hashed_file = transform(load_input(path, file_type_of(path, options), options)?, options)?;
+    Ok(format!("{}{}", shard_dir(&hashed_file.filename, options.shard_depth), hashed_file.filename))
+}
+
+/// Loads the entries of the manifest in `output_dir` for a partial build to update, if there is one.
+fn existing_manifest(output_dir: &Path, options: &ProcessOptions) -> Result<HashMap<String, ManifestEntry>, LibError> {
+    let manifest_path = output_dir.join(&options.manifest_filename);
+    if options.write_manifest && manifest_path.exists() {
+        Ok(manifest::Manifest::load(&manifest_path)?.into_entries())
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+/// Collects the files in `input_dir` that should be processed. When `input_dir` is a file, it is filtered like any file found in a directory.
+fn collect_files(input_dir: &Path, options: &ProcessOptions) -> Result<Vec<PathBuf>, LibError> {
+    let mut paths = Vec::new();
+    let mut ignore_rules = IgnoreRules::new(input_dir, options);
+    for_each_file(input_dir, &mut |path| {
+        if is_input(input_dir, path, options) && !ignore_rules.is_ignored(path) {
+            paths.push(path.to_path_buf());
+        }
+        Ok(())
+    })?;
+    if options.reproducible {
+        paths.sort();
+    }
+    Ok(paths)
+}
+
+/// The patterns of the ignore files in an input directory, loaded as they're needed.
+struct IgnoreRules<'a> {
+    /// The input directory, above which no ignore files apply
+    root: &'a Path,
+    /// The name of the ignore files, if enabled
+    filename: Option<&'a str>,
+    /// The patterns of each directory visited so far, `None` for directories without an ignore file
+    matchers: HashMap<PathBuf, Option<Gitignore>>
+}
+
+impl<'a> IgnoreRules<'a> {
+    /// Creates the ignore rules of `root`, as configured by `ignore_filename`.
+    fn new(root: &'a Path, options: &'a ProcessOptions) -> Self {
+        IgnoreRules { root, filename: options.ignore_filename.as_deref(), matchers: HashMap::new() }
+    }
+
+    /// Returns whether the ignore files of its directory or any directory above exclude `path`.
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let Some(filename) = self.filename else { return false };
+        if path.file_name().is_some_and(|name| *name == *filename) {
+            return true;
+        }
+
+        // The deepest ignore file with a matching pattern decides, so it can re-include files
+        for dir in path.ancestors().skip(1).take_while(|dir| dir.starts_with(self.root)) {
+            let matcher = self.matchers.entry(dir.to_path_buf()).or_insert_with(|| {
+                let ignore_path = dir.join(filename);
+                ignore_path.is_file().then(|| {
+                    let mut builder = GitignoreBuilder::new(dir);
+                    // An unreadable or malformed ignore file excludes nothing
+                    let _ = builder.add(&ignore_path);
+                    builder.build().unwrap_or_else(|_| Gitignore::empty())
+                })
+            });
+            if let Some(matcher) = matcher {
+                let matched = matcher.matched_path_or_any_parents(path, false);
+                if !matched.is_none() {
+                    return matched.is_ignore();
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Returns the number of subdirectories of a tree without any files, or `None` if it has a file or no subdirectory.
+fn empty_subdirectories(dir: &Path) -> Result<Option<usize>, LibError> {
+    let mut directories = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                return Ok(None);
+            }
+            directories += 1;
+            pending.push(path);
+        }
+    }
+    Ok((directories > 0).then_some(directories))
+}
+
+/// Warns about input files that share a basename but produced different outputs.
+fn warn_ambiguous_basenames(results: &[FileResult], options: &ProcessOptions) {
+    let mut written: Vec<&FileResult> = results.iter().filter(|result| result.output.is_some()).collect();
+    written.sort_by(|a, b| a.input.cmp(&b.input));
+
+    let mut first_by_name: HashMap<&std::ffi::OsStr, &FileResult> = HashMap::new();
+    for result in written {
+        let Some(name) = result.input.file_name() else { continue };
+        match first_by_name.get(name) {
+            Some(first) if first.output != result.output => options.warn(Warning::AmbiguousBasename {
+                first: first.input.clone(),
+                second: result.input.clone()
+            }),
+            Some(_) => {},
+            None => { first_by_name.insert(name, result); }
+        }
+    }
+}
+
+/// Processes files found in `input_dir`, copying those in verbatim directories as they are and passing the rest to [`process_paths`].
+fn process_tree(
+    input_dir: &Path,
+    paths: &[PathBuf],
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &mut OutputPaths,
+) -> Result<Vec<FileResult>, LibError> {
+    if options.verbatim_dirs.is_empty() {
+        return process_paths(paths, output_dir, options, manifest, outputs);
+    }
+
+    let mut globs = GlobSetBuilder::new();
+    for pattern in &options.verbatim_dirs {
+        globs.add(Glob::new(pattern).map_err(|err| LibError::ParsingError(err.to_string()))?);
+    }
+    let globs = globs.build().map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let mut results = Vec::new();
+    let mut transformed = Vec::new();
+    for path in paths {
+        let relative = path.strip_prefix(input_dir).unwrap_or(path);
+        let verbatim = relative
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| globs.is_match(dir));
+        if verbatim {
+            let result = copy_verbatim(path, relative, output_dir, options, manifest, outputs).map_err(|err| LibError::FileError {
+                path: path.to_path_buf(),
+                source: Box::new(err)
+            })?;
+            outputs.insert(normalize_path(path), output_dir.join(relative));
+            results.push(result);
+        } else {
+            transformed.push(path.to_path_buf());
+        }
+    }
+
+    results.extend(process_paths(&transformed, output_dir, options, manifest, outputs)?);
+    Ok(results)
+}
+
+/// Copies a file unchanged to `relative` within the output directory and records an identity manifest entry.
+fn copy_verbatim(
+    path: &Path,
+    relative: &Path,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &OutputPaths,
+) -> Result<FileResult, LibError> {
+    let output = relative
+        .to_str()
+        .ok_or_else(|| LibError::NonUtf8Path(path.to_path_buf()))?
+        .replace('\\', "/");
+    let output_path = output_dir.join(relative);
+
+    let contents = fs::read(path)?;
+    outputs.claim(&output_path, &contents)?;
+    if !is_unchanged(&output_path, &contents) {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&output_path, &contents)?;
+    }
+    let file_type = path.extension().and_then(|ext| ext.to_str()).map_or(FileType::Other, detect_file_type);
+    outputs.wrote(&output_path, file_type, options)?;
+
+    manifest.insert(
+        manifest_key(path, options)?,
+        ManifestEntry {
+            hashed: output.clone(),
+            cache_control: options.cache_control(false),
+            content_type: options.content_type(&output),
+            size: options.manifest_sizes.then_some(contents.len() as u64),
+            metadata: options.metadata(path)?,
+            ..ManifestEntry::default()
+        },
+    );
+
+    Ok(FileResult {
+        input: path.to_path_buf(),
+        output: Some(output),
+        file_type,
+        input_size: contents.len() as u64,
+        output_size: contents.len() as u64,
+        outcome: FileOutcome::Copied,
+        duration: None
+    })
+}
+
+/// Derives the manifest key of an input file.
+fn manifest_key(path: &Path, options: &ProcessOptions) -> Result<String, LibError> {
+    match &options.manifest_key {
+        Some(Callback(key)) => Ok(key(path)),
+        // A lossy key would not match the real file, so non-UTF-8 paths are rejected outright
+        None => Ok(path.to_str().ok_or_else(|| LibError::NonUtf8Path(path.to_path_buf()))?.to_string())
+    }
+}
+
+/// The outputs of the files processed in a run.
+#[derive(Debug, Default)]
+struct OutputPaths {
+    /// The input directory the processed files are relative to, if any
+    root: Option<PathBuf>,
+    /// The absolute output path of every file processed so far, by its lexically normalized input path
+    paths: HashMap<PathBuf, PathBuf>,
+    /// The hash of the contents written to each output path in this run, shared with the image workers
+    written: Mutex<HashMap<PathBuf, blake3::Hash>>,
+    /// Every file written in this run, including compressed siblings and manifests, for the file listing
+    files: Mutex<Vec<PathBuf>>
+}
+
+impl OutputPaths {
+    /// Creates the outputs of a run over the files in `root`.
+    fn with_root(root: &Path) -> Self {
+        OutputPaths { root: Some(root.to_path_buf()), ..OutputPaths::default() }
+    }
+
+    /// Returns the trailing input directories kept in the output path of a file, as configured by `flatten_to_depth`.
+    fn kept_dirs(&self, path: &Path, options: &ProcessOptions) -> String {
+        let Some(depth) = options.flatten_to_depth else { return String::new() };
+        let relative = self.root.as_deref().and_then(|root| path.strip_prefix(root).ok()).unwrap_or(path);
+        let dirs: Vec<_> = relative
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .filter_map(|component| match component {
+                Component::Normal(dir) => Some(dir.to_string_lossy()),
+                _ => None
+            })
+            .collect();
+        dirs[dirs.len().saturating_sub(depth)..].iter().map(|dir| format!("{}/", dir)).collect()
+    }
+
+    /// Returns the output path of a processed input file.
+    fn get(&self, input: &Path) -> Option<&PathBuf> {
+        self.paths.get(input)
+    }
+
+    /// Records the output path of a processed input file.
+    fn insert(&mut self, input: PathBuf, output: PathBuf) {
+        self.paths.insert(input, output);
+    }
+
+    /// Records that `contents` are about to be written to `path`, failing if different contents were already written there in this run.
+    fn claim(&self, path: &Path, contents: &[u8]) -> Result<(), LibError> {
+        self.claim_hash(path, blake3::hash(contents))
+    }
+
+    /// Records that contents with the given BLAKE3 hash are about to be written to `path`, like [`OutputPaths::claim`].
+    fn claim_hash(&self, path: &Path, hash: blake3::Hash) -> Result<(), LibError> {
+        match self.written.lock().unwrap().entry(normalize_path(path)) {
+            Entry::Occupied(entry) if *entry.get() != hash => Err(LibError::ConflictingOutput(path.to_path_buf())),
+            Entry::Occupied(_) => Ok(()),
+            Entry::Vacant(entry) => {
+                entry.insert(hash);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records an output file that was just written and passes it to the `on_written` hook, if any.
+    fn wrote(&self, path: &Path, file_type: FileType, options: &ProcessOptions) -> Result<(), LibError> {
+        self.record(path);
+        options.written(path, file_type)
+    }
+
+    /// Records a file that was just written, without passing it to any hook.
+    fn record(&self, path: &Path) {
+        self.files.lock().unwrap().push(path.to_path_buf());
+    }
+
+    /// Forgets the writes of the previous run, keeping the output paths, so a rebuild can overwrite its own outputs.
+    fn start_run(&mut self) {
+        self.written.get_mut().unwrap().clear();
+        self.files.get_mut().unwrap().clear();
+    }
+}
+
+/// Processes files into the manifest, recording their outputs in `outputs`.
+///
+/// When CSS URLs are rewritten, stylesheets are processed after everything else, so the files
+/// they reference already have their final names. The type of each file is detected once, here,
+/// and passed down. The results are returned in the order of `paths`.
+fn process_paths(
+    paths: &[PathBuf],
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &mut OutputPaths,
+) -> Result<Vec<FileResult>, LibError> {
+    let file_types: Vec<_> = paths.iter().map(|path| file_type_of(path, options)).collect();
+    let (stylesheets, others): (Vec<_>, Vec<_>) = paths
+        .iter()
+        .zip(&file_types)
+        .enumerate()
+        .map(|(index, (path, file_type))| (index, path, *file_type))
+        .partition(|(_, _, file_type)| options.rewrite_css_urls && matches!(file_type, Some(FileType::CSS | FileType::Scss)));
+
+    let mut results = Vec::with_capacity(paths.len());
+    for stage in [others, stylesheets] {
+        let stage_results = process_stage(stage, output_dir, options, manifest, outputs)?;
+        for (index, result) in &stage_results {
+            if let Some(output) = &result.output {
+                outputs.insert(normalize_path(&result.input), output_root(file_types[*index], options, output_dir).join(output));
+            }
+        }
+        results.extend(stage_results);
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Processes a batch of indexed files, handing images to a pool of `options.image_threads` workers so they don't hold up the other files.
+fn process_stage(
+    paths: Vec<(usize, &PathBuf, Option<FileType>)>,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &OutputPaths,
+) -> Result<Vec<(usize, FileResult)>, LibError> {
+    let (images, others): (Vec<_>, Vec<_>) = paths.into_iter().partition(|(_, _, file_type)| *file_type == Some(FileType::Image));
+    let queue = Mutex::new(images.into_iter());
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.image_threads.max(1))
+            .map(|_| scope.spawn(|| {
+                let mut manifest = HashMap::new();
+                let mut results = Vec::new();
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, path, file_type)) = next else { break };
+                    results.push((index, process_file(path, file_type, output_dir, options, &mut manifest, outputs)?));
+                }
+                Ok::<_, LibError>((manifest, results))
+            }))
+            .collect();
+
+        let mut results = Vec::new();
+        for (index, path, file_type) in others {
+            results.push((index, process_file(path, file_type, output_dir, options, manifest, outputs)?));
+        }
+
+        for worker in workers {
+            let (images, image_results) = worker.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload))?;
+            manifest.extend(images);
+            results.extend(image_results);
+        }
+
+        Ok(results)
+    })
+}
+
+/// Returns the output directory a file of the given type is written to, taking per-type routing into account.
+fn output_root<'a>(file_type: Option<FileType>, options: &'a ProcessOptions, output_dir: &'a Path) -> &'a Path {
+    file_type
+        .and_then(|file_type| options.type_output_dirs.get(&file_type))
+        .map_or(output_dir, PathBuf::as_path)
+}
+
+/// Processes a single file, wrapping any error in [`StaticPreprocessingError::FileError`] so it carries the offending path.
+fn process_file(
+    path: &Path,
+    file_type: Option<FileType>,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &OutputPaths,
+) -> Result<FileResult, LibError> {
+    let start = options.record_timings.then(Instant::now);
+    let result = try_process_file(path, file_type, output_dir, options, manifest, outputs).map_err(|err| {
+        let err = LibError::FileError { path: path.to_path_buf(), source: Box::new(err) };
+        options.log(log::Level::Error, format_args!("{}", err));
+        err
+    })?;
+    match &result.output {
+        Some(output) => options.log(log::Level::Info, format_args!("{} -> {}", path.display(), output)),
+        None => options.log(log::Level::Info, format_args!("{} skipped", path.display()))
+    }
+    Ok(FileResult { duration: start.map(|start| start.elapsed()), ..result })
+}
+
+/// Processes a single file of the type detected by [`file_type_of`]: loads it, hashes its name, and saves it to the output directory.
+fn try_process_file(
+    path: &Path,
+    file_type: Option<FileType>,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &mut HashMap<String, ManifestEntry>,
+    outputs: &OutputPaths,
+) -> Result<FileResult, LibError> {
+    let key = manifest_key(path, options)?;
+
+    if options.strict_extensions {
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+        if detect_file_type(&extension) == FileType::Other {
+            return Err(LibError::UnrecognizedExtension { path: path.to_path_buf(), extension });
+        }
+    }
+
+    if file_type == Some(FileType::Other) {
+        match options.unknown_file_policy {
+            UnknownFilePolicy::Copy => {},
+            UnknownFilePolicy::Skip => return skipped_result(path, FileType::Other),
+            UnknownFilePolicy::Error => return Err(LibError::UnknownFileType(
+                path.file_name().unwrap_or_default().to_string_lossy().to_string()
+            ))
+        }
+    }
+
+    if let Some(allowed) = &options.allowed_output_extensions {
+        let extension = output_extension(path, file_type);
+        if !allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(&extension)) {
+            match options.disallowed_output_policy {
+                DisallowedOutputPolicy::Skip => return skipped_result(path, file_type.unwrap_or(FileType::Other)),
+                DisallowedOutputPolicy::Error => return Err(LibError::DisallowedOutputExtension {
+                    path: path.to_path_buf(),
+                    extension
+                })
+            }
+        }
+    }
+
+    if options.strict_file_types && let Some(expected) = file_type {
+        check_magic_bytes(path, expected)?;
+    }
+
+    if let Some(file_type @ (FileType::CSS | FileType::JS)) = file_type
+        && options.csp_inline.as_ref().is_some_and(|Callback(select)| select(path))
+    {
+        let contents = fs::read(path)?;
+        manifest.insert(key, ManifestEntry {
+            csp: Some(csp_hash(&contents)),
+            metadata: options.metadata(path)?,
+            ..ManifestEntry::default()
+        });
+        return Ok(FileResult {
+            input: path.to_path_buf(),
+            output: None,
+            file_type,
+            input_size: contents.len() as u64,
+            output_size: 0,
+            outcome: FileOutcome::Inlined,
+            duration: None
+        });
+    }
+
+    let routed_dir = file_type.and_then(|file_type| options.type_output_dirs.get(&file_type));
+    let kept_dirs = outputs.kept_dirs(path, options);
+    let output_dir = &output_root(file_type, options, output_dir).join(&kept_dirs);
+    if routed_dir.is_some() || !kept_dirs.is_empty() {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    let passthrough = |file_type: FileType| {
+        file_type.is_passthrough()
+            && !(file_type.is_text() && options.trailing_newline.is_some())
+            && !(file_type == FileType::Font && options.font_subsetter.is_some())
+            && !(file_type == FileType::Image && options.normalize_orientation)
+    };
+
+    let input_size = fs::metadata(path)?.len();
+
+    let (shard, filename, file_type, transformed) = match file_type {
+        Some(file_type) if input_size < options.min_hash_size => {
+            let filename = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| LibError::NonUtf8Path(path.to_path_buf()))?
+                .to_string();
+            let contents = fs::read(path)?;
+            let output_path = output_dir.join(&filename);
+            outputs.claim(&output_path, &contents)?;
+            if !is_unchanged(&output_path, &contents) {
+                fs::write(output_path, contents)?;
+            }
+            (String::new(), filename, file_type, false)
+        },
+        Some(file_type) if options.hard_link_passthrough && passthrough(file_type) => {
+            let filename = hashed_filename(path, options.hash_naming())?;
+            let shard = shard_dir(&filename, options.shard_depth);
+            fs::create_dir_all(output_dir.join(&shard))?;
+            let output_path = output_dir.join(&shard).join(&filename);
+            // The name is the hash of the contents, so an existing output is already identical,
+            // unless the name only covers samples of them
+            if options.hash_naming().samples(input_size) {
+                outputs.claim_hash(&output_path, content_hash(path)?)?;
+                if output_path.exists() {
+                    fs::remove_file(&output_path)?;
+                }
+                link_or_copy(path, &output_path)?;
+            } else if !output_path.exists() {
+                link_or_copy(path, &output_path)?;
+            }
+            (shard, filename, file_type, false)
+        },
+        Some(file_type) if options.fast_passthrough && passthrough(file_type) => {
+            let (temp_path, filename) = hash_copy_file(path, output_dir, options.hash_naming())?;
+            let shard = shard_dir(&filename, options.shard_depth);
+            fs::create_dir_all(output_dir.join(&shard))?;
+            let output_path = output_dir.join(&shard).join(&filename);
+            let sampled = options.hash_naming().samples(input_size);
+            if sampled && let Err(err) = content_hash(path).map_err(LibError::from).and_then(|hash| outputs.claim_hash(&output_path, hash)) {
+                fs::remove_file(temp_path)?;
+                return Err(err);
+            }
+            // The name is the hash of the contents, so an existing output is already identical,
+            // unless the name only covers samples of them
+            if output_path.exists() && !sampled {
+                fs::remove_file(temp_path)?;
+            } else {
+                fs::rename(temp_path, output_path)?;
+            }
+            (shard, filename, file_type, false)
+        },
+        _ => transform_file(path, file_type, output_dir, options, outputs)?
+    };
+
+    let output_path = output_dir.join(&shard).join(&filename);
+    let shard = format!("{}{}", kept_dirs, shard);
+
+    if options.preserve_mtime {
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(path)?);
+        filetime::set_file_mtime(&output_path, mtime)?;
+    }
+
+    outputs.wrote(&output_path, file_type, options)?;
+
+    let siblings = if options.compression && options.compresses(file_type, &output_path) {
+        compress::write_worthwhile_siblings(&output_path, options.min_compression_ratio)?
+    } else {
+        Vec::new()
+    };
+    for sibling in &siblings {
+        outputs.wrote(sibling, file_type, options)?;
+    }
+
+    if options.sha256_sidecars {
+        outputs.wrote(&write_sha256_sidecar(&output_path)?, file_type, options)?;
+    }
+
+    let debug = match file_type {
+        FileType::CSS if options.debug_css => match write_debug_css(&output_path, options, outputs) {
+            Ok(debug_filename) => Some(format!("{}{}", shard, debug_filename)),
+            Err(LibError::ParsingError(_)) if options.lenient_css => None,
+            Err(err) => return Err(err)
+        },
+        _ => None
+    };
+
+    let output = format!("{}{}", shard, filename);
+    let result = FileResult {
+        input: path.to_path_buf(),
+        output: Some(output.clone()),
+        file_type,
+        input_size,
+        output_size: fs::metadata(&output_path)?.len(),
+        outcome: if transformed { FileOutcome::Transformed } else { FileOutcome::Copied },
+        duration: None
+    };
+
+    // Images whose header can't be read are still processed, just without dimensions
+    let dimensions = (options.manifest_image_dimensions && file_type == FileType::Image)
+        .then(|| imagesize::size(&output_path).ok())
+        .flatten()
+        .and_then(|size| Some((u32::try_from(size.width).ok()?, u32::try_from(size.height).ok()?)));
+
+    let sibling_size = |ext: &str| -> Result<Option<u64>, io::Error> {
+        match siblings.iter().find(|sibling| sibling.extension().is_some_and(|sibling_ext| sibling_ext == ext)) {
+            Some(sibling) if options.manifest_sizes => Ok(Some(fs::metadata(sibling)?.len())),
+            _ => Ok(None)
+        }
+    };
+    let (gzip_size, brotli_size) = (sibling_size("gz")?, sibling_size("br")?);
+
+    let formats = if file_type == FileType::Image && !options.image_variants.is_empty() {
+        write_image_variants(&output_path, options, outputs)?
+    } else {
+        None
+    };
+
+    let chunks = match options.chunk_size {
+        Some(chunk_size) if matches!(file_type, FileType::CSS | FileType::JS) && result.output_size > chunk_size as u64 => {
+            write_chunks(&output_path, file_type, chunk_size, options, outputs)?
+                .map(|chunks| chunks.into_iter().map(|chunk| format!("{}{}", shard, chunk)).collect())
+        },
+        _ => None
+    };
+
+    let content_type = options.content_type(&output);
+    manifest.insert(
+        key,
+        ManifestEntry {
+            hashed: output,
+            short: (options.manifest_short_hash && input_size >= options.min_hash_size).then(|| filename.chars().take(7).collect()),
+            dir: routed_dir.map(|dir| dir.to_string_lossy().to_string()),
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            original: options.manifest_original_name
+                .then(|| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                .flatten(),
+            cache_control: options.cache_control(input_size >= options.min_hash_size),
+            content_type,
+            csp: None,
+            debug,
+            size: options.manifest_sizes.then_some(result.output_size),
+            gzip_size,
+            brotli_size,
+            formats,
+            chunks,
+            metadata: options.metadata(path)?
+        },
+    );
+
+    Ok(result)
+}
+
+/// Determines the type of the file at `path`, asking the custom [`TypeDetector`] before falling back to its extension.
+fn file_type_of(path: &Path, options: &ProcessOptions) -> Option<FileType> {
+    if let Some(detector) = &options.type_detector {
+        // An unreadable file is left for processing to report
+        let mut head = Vec::with_capacity(TYPE_DETECTOR_HEAD_SIZE);
+        let _ = fs::File::open(path).and_then(|file| file.take(TYPE_DETECTOR_HEAD_SIZE as u64).read_to_end(&mut head));
+        if let Some(file_type) = detector.detect(path, &head) {
+            return Some(file_type);
+        }
+    }
+    path.extension().and_then(|ext| ext.to_str()).map(detect_file_type)
+}
+
+/// Returns the [`FileResult`] of a file left out of the output.
+fn skipped_result(path: &Path, file_type: FileType) -> Result<FileResult, LibError> {
+    Ok(FileResult {
+        input: path.to_path_buf(),
+        output: None,
+        file_type,
+        input_size: fs::metadata(path)?.len(),
+        output_size: 0,
+        outcome: FileOutcome::Skipped,
+        duration: None
+    })
+}
+
+/// Returns the extension the output of the file at `path` gets, which differs from its own for rendered or compiled files.
+fn output_extension(path: &Path, file_type: Option<FileType>) -> String {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    match file_type.unwrap_or(FileType::Other) {
+        FileType::Markdown => "html".to_string(),
+        FileType::Scss => "css".to_string(),
+        _ => extension.to_string()
+    }
+}
+
+/// Fails if the magic bytes of the file at `path` identify a type other than `expected`.
+fn check_magic_bytes(path: &Path, expected: FileType) -> Result<(), LibError> {
+    let mut header = Vec::with_capacity(12);
+    fs::File::open(path)?.take(12).read_to_end(&mut header)?;
+
+    match sniff_file_type(&header) {
+        Some(found) if expected != FileType::Other && found != expected => {
+            Err(LibError::FileTypeMismatch { expected, found })
+        },
+        _ => Ok(())
+    }
+}
+
+/// Loads, transforms, hashes and saves a file, returning its shard, hashed filename, final type and whether its contents changed.
+fn transform_file(
+    path: &Path,
+    file_type: Option<FileType>,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    outputs: &OutputPaths,
+) -> Result<(String, String, FileType, bool), LibError> {
+    let mut input_file = load_input(path, file_type, options)?;
+    let input_ext = Path::new(&input_file.filename).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let input_hash = options.hash_naming().content_hasher(input_ext, &input_file.contents).finalize();
+
+    if options.rewrite_css_urls && input_file.file_type == FileType::CSS {
+        input_file = rewrite_css_urls(input_file, path, output_dir, options, outputs)?;
+    }
+    if let Some(threshold) = options.extract_data_uris && input_file.file_type == FileType::CSS {
+        input_file = extract_data_uris(input_file, threshold, output_dir, options, outputs)?;
+    }
+
+    let hashed_file = transform(input_file, options)?;
+    let transformed = hashed_file.hash != Some(*input_hash.as_bytes());
+
+    let shard = shard_dir(&hashed_file.filename, options.shard_depth);
+    fs::create_dir_all(output_dir.join(&shard))?;
+    outputs.claim(&output_dir.join(&shard).join(&hashed_file.filename), &hashed_file.contents)?;
+    save_file(&output_dir.join(&shard), &hashed_file)?;
+
+    Ok((shard, hashed_file.filename, hashed_file.file_type, transformed))
+}
+
+/// Writes a pretty-printed `<stem>.debug.css` copy next to a stylesheet, returning its filename.
+fn write_debug_css(output_path: &Path, options: &ProcessOptions, outputs: &OutputPaths) -> Result<String, LibError> {
+    let contents = fs::read_to_string(output_path)?;
+    let ss = StyleSheet::parse(&contents, ParserOptions::default())
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+    let pretty = ss.to_css(PrinterOptions { minify: false, ..PrinterOptions::default() })
+        .map_err(|err| LibError::MinificationError(err.to_string()))?
+        .code;
+
+    let debug_path = output_path.with_extension("debug.css");
+    outputs.claim(&debug_path, pretty.as_bytes())?;
+    if !is_unchanged(&debug_path, pretty.as_bytes()) {
+        fs::write(&debug_path, &pretty)?;
+    }
+    outputs.wrote(&debug_path, FileType::CSS, options)?;
+    Ok(debug_path.file_name().unwrap_or_default().to_string_lossy().to_string())
+}
+
+/// Rewrites the `url()`s of a stylesheet that point at processed files to the relative paths of their outputs.
+fn rewrite_css_urls(f: File, path: &Path, output_dir: &Path, options: &ProcessOptions, outputs: &OutputPaths) -> Result<File, LibError> {
+    let mut rewriter = UrlRewriter {
+        source_dir: path.parent().unwrap_or(Path::new("")),
+        output_dir: &normalize_path(output_dir),
+        shard_depth: options.shard_depth,
+        outputs,
+        unprocessed: Vec::new()
+    };
+    let rewritten = rewrite_stylesheet(f, &mut rewriter, options)?;
+    for url in rewriter.unprocessed {
+        options.warn(Warning::UnprocessedReference { stylesheet: path.to_path_buf(), url });
+    }
+    Ok(rewritten)
+}
+
+/// Moves the base64 `data:` URIs of a stylesheet that decode to more than `threshold` bytes
+/// into hashed files next to the other outputs, replacing them with relative `url()`s.
+fn extract_data_uris(
+    f: File,
+    threshold: usize,
+    output_dir: &Path,
+    options: &ProcessOptions,
+    outputs: &OutputPaths,
+) -> Result<File, LibError> {
+    let (shard_depth, hash_naming) = (options.shard_depth, options.hash_naming());
+    let mut extractor = DataUriExtractor {
+        threshold,
+        output_dir: &normalize_path(output_dir),
+        shard_depth,
+        hash_naming,
+        extracted: Vec::new()
+    };
+    let rewritten = rewrite_stylesheet(f, &mut extractor, options)?;
+
+    for (shard, extracted) in extractor.extracted {
+        fs::create_dir_all(output_dir.join(&shard))?;
+        outputs.claim(&output_dir.join(&shard).join(&extracted.filename), &extracted.contents)?;
+        save_file(&output_dir.join(&shard), &extracted)?;
+        outputs.wrote(&output_dir.join(&shard).join(&extracted.filename), extracted.file_type, options)?;
+    }
+
+    Ok(rewritten)
+}
+
+/// Runs a URL visitor over a stylesheet and prints it back, unminified unless it was already.
+///
+/// With `lenient_css`, a stylesheet that fails to parse is passed through untouched with a warning.
+fn rewrite_stylesheet<V: for<'i> Visitor<'i, Error = std::convert::Infallible>>(f: File, visitor: &mut V, options: &ProcessOptions) -> Result<File, LibError> {
+    // `minify_css` leaves `.min.css` files alone, so they have to come out of here minified
+    match rewrite_stylesheet_contents(&f.contents, visitor, is_minified(&f.filename)) {
+        Ok(rewritten) => Ok(File {
+            contents: rewritten,
+            ..f
+        }),
+        Err(err) if options.lenient_css => {
+            options.warn(Warning::UnminifiedStylesheet { filename: f.filename.clone(), error: err.to_string() });
+            Ok(f)
+        },
+        Err(err) => Err(err)
+    }
+}
+
+/// Parses a stylesheet, runs a visitor over it and prints it back.
+fn rewrite_stylesheet_contents<V: for<'i> Visitor<'i, Error = std::convert::Infallible>>(contents: &[u8], visitor: &mut V, minify: bool) -> Result<Vec<u8>, LibError> {
+    let contents = std::str::from_utf8(contents)
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let mut ss = StyleSheet::parse(contents, ParserOptions::default())
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let Ok(()) = ss.visit(visitor);
+
+    // Keep every license comment, not just the leading ones lightningcss holds on to
+    ss.license_comments = license_comments(contents).into_iter().map(Into::into).collect();
+
+    Ok(ss.to_css(PrinterOptions { minify, ..PrinterOptions::default() })
+        .map_err(|err| LibError::ParsingError(err.to_string()))?
+        .code
+        .into_bytes())
+}
+
+/// Rewrites relative `url()`s that resolve to a processed file.
+struct UrlRewriter<'a> {
+    source_dir: &'a Path,
+    output_dir: &'a Path,
+    shard_depth: usize,
+    outputs: &'a OutputPaths,
+    /// The URLs of existing files that have no output yet.
+    unprocessed: Vec<String>
+}
+
+impl<'i> Visitor<'i> for UrlRewriter<'_> {
+    type Error = std::convert::Infallible;
+
+    fn visit_types(&self) -> VisitTypes {
+        visit_types!(URLS)
+    }
+
+    fn visit_url(&mut self, url: &mut Url<'i>) -> Result<(), Self::Error> {
+        let is_relative = !url.url.is_empty()
+            && !url.url.starts_with(['/', '#'])
+            && !url.url.split('/').next().is_some_and(|first| first.contains(':'));
+        if !is_relative {
+            return Ok(());
+        }
+
+        // A query or fragment, e.g. `font.woff2?v=1` or `sprite.svg#icon`, is kept as it is
+        let (file, suffix) = url.url.split_at(url.url.find(['?', '#']).unwrap_or(url.url.len()));
+        let source = normalize_path(&self.source_dir.join(file));
+        if let Some(output) = self.outputs.get(&source) {
+            url.url = format!("{}{}", stylesheet_url(self.output_dir, self.shard_depth, output), suffix).into();
+        } else if source.is_file() {
+            self.unprocessed.push(url.url.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Replaces large base64 `data:` URIs with the hashed files they are extracted to.
+struct DataUriExtractor<'a> {
+    threshold: usize,
+    output_dir: &'a Path,
+    shard_depth: usize,
+    hash_naming: HashNaming,
+    /// The extracted files, with their shard subdirectory.
+    extracted: Vec<(String, File)>
+}
+
+impl<'i> Visitor<'i> for DataUriExtractor<'_> {
+    type Error = std::convert::Infallible;
+
+    fn visit_types(&self) -> VisitTypes {
+        visit_types!(URLS)
+    }
+
+    fn visit_url(&mut self, url: &mut Url<'i>) -> Result<(), Self::Error> {
+        let Some((header, data)) = url.url.strip_prefix("data:").and_then(|uri| uri.split_once(',')) else { return Ok(()) };
+        let Some(mime) = header.strip_suffix(";base64").map(|mime| mime.split(';').next().unwrap_or_default()) else { return Ok(()) };
+        let ext = match mime {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/avif" => "avif",
+            "image/svg+xml" => "svg",
+            _ => return Ok(())
+        };
+
+        // Malformed data is left for the browser to deal with
+        let Ok(contents) = base64::engine::general_purpose::STANDARD.decode(data) else { return Ok(()) };
+        if contents.len() <= self.threshold {
+            return Ok(());
+        }
+
+        let file = File { filename: format!("data.{}", ext), file_type: detect_file_type(ext), contents, hash: None };
+        let Ok(hashed) = hash_file_rename_with(file, self.hash_naming) else { return Ok(()) };
+        let shard = shard_dir(&hashed.filename, self.shard_depth);
+        url.url = stylesheet_url(self.output_dir, self.shard_depth, &self.output_dir.join(&shard).join(&hashed.filename)).into();
+        self.extracted.push((shard, hashed));
+        Ok(())
+    }
+}
+
+/// Resolves `.` and `..` components of a path lexically, without touching the file system.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            },
+            component => normalized.push(component)
+        }
+    }
+    normalized
+}
+
+/// Returns the URL of `target` relative to the directory `from`, with forward slashes. Both paths must be normalized.
+fn relative_url(from: &Path, target: &Path) -> String {
+    let from: Vec<_> = from.components().collect();
+    let target: Vec<_> = target.components().collect();
+    let common = from.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    let parents = std::iter::repeat_n("..".to_string(), from.len() - common);
+    let rest = target[common..].iter().map(|component| component.as_os_str().to_string_lossy().to_string());
+    parents.chain(rest).collect::<Vec<_>>().join("/")
+}
+
+/// Returns the URL of `target` from a stylesheet written to `output_dir`.
+///
+/// The stylesheet's own hashed name isn't known yet, but [`shard_dir`] always nests it exactly
+/// one level deeper when sharding is enabled, whichever shard that turns out to be.
+fn stylesheet_url(output_dir: &Path, shard_depth: usize, target: &Path) -> String {
+    let url = relative_url(output_dir, target);
+    if shard_depth > 0 { format!("../{}", url) } else { url }
+}
+
+/// Loads an input file, with its imports inlined if it is a stylesheet and bundling is enabled,
+/// or compiled to CSS if it is a Sass stylesheet. `file_type` is the type [`file_type_of`] detected.
+fn load_input(path: &Path, file_type: Option<FileType>, options: &ProcessOptions) -> Result<File, LibError> {
+    let bundles = options.bundle_css_imports
+        && path.extension().is_some_and(|ext| ext == "css")
+        && !is_minified(&path.to_string_lossy());
+    if bundles {
+        return bundle_css_entry(path);
+    }
+
+    let mut file = load_file(path)?;
+    if let Some(file_type) = file_type {
+        file.file_type = file_type;
+    }
+    compile_scss(file, path.parent())
+}
+
+/// Runs a [`File`] through the in-memory pipeline: rendering, minification and hashing.
+fn transform(f: File, options: &ProcessOptions) -> Result<File, LibError> {
+    Ok(hash_file_rename_with(transform_contents(f, options)?, options.hash_naming())?)
+}
+
+/// Runs a [`File`] through the in-memory pipeline up to hashing, keeping the name it was rendered or compiled to.
+fn transform_contents(f: File, options: &ProcessOptions) -> Result<File, LibError> {
+    let compiled_scss = compile_scss(f, None)?;
+    let rendered_markdown = render_markdown(compiled_scss)?;
+
+    let minified_css = minify_css(rendered_markdown, options)?;
+
+    let minified_css = if options.normalize_charset { normalize_charset(minified_css) } else { minified_css };
+
+    let normalized = normalize_trailing_newline(minified_css, options.trailing_newline);
+
+    let normalized = if options.normalize_orientation { normalize_orientation(normalized)? } else { normalized };
+
+    match &options.font_subsetter {
+        Some(subsetter) if normalized.file_type == FileType::Font => (subsetter.subset)(normalized, &subsetter.glyphs),
+        _ => Ok(normalized)
+    }
+}
+
+/// Returns the shard subdirectory (with a trailing `/`) for a hashed filename, or an empty string when sharding is disabled.
+///
+/// The subdirectory is always a single level, named after the first `shard_depth` characters.
+fn shard_dir(filename: &str, shard_depth: usize) -> String {
+    match filename.get(..shard_depth) {
+        Some(prefix) if shard_depth > 0 => format!("{}/", prefix),
+        _ => String::new()
+    }
+}
+
+/// Loads a stylesheet with its `@import` rules inlined.
+///
+/// Imports are resolved relative to the importing file, recursively. The result is not
+/// minified yet; it flows into minification like any other stylesheet.
+///
+/// # Parameters
+///
+/// - `path`: The path to the entry stylesheet.
+///
+/// # Returns
+///
+/// [`Ok`] containing the bundled [`File`], named after the entry stylesheet, or a
+/// [`StaticPreprocessingError::ParsingError`] if a stylesheet can't be read or parsed.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::bundle_css_entry;
+/// #
+/// let dir = tempdir().unwrap();
+/// fs::write(dir.path().join("base.css"), "body { margin: 0; }").unwrap();
+/// fs::write(dir.path().join("main.css"), "@import \"base.css\";\np { color: red; }").unwrap();
+///
+/// let bundled = bundle_css_entry(&dir.path().join("main.css")).unwrap();
+/// let css = String::from_utf8(bundled.contents).unwrap();
+///
+/// assert!(css.contains("margin: 0"));
+/// assert!(!css.contains("@import"));
+/// ```
+pub fn bundle_css_entry(path: &Path) -> Result<File, LibError> {
+    let provider = FileProvider::new();
+    let mut bundler = Bundler::new(&provider, None, ParserOptions::default());
+    let ss = bundler.bundle(path)
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let contents = ss.to_css(PrinterOptions::default())
+        .map_err(|err| LibError::ParsingError(err.to_string()))?
+        .code
+        .into_bytes();
+
+    Ok(File {
+        filename: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        file_type: FileType::CSS,
+        contents,
+        hash: None
+    })
+}
+
+/// Concatenates the stylesheets in a directory in an explicit order.
+///
+/// The patterns are globs relative to `input_dir`, like `["reset.css", "components/*.css",
+/// "overrides.css"]`, where `*` doesn't cross directories and `**` does. Files are concatenated
+/// in pattern order and sorted by path within each pattern; a file matched by several patterns
+/// is only included for the first. The result is not minified yet.
+///
+/// # Parameters
+///
+/// - `input_dir`: The directory the patterns are resolved against.
+/// - `patterns`: The ordered glob patterns.
+/// - `filename`: The filename of the bundle.
+///
+/// # Returns
+///
+/// [`Ok`] containing the bundled [`File`], or a [`StaticPreprocessingError::ParsingError`] if a
+/// pattern is invalid, or an I/O error if a stylesheet can't be read.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::bundle_css;
+/// #
+/// let dir = tempdir().unwrap();
+/// fs::write(dir.path().join("reset.css"), "* { margin: 0; }").unwrap();
+/// fs::write(dir.path().join("main.css"), "p { color: red; }").unwrap();
+///
+/// let bundled = bundle_css(dir.path(), &["reset.css", "*.css"], "bundle.css").unwrap();
+///
+/// assert_eq!(bundled.contents, b"* { margin: 0; }\np { color: red; }\n");
+/// ```
+pub fn bundle_css(input_dir: &Path, patterns: &[&str], filename: &str) -> Result<File, LibError> {
+    let mut paths = Vec::new();
+    for_each_file(input_dir, &mut |path| {
+        paths.push(path.to_path_buf());
+        Ok(())
+    })?;
+    paths.sort();
+
+    let mut included = HashSet::new();
+    let mut contents = Vec::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| LibError::ParsingError(err.to_string()))?
+            .compile_matcher();
+
+        for path in &paths {
+            let relative = path.strip_prefix(input_dir).unwrap_or(path);
+            if glob.is_match(relative) && included.insert(path) {
+                contents.extend(fs::read(path)?);
+                if contents.last() != Some(&b'\n') {
+                    contents.push(b'\n');
+                }
+            }
+        }
+    }
+
+    Ok(File {
+        filename: filename.to_string(),
+        file_type: FileType::CSS,
+        contents,
+        hash: None
+    })
+}
+
+/// Minifies a [`File`] with the minifier for its type.
+///
+/// Unlike the processing pipeline, which passes files without a minifier through unchanged,
+/// this reports them, so callers can tell a missing minifier from a failed minification.
+///
+/// # Parameters
+///
+/// - `f`: The [`File`] to minify.
+///
+/// # Returns
+///
+/// [`Ok`] containing the minified [`File`], [`StaticPreprocessingError::UnsupportedOperation`]
+/// if there is no minifier for its type, or the error of the minifier.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{minify, File, FileType, StaticPreprocessingError};
+/// #
+/// let css = File { filename: "main.css".into(), file_type: FileType::CSS, contents: b"body { margin: 0; }".to_vec(), hash: None };
+/// assert_eq!(minify(css).unwrap().contents, b"body{margin:0}");
+///
+/// let js = File { filename: "app.js".into(), file_type: FileType::JS, contents: b"let a = 1;".to_vec(), hash: None };
+/// assert!(matches!(minify(js), Err(StaticPreprocessingError::UnsupportedOperation { file_type: FileType::JS, .. })));
+/// ```
+pub fn minify(f: File) -> Result<File, LibError> {
+    match f.file_type {
+        FileType::CSS => minify_css(f, &ProcessOptions::default()),
+        file_type => Err(LibError::UnsupportedOperation {
+            file_type,
+            detail: "there is no minifier for this type".to_string()
+        })
+    }
+}
+
+fn minify_css(f: File, options: &ProcessOptions) ->  Result<File, LibError> {
+    if f.file_type != FileType::CSS || is_minified(&f.filename) {
+        return Ok(f);
+    }
+
+    let minified = match &options.cache_dir {
+        Some(cache_dir) => minify_css_cached(&f.contents, cache_dir, options),
+        None => minify_css_contents(&f.contents, options)
+    };
+    match minified {
+        Ok(minified_contents) => Ok(File {
+            contents: minified_contents,
+            ..f
+        }),
+        Err(err) if options.lenient_css => {
+            options.warn(Warning::UnminifiedStylesheet { filename: f.filename.clone(), error: err.to_string() });
+            Ok(f)
+        },
+        Err(err) => Err(err)
+    }
+}
+
+/// Parses and minifies the contents of a stylesheet.
+fn minify_css_contents(contents: &[u8], options: &ProcessOptions) -> Result<Vec<u8>, LibError> {
+    let contents = std::str::from_utf8(contents)
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let mut ss = StyleSheet::parse(contents, ParserOptions::default())
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    // lightningcss only keeps the license comments at the very top of the stylesheet
+    ss.license_comments = if options.preserve_license_comments {
+        license_comments(contents).into_iter().map(Into::into).collect()
+    } else {
+        Vec::new()
+    };
+
+    if options.remove_unused_custom_properties {
+        remove_unused_custom_properties(&mut ss);
+    }
+
+    let targets = options.css_color_targets.targets();
+    ss.minify(MinifyOptions { targets, ..MinifyOptions::default() })
+        .map_err(|err| LibError::MinificationError(err.to_string()))?;
+
+    let pseudo_classes = options.css_pseudo_classes.as_ref().map(PseudoClassReplacements::printer_options);
+    Ok(ss.to_css(PrinterOptions { minify: true, pseudo_classes, targets, ..PrinterOptions::default() })
+        .map_err(|err| LibError::MinificationError(err.to_string()))?
+        .code
+        .into_bytes())
+}
+
+/// Returns the bodies (everything between `/*` and `*/`) of all `/*! ... */` license comments in a stylesheet.
+fn license_comments(css: &str) -> Vec<&str> {
+    let mut comments = Vec::new();
+    let mut rest = css;
+    while let Some(start) = rest.find(['/', '"', '\'']) {
+        let tail = &rest[start..];
+        if let Some(quote) = tail.chars().next().filter(|c| *c != '/') {
+            // Skip over the string, including escaped quotes
+            let mut escaped = false;
+            let end = tail[1..]
+                .find(|c| {
+                    let closes = c == quote && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map_or(tail.len(), |end| end + 2);
+            rest = &tail[end..];
+        } else if let Some(comment) = tail.strip_prefix("/*") {
+            let end = comment.find("*/").unwrap_or(comment.len());
+            if comment.starts_with('!') {
+                comments.push(&comment[..end]);
+            }
+            rest = comment.get(end + 2..).unwrap_or_default();
+        } else {
+            rest = &tail[1..];
+        }
+    }
+    comments
+}
+
+/// Removes the custom properties declared in top-level `:root` rules that no `var()` in the stylesheet references.
+fn remove_unused_custom_properties(ss: &mut StyleSheet) {
+    let mut references = VariableReferences(HashSet::new());
+    let Ok(()) = ss.visit(&mut references);
+    let referenced = references.0;
+
+    let unused = |property: &Property| matches!(
+        property,
+        Property::Custom(custom) if matches!(&custom.name, CustomPropertyName::Custom(name) if !referenced.contains(name.0.as_ref()))
+    );
+
+    for rule in &mut ss.rules.0 {
+        if let CssRule::Style(style) = rule
+            && style.selectors.to_css_string(PrinterOptions::default()).is_ok_and(|selectors| selectors == ":root")
+        {
+            style.declarations.declarations.retain(|property| !unused(property));
+            style.declarations.important_declarations.retain(|property| !unused(property));
+        }
+    }
+}
+
+/// Collects the names of all custom properties referenced through `var()`.
+struct VariableReferences(HashSet<String>);
+
+impl<'i> Visitor<'i> for VariableReferences {
+    type Error = std::convert::Infallible;
+
+    fn visit_types(&self) -> VisitTypes {
+        visit_types!(VARIABLES)
+    }
+
+    fn visit_variable(&mut self, var: &mut Variable<'i>) -> Result<(), Self::Error> {
+        self.0.insert(var.name.ident.0.to_string());
+        var.visit_children(self)
+    }
+}
+
+/// Minifies the contents of a stylesheet through the cache in `cache_dir`, keyed by the hash of the contents and the CSS options.
+///
+/// Only successful minifications are cached, so a stylesheet passed through by `lenient_css`
+/// is reported again on every run.
+fn minify_css_cached(contents: &[u8], cache_dir: &Path, options: &ProcessOptions) -> Result<Vec<u8>, LibError> {
+    let mut key = blake3::Hasher::new();
+    key.update(contents);
+    key.update(&[
+        options.preserve_license_comments as u8,
+        options.remove_unused_custom_properties as u8,
+        options.css_color_targets as u8
+    ]);
+    if let Some(pseudo_classes) = &options.css_pseudo_classes {
+        key.update(format!("{:?}", pseudo_classes).as_bytes());
+    }
+    let cache_path = cache_dir.join(format!("{}.css", key.finalize()));
+    if let Ok(cached) = fs::read(&cache_path) {
+        return Ok(cached);
+    }
+
+    let minified = minify_css_contents(contents, options)?;
+
+    // Write through a temporary file so concurrent runs never read a partial entry
+    fs::create_dir_all(cache_dir)?;
+    let temp_path = cache_path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temp_path, &minified)?;
+    fs::rename(temp_path, cache_path)?;
+
+    Ok(minified)
+}
+
+/// Removes every `@charset` rule of a stylesheet, adding a single `@charset "UTF-8";` at the top if it holds non-ASCII text.
+fn normalize_charset(f: File) -> File {
+    if f.file_type != FileType::CSS {
+        return f;
+    }
+    let Ok(css) = std::str::from_utf8(&f.contents) else { return f };
+
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find(['@', '"', '\'', '/']) {
+        let (head, tail) = rest.split_at(start);
+        stripped.push_str(head);
+        let end = if tail.starts_with("/*") {
+            tail.find("*/").map_or(tail.len(), |end| end + 2)
+        } else if let Some(quote) = tail.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            // Skip over the string, including escaped quotes
+            let mut escaped = false;
+            tail[1..]
+                .find(|c| {
+                    let closes = c == quote && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map_or(tail.len(), |end| end + 2)
+        } else if tail.starts_with("@charset") {
+            rest = tail[tail.find(';').map_or(tail.len(), |end| end + 1)..].trim_start();
+            continue;
+        } else {
+            1
+        };
+        stripped.push_str(&tail[..end]);
+        rest = &tail[end..];
+    }
+    stripped.push_str(rest);
+
+    if !stripped.is_ascii() {
+        stripped.insert_str(0, "@charset \"UTF-8\";");
+    }
+    File { contents: stripped.into_bytes(), ..f }
+}
+
+/// Ensures exactly one trailing newline (`Some(true)`) or none (`Some(false)`) at the end of a text [`File`].
+fn normalize_trailing_newline(mut f: File, trailing_newline: Option<bool>) -> File {
+    let Some(trailing_newline) = trailing_newline else { return f };
+    if !f.file_type.is_text() {
+        return f;
+    }
+
+    while f.contents.last().is_some_and(|byte| *byte == b'\n' || *byte == b'\r') {
+        f.contents.pop();
+    }
+    if trailing_newline {
+        f.contents.push(b'\n');
+    }
+    f
+}
+
+/// Returns whether a filename marks its contents as already minified, e.g. `app.min.css`.
+fn is_minified(filename: &str) -> bool {
+    Path::new(filename)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with(".min"))
+}
+
+/// Renders a Markdown [`File`] to HTML.
+///
+/// The rendered file keeps its stem but gets the `.html` extension and [`FileType::HTML`],
+/// so it flows into any HTML processing that follows. Files of any other type are returned
+/// unchanged.
+///
+/// # Parameters
+///
+/// - `f`: The [`File`] to render.
+///
+/// # Returns
+///
+/// [`Ok`] containing the rendered [`File`], or a [`StaticPreprocessingError::ParsingError`] if
+/// the contents are not valid UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{File, FileType, render_markdown};
+/// #
+/// let file = File {
+///     filename: "post.md".into(),
+///     file_type: FileType::Markdown,
+///     contents: b"# Hello".to_vec(),
+///     hash: None,
+/// };
+///
+/// let rendered = render_markdown(file).unwrap();
+/// assert_eq!(rendered.filename, "post.html");
+/// assert_eq!(rendered.file_type, FileType::HTML);
+/// assert_eq!(rendered.contents, b"<h1>Hello</h1>\n");
+/// ```
+pub fn render_markdown(f: File) -> Result<File, LibError> {
+    if f.file_type != FileType::Markdown {
+        return Ok(f);
+    }
+
+    let contents = std::str::from_utf8(&f.contents)
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(contents));
+
+    Ok(File {
+        filename: Path::new(&f.filename).with_extension("html").to_string_lossy().to_string(),
+        file_type: FileType::HTML,
+        contents: html.into_bytes(),
+        ..f
+    })
+}
+
+/// Compiles a Sass stylesheet ([`FileType::Scss`]) to CSS.
+///
+/// Both the SCSS and the indented syntax are supported, picked by the `.scss` or `.sass`
+/// extension. The compiled file keeps its stem but gets the `.css` extension and
+/// [`FileType::CSS`], so it flows into CSS minification. Files of any other type are returned
+/// unchanged.
+///
+/// # Parameters
+///
+/// - `f`: The [`File`] to compile.
+/// - `import_dir`: The directory `@use` and `@import` rules are resolved against, usually the
+///   one containing the stylesheet.
+///
+/// # Returns
+///
+/// [`Ok`] containing the compiled [`File`], or a [`StaticPreprocessingError::ParsingError`]
+/// describing the error and its location if compilation fails.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{compile_scss, File, FileType};
+/// #
+/// let file = File {
+///     filename: "main.scss".into(),
+///     file_type: FileType::Scss,
+///     contents: b"$gap: 4px; ul { li { margin: $gap; } }".to_vec(),
+///     hash: None,
+/// };
+///
+/// let compiled = compile_scss(file, None).unwrap();
+/// assert_eq!(compiled.filename, "main.css");
+/// assert_eq!(compiled.file_type, FileType::CSS);
+/// assert!(String::from_utf8(compiled.contents).unwrap().contains("ul li {"));
+/// ```
+pub fn compile_scss(f: File, import_dir: Option<&Path>) -> Result<File, LibError> {
+    if f.file_type != FileType::Scss {
+        return Ok(f);
+    }
+
+    let contents = std::str::from_utf8(&f.contents)
+        .map_err(|err| LibError::ParsingError(err.to_string()))?;
+
+    let filename = Path::new(&f.filename);
+    let syntax = if filename.extension().is_some_and(|ext| ext == "sass") { grass::InputSyntax::Sass } else { grass::InputSyntax::Scss };
+    let mut grass_options = grass::Options::default().input_syntax(syntax);
+    if let Some(import_dir) = import_dir {
+        grass_options = grass_options.load_path(import_dir);
+    }
+
+    let css = grass::from_string(contents, &grass_options)
+        .map_err(|err| LibError::ParsingError(format!("{}: {}", f.filename, err)))?;
+
+    Ok(File {
+        filename: filename.with_extension("css").to_string_lossy().to_string(),
+        file_type: FileType::CSS,
+        contents: css.into_bytes(),
+        ..f
+    })
+}
+
+/// Bakes the EXIF orientation of an image ([`FileType::Image`]) into its pixels.
+///
+/// Images whose orientation is set to anything but the default are rotated and flipped
+/// accordingly, then re-encoded in their original format without EXIF metadata, so browsers
+/// that ignore the tag display them the same as those that honor it. JPEGs are re-encoded at
+/// quality 90. Images without an orientation, in formats that can't be decoded, and files of
+/// any other type are returned unchanged.
+///
+/// # Parameters
+///
+/// - `f`: The [`File`] to normalize.
+///
+/// # Returns
+///
+/// [`Ok`] containing the normalized [`File`], or a
+/// [`StaticPreprocessingError::ImageProcessingError`] if the image is corrupt.
+///
+/// # Examples
+///
+/// ```
+/// # use std::io::Cursor;
+/// # use image::{codecs::png::PngEncoder, ImageEncoder, ExtendedColorType, GenericImageView};
+/// # use static_preprocessing::{normalize_orientation, File, FileType};
+/// #
+/// // A 2x1 PNG tagged to be rotated a quarter turn clockwise
+/// let mut png = Vec::new();
+/// let mut encoder = PngEncoder::new(Cursor::new(&mut png));
+/// encoder.set_exif_metadata(b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0".to_vec()).unwrap();
+/// encoder.write_image(&[0, 0, 0, 255, 255, 255], 2, 1, ExtendedColorType::Rgb8).unwrap();
+///
+/// let file = File { filename: "photo.png".into(), file_type: FileType::Image, contents: png, hash: None };
+/// let normalized = normalize_orientation(file).unwrap();
+///
+/// assert_eq!(image::load_from_memory(&normalized.contents).unwrap().dimensions(), (1, 2));
+/// ```
+pub fn normalize_orientation(f: File) -> Result<File, LibError> {
+    if f.file_type != FileType::Image {
+        return Ok(f);
+    }
+
+    let reader = image::ImageReader::new(io::Cursor::new(&f.contents))
+        .with_guessed_format()
+        .map_err(|err| LibError::ImageProcessingError(err.to_string()))?;
+    let Some(format) = reader.format() else { return Ok(f) };
+    if !matches!(format, image::ImageFormat::Jpeg | image::ImageFormat::Png | image::ImageFormat::WebP) {
+        return Ok(f);
+    }
+
+    let decoded = (|| {
+        let mut decoder = reader.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        if orientation == image::metadata::Orientation::NoTransforms {
+            return Ok(None);
+        }
+        Ok(Some((image::DynamicImage::from_decoder(decoder)?, orientation)))
+    })().map_err(|err: image::ImageError| LibError::ImageProcessingError(err.to_string()))?;
+    let Some((mut oriented, orientation)) = decoded else { return Ok(f) };
+    oriented.apply_orientation(orientation);
+
+    let mut contents = Vec::new();
+    let encoded = match format {
+        image::ImageFormat::Jpeg => oriented.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut contents, 90)),
+        format => oriented.write_to(&mut io::Cursor::new(&mut contents), format)
+    };
+    encoded.map_err(|err| LibError::ImageProcessingError(err.to_string()))?;
+
+    Ok(File { contents, ..f })
+}
+
+/// Converts a written image to each variant format next to it, returning the extensions it's available in, or `None` if it can't be decoded.
+fn write_image_variants(output_path: &Path, options: &ProcessOptions, outputs: &OutputPaths) -> Result<Option<Vec<String>>, LibError> {
+    let Ok(image) = image::open(output_path) else { return Ok(None) };
+    let ext = output_path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    let own = if ext == "jpeg" { "jpg" } else { ext.as_str() };
+    let transparent = image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255);
+
+    let mut formats = vec![ext.clone()];
+    for &requested in &options.image_variants {
+        let variant = match requested {
+            ImageVariant::Jpeg if transparent => match options.image_transparency {
+                TransparencyPolicy::WebP => {
+                    options.warn(Warning::TransparencyFallback {
+                        filename: output_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                        format: "JPEG".to_string()
+                    });
+                    ImageVariant::WebP
+                },
+                TransparencyPolicy::Error => return Err(LibError::TransparencyLoss {
+                    path: output_path.to_path_buf(),
+                    format: "JPEG".to_string()
+                }),
+                TransparencyPolicy::Discard => requested
+            },
+            _ => requested
+        };
+        if variant.extension() == own || formats.iter().any(|format| format == variant.extension()) {
+            continue;
+        }
+        let mut contents = Vec::new();
+        let encoded = match variant {
+            ImageVariant::Jpeg => image::DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut contents, 90)),
+            ImageVariant::Png => image.write_to(&mut io::Cursor::new(&mut contents), image::ImageFormat::Png),
+            ImageVariant::WebP => image::DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_to(&mut io::Cursor::new(&mut contents), image::ImageFormat::WebP)
+        };
+        encoded.map_err(|err| LibError::ImageProcessingError(err.to_string()))?;
+
+        let variant_path = output_path.with_extension(variant.extension());
+        outputs.claim(&variant_path, &contents)?;
+        if !is_unchanged(&variant_path, &contents) {
+            fs::write(&variant_path, &contents)?;
+        }
+        outputs.wrote(&variant_path, FileType::Image, options)?;
+        formats.push(variant.extension().to_string());
+    }
+    Ok(Some(formats))
+}
+
+/// Splits a written stylesheet or script into numbered chunks next to it, returning their filenames, or `None` if it doesn't split.
+fn write_chunks(
+    output_path: &Path,
+    file_type: FileType,
+    chunk_size: usize,
+    options: &ProcessOptions,
+    outputs: &OutputPaths,
+) -> Result<Option<Vec<String>>, LibError> {
+    let contents = fs::read(output_path)?;
+    let Ok(source) = std::str::from_utf8(&contents) else { return Ok(None) };
+    let chunks = split_chunks(source, file_type, chunk_size);
+    if chunks.len() < 2 {
+        return Ok(None);
+    }
+
+    let stem = output_path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = output_path.extension().unwrap_or_default().to_string_lossy();
+    let mut filenames = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let filename = format!("{}.{}.{}", stem, index, ext);
+        let chunk_path = output_path.with_file_name(&filename);
+        outputs.claim(&chunk_path, chunk.as_bytes())?;
+        if !is_unchanged(&chunk_path, chunk.as_bytes()) {
+            fs::write(&chunk_path, chunk)?;
+        }
+        outputs.wrote(&chunk_path, file_type, options)?;
+        filenames.push(filename);
+    }
+    Ok(Some(filenames))
+}
+
+/// Splits CSS between top-level rules, or JavaScript between top-level statements, into chunks of at least `chunk_size` bytes, except for the last.
+///
+/// JavaScript is only split after a top-level `;`, or after a top-level `}` ending its line
+/// that isn't followed by `else`, `catch`, `finally` or `while`, so a block is never
+/// separated from the clause continuing it.
+/// Strings and comments are skipped, but JavaScript regular expression literals and template
+/// literal substitutions aren't understood, so unusual code may be split in the wrong place.
+fn split_chunks(source: &str, file_type: FileType, chunk_size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let (mut start, mut depth) = (0, 0usize);
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' | '\'' | '`' if c != '`' || file_type == FileType::JS => {
+                // Skip over the string, including escaped quotes
+                let mut escaped = false;
+                for (_, inner) in chars.by_ref() {
+                    if inner == c && !escaped {
+                        break;
+                    }
+                    escaped = inner == '\\' && !escaped;
+                }
+            },
+            '/' if chars.peek().is_some_and(|(_, next)| *next == '*') => {
+                chars.next();
+                let mut previous = ' ';
+                for (_, inner) in chars.by_ref() {
+                    if previous == '*' && inner == '/' {
+                        break;
+                    }
+                    previous = inner;
+                }
+            },
+            '/' if file_type == FileType::JS && chars.peek().is_some_and(|(_, next)| *next == '/') => {
+                for (_, inner) in chars.by_ref() {
+                    if inner == '\n' {
+                        break;
+                    }
+                }
+            },
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        let boundary = depth == 0 && match (c, file_type) {
+            (';', _) => true,
+            ('}', FileType::JS) => ends_statement(&source[index + 1..]),
+            ('}', _) => true,
+            _ => false
+        };
+        if boundary && index + 1 - start >= chunk_size {
+            chunks.push(&source[start..index + 1]);
+            start = index + 1;
+        }
+    }
+    // Trailing whitespace stays with the last chunk
+    if source[start..].trim().is_empty() && let Some(last) = chunks.pop() {
+        start -= last.len();
+    }
+    chunks.push(&source[start..]);
+    chunks
+}
+
+/// Returns whether a top-level JavaScript block closed just before `rest` ends its statement: the
+/// brace must end its line, and the next line must not continue the statement with `else`,
+/// `catch`, `finally` or a `do` loop's `while`.
+fn ends_statement(rest: &str) -> bool {
+    if !rest.trim_start_matches([' ', '\t', '\r']).starts_with('\n') {
+        return false;
+    }
+    let next = rest.trim_start();
+    !["else", "catch", "finally", "while"].iter().any(|keyword| {
+        next.strip_prefix(keyword)
+            .is_some_and(|after| !after.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$'))
+    })
+}
+
+/// Returns whether the walker should pick up `path`: it must not be a manifest or a Sass partial
+/// (`_name.scss`), which is only compiled into the stylesheets using it, and must pass the extension filter.
+fn is_input(input_dir: &Path, path: &Path, options: &ProcessOptions) -> bool {
+    let allowed = match &options.only_extensions {
+        Some(extensions) => path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext))),
+        None => true
+    };
+    let is_partial = path.extension().and_then(|ext| ext.to_str()).map(detect_file_type) == Some(FileType::Scss)
+        && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('_'));
+    allowed && !is_partial && !is_manifest(input_dir, path, options)
+}
+
+/// Returns whether `path` is a manifest at the root of `input_dir`, which happens when a previous output is fed back as input.
+fn is_manifest(input_dir: &Path, path: &Path, options: &ProcessOptions) -> bool {
+    path.parent() == Some(input_dir)
+        && path.file_name().is_some_and(|name| {
+            name == DEFAULT_MANIFEST_FILENAME
+                || *name == *options.manifest_filename
+                || (options.manifest_js_global.is_some() && *name == *manifest_js_filename(options))
+                || options.preload.as_ref().is_some_and(|preload| *name == *preload.filename)
+                || options.import_map.as_ref().is_some_and(|import_map| *name == *import_map.filename)
+                || options.nginx_map.as_ref().is_some_and(|nginx_map| *name == *nginx_map.filename)
+                || options.file_listing.as_ref().is_some_and(|listing| *name == **listing)
+                || options.reverse_manifest.as_ref().is_some_and(|reverse| *name == **reverse)
+                || (options.split_manifest_by_type && SUPPORTED_EXTENSIONS
+                    .iter()
+                    .map(|(_, file_type)| *file_type)
+                    .chain([FileType::Other])
+                    .any(|file_type| *name == *type_manifest_filename(options, file_type)))
+        })
+}
+
+/// Returns the filename of the `manifest.js`, which is named after the manifest.
+fn manifest_js_filename(options: &ProcessOptions) -> String {
+    Path::new(&options.manifest_filename).with_extension("js").to_string_lossy().to_string()
+}
+
+/// Returns the filename of the manifest holding only the entries of one type, e.g. `manifest.css.json`.
+fn type_manifest_filename(options: &ProcessOptions, file_type: FileType) -> String {
+    let manifest_filename = Path::new(&options.manifest_filename);
+    let ext = manifest_filename.extension().map_or("json".into(), |ext| ext.to_string_lossy());
+    manifest_filename.with_extension(format!("{}.{}", file_type.name(), ext)).to_string_lossy().to_string()
+}
+
+/// Writes the manifest files enabled in the options to the output directory, failing on an empty manifest if configured to.
+fn emit_manifests(
+    output_dir: &Path,
+    options: &ProcessOptions,
+    manifest: &HashMap<String, ManifestEntry>,
+    outputs: &OutputPaths,
+) -> Result<(), LibError> {
+    if options.error_on_empty && manifest.is_empty() {
+        return Err(LibError::EmptyManifest);
+    }
+    if options.write_manifest {
+        write_manifest(output_dir, options, manifest)?;
+        outputs.record(&output_dir.join(&options.manifest_filename));
+    }
+    if options.split_manifest_by_type {
+        let mut by_type: HashMap<FileType, HashMap<String, ManifestEntry>> = HashMap::new();
+        for (original, entry) in manifest {
+            let file_type = Path::new(&entry.hashed).extension().and_then(|ext| ext.to_str()).map_or(FileType::Other, detect_file_type);
+            by_type.entry(file_type).or_default().insert(original.clone(), entry.clone());
+        }
+        for (file_type, entries) in by_type {
+            let path = output_dir.join(type_manifest_filename(options, file_type));
+            write_manifest_file(&path, options, &entries)?;
+            outputs.record(&path);
+        }
+    }
+    if let Some(global) = &options.manifest_js_global {
+        write_manifest_js(output_dir, global, options, manifest)?;
+        outputs.record(&output_dir.join(manifest_js_filename(options)));
+    }
+    if let Some(preload) = &options.preload {
+        fs::write(output_dir.join(&preload.filename), preload::render_hints(manifest, preload))?;
+        outputs.record(&output_dir.join(&preload.filename));
+    }
+    if let Some(import_map) = &options.import_map {
+        let json = importmap::render_import_map(manifest, import_map, |warning| options.warn(warning))
+            .map_err(io::Error::other)?;
+        fs::write(output_dir.join(&import_map.filename), json)?;
+        outputs.record(&output_dir.join(&import_map.filename));
+    }
+    if let Some(nginx_map) = &options.nginx_map {
+        fs::write(output_dir.join(&nginx_map.filename), nginx::render_map(manifest, nginx_map))?;
+        outputs.record(&output_dir.join(&nginx_map.filename));
+    }
+    if let Some(reverse) = &options.reverse_manifest {
+        fs::write(output_dir.join(reverse), manifest::to_reverse_json(manifest).map_err(io::Error::other)?)?;
+        outputs.record(&output_dir.join(reverse));
+    }
+    if let Some(listing) = &options.file_listing {
+        write_file_listing(output_dir, listing, outputs)?;
+    }
+    Ok(())
+}
+
+/// Writes the paths of the files written in this run to `listing`, sorted, as lines or as a JSON
+/// array. Paths within the output directory are relative to it, and others are kept whole.
+fn write_file_listing(output_dir: &Path, listing: &str, outputs: &OutputPaths) -> Result<(), LibError> {
+    let listing_path = output_dir.join(listing);
+    let mut files: Vec<String> = outputs.files.lock().unwrap()
+        .iter()
+        .filter(|path| **path != listing_path)
+        .map(|path| match path.strip_prefix(output_dir) {
+            Ok(relative) => relative.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+            Err(_) => path.to_string_lossy().to_string()
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let contents = if listing.ends_with(".json") {
+        serde_json::to_string_pretty(&files).map_err(io::Error::other)?
+    } else {
+        files.iter().map(|file| format!("{}\n", file)).collect()
+    };
+    fs::write(listing_path, contents).map_err(LibError::IOError)
+}
+
+/// Writes the manifest file to the output directory as pretty-printed JSON.
+fn write_manifest(output_dir: &Path, options: &ProcessOptions, manifest: &HashMap<String, ManifestEntry>) -> Result<(), LibError> {
+    write_manifest_file(&output_dir.join(&options.manifest_filename), options, manifest)
+}
+
+/// Writes manifest entries to a file as pretty-printed JSON.
+fn write_manifest_file(manifest_path: &Path, options: &ProcessOptions, manifest: &HashMap<String, ManifestEntry>) -> Result<(), LibError> {
+    let meta = options.manifest_build_id.as_deref().map(|build_id| if options.reproducible {
+        manifest::BuildMetadata::reproducible(build_id)
+    } else {
+        manifest::BuildMetadata::new(build_id)
+    });
+    let json = manifest::to_json(manifest, options.detailed_manifest(manifest), meta.as_ref(), true)
+        .map_err(io::Error::other)?;
+    fs::write(manifest_path, json).map_err(LibError::IOError)
+}
+
+/// Writes the manifest as a script assigning it, as minified JSON, to a global on `window`.
+fn write_manifest_js(output_dir: &Path, global: &str, options: &ProcessOptions, manifest: &HashMap<String, ManifestEntry>) -> Result<(), LibError> {
+    let json = manifest::to_json(manifest, options.detailed_manifest(manifest), None, false)
+        .map_err(io::Error::other)?;
+    fs::write(output_dir.join(manifest_js_filename(options)), format!("window.{}={};", global, json)).map_err(LibError::IOError)
+}
+
+/// Recursively traverses a directory tree, applying a function to each file found.
+///
+/// This function walks through the directory at `path` and all its subdirectories,
+/// calling the provided closure `f` on every file encountered. If `path` itself is a
+/// file, the closure is applied directly. Special files, like named pipes, sockets and
+/// devices, are skipped, since reading them could block forever.
+///
+/// # Parameters
+///
+/// - `path`: The starting directory or file path to traverse.
+/// - `f`: A mutable closure that takes a reference to a [`Path`] and returns an [`io::Result<()>`].
+///
+/// # Returns
+///
+/// [`Ok(())`] if all files were processed successfully, or the first [`io::Error`] encountered.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs::{self, File as FsFile};
+/// # use std::io::Write;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::for_each_file;
+/// #
+/// let temp = tempdir().unwrap();
+/// let file_path = temp.path().join("foo.txt");
+/// let mut file = FsFile::create(&file_path).unwrap();
+/// writeln!(file, "Hello").unwrap();
+///
+/// let mut count = 0;
+/// for_each_file(temp.path(), &mut |path| {
+///     if path.is_file() {
+///         count += 1;
+///     }
+///     Ok(())
+/// }).unwrap();
+///
+/// assert_eq!(count, 1);
+/// ```
+pub fn for_each_file<F: FnMut(&Path) -> Result<(), LibError>>(path: &Path, f: &mut F) -> Result<(), LibError> {
+    walk_files(path, false, f)
+}
+
+/// Walks a directory tree like [`for_each_file`], visiting the entries of each directory sorted by name if `sorted` is set.
+fn walk_files<F: FnMut(&Path) -> Result<(), LibError>>(path: &Path, sorted: bool, f: &mut F) -> Result<(), LibError> {
+    // An explicit queue instead of recursion, so deep trees can't overflow the stack
+    let mut pending = VecDeque::from([path.to_path_buf()]);
+    while let Some(path) = pending.pop_front() {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            if sorted {
+                entries.sort();
+            }
+            // Visit a directory's contents before its siblings, in the order they were read or sorted
+            for entry in entries.into_iter().rev() {
+                pending.push_front(entry);
+            }
+        } else if fs::metadata(&path).is_ok_and(|metadata| !metadata.is_file()) {
+            continue;
+        } else {
+            // Unreadable entries, like broken symlinks, are left for `f` to report
+            f(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Determines the [`FileType`] based on the file extension.
+///
+/// This function maps common file extensions to specific [`FileType`] variants.
+/// It recognizes `"css"`, `"js"`, `"html"`, `"md"`, `"json"`, `"svg"`, `"wasm"`, common font formats like `"woff2"`,
+/// common image formats like `"webp"`, `"jpg"`, `"jpeg"`, `"png"`, and `"avif"`, the video formats
+/// `"mp4"`, `"webm"` and `"ogg"`, and the Sass formats `"scss"` and `"sass"`.
+/// All other extensions are classified as [`FileType::Other`].
+///
+/// # Parameters
+///
+/// - `ext`: A string slice representing the file extension (without the dot).
+///
+/// # Returns
+///
+/// A corresponding variant of [`FileType`] based on the extension.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::detect_file_type;
+/// # use static_preprocessing::FileType;
+/// assert!(matches!(detect_file_type("css"), FileType::CSS));
+/// assert!(matches!(detect_file_type("png"), FileType::Image));
+/// assert!(matches!(detect_file_type("txt"), FileType::Other));
+/// ```
+pub fn detect_file_type(ext: &str) -> FileType {
+    SUPPORTED_EXTENSIONS
+        .iter()
+        .find(|(supported, _)| *supported == ext)
+        .map_or(FileType::Other, |(_, file_type)| *file_type)
+}
+
+/// Returns the MIME type to serve files with the given extension with.
+///
+/// Besides the extensions recognized by [`detect_file_type`], this knows a few other common
+/// web formats. All other extensions are served as `application/octet-stream`.
+///
+/// # Parameters
+///
+/// - `ext`: The file extension, without the dot.
+///
+/// # Returns
+///
+/// The MIME type, with a UTF-8 charset for text formats.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::mime_type;
+/// assert_eq!(mime_type("css"), "text/css; charset=utf-8");
+/// assert_eq!(mime_type("PNG"), "image/png");
+/// assert_eq!(mime_type("xyz"), "application/octet-stream");
+/// ```
+pub fn mime_type(ext: &str) -> &'static str {
+    match ext.to_ascii_lowercase().as_str() {
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "json" | "map" => "application/json",
+        "svg" => "image/svg+xml",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "webp" => "image/webp",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "ogg" => "video/ogg",
+        "pdf" => "application/pdf",
+        "xml" => "application/xml",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Returns whether the extension of `path` has a textual MIME type, e.g. `.txt` or `.xml`.
+fn is_text_extension(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let mime = mime_type(ext);
+    mime.starts_with("text/") || mime == "application/json" || mime == "application/xml"
+}
+
+/// The extensions recognized by [`detect_file_type`], with their types.
+const SUPPORTED_EXTENSIONS: &[(&str, FileType)] = &[
+    ("css", FileType::CSS),
+    ("js", FileType::JS),
+    ("html", FileType::HTML),
+    ("htm", FileType::HTML),
+    ("md", FileType::Markdown),
+    ("markdown", FileType::Markdown),
+    ("json", FileType::JSON),
+    ("svg", FileType::SVG),
+    ("woff", FileType::Font),
+    ("woff2", FileType::Font),
+    ("ttf", FileType::Font),
+    ("otf", FileType::Font),
+    ("wasm", FileType::Wasm),
+    ("webp", FileType::Image),
+    ("jpg", FileType::Image),
+    ("jpeg", FileType::Image),
+    ("png", FileType::Image),
+    ("avif", FileType::Image),
+    ("mp4", FileType::Video),
+    ("webm", FileType::Video),
+    ("ogg", FileType::Video),
+    ("scss", FileType::Scss),
+    ("sass", FileType::Scss)
+];
+
+/// Describes a file extension the pipeline recognizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeInfo {
+    /// The extension, without the dot.
+    pub extension: &'static str,
+    /// The [`FileType`] files with this extension get.
+    pub file_type: FileType,
+    /// Whether files of this type are transformed with the default options, rather than copied
+    /// as they are. Images, for one, are only transformed with [`ProcessOptions::normalize_orientation`]
+    /// or [`ProcessOptions::image_variants`].
+    pub transformed: bool
+}
+
+/// Lists every file extension the pipeline recognizes, e.g. for tooling that shouldn't hardcode them.
+///
+/// Extensions not listed are [`FileType::Other`]. Whether a type is transformed reflects the
+/// default options; some options, like a font subsetter or image variants, transform more types.
+///
+/// # Returns
+///
+/// A [`TypeInfo`] per extension, in a stable order.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{supported_types, FileType};
+/// let css = supported_types().into_iter().find(|info| info.extension == "css").unwrap();
+///
+/// assert_eq!(css.file_type, FileType::CSS);
+/// assert!(css.transformed);
+/// ```
+pub fn supported_types() -> Vec<TypeInfo> {
+    SUPPORTED_EXTENSIONS
+        .iter()
+        .map(|(extension, file_type)| TypeInfo {
+            extension,
+            file_type: *file_type,
+            transformed: !file_type.is_passthrough()
+        })
+        .collect()
+}
+
+/// Determines the [`FileType`] of binary contents from their magic bytes.
+///
+/// Only formats with a reliable signature are recognized: PNG, JPEG, GIF, WebP and AVIF images,
+/// WOFF, WOFF2, TrueType and OpenType fonts, MP4, WebM and Ogg videos, and WebAssembly modules. Text formats have no
+/// signature, so they are never detected.
+///
+/// # Parameters
+///
+/// - `contents`: The contents, or at least their first 12 bytes.
+///
+/// # Returns
+///
+/// The detected [`FileType`], or `None` if no known signature matches.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{sniff_file_type, FileType};
+/// assert_eq!(sniff_file_type(b"\x89PNG\r\n\x1a\n"), Some(FileType::Image));
+/// assert_eq!(sniff_file_type(b"body { margin: 0; }"), None);
+/// ```
+pub fn sniff_file_type(contents: &[u8]) -> Option<FileType> {
+    match contents {
+        [0x89, b'P', b'N', b'G', ..]
+        | [0xFF, 0xD8, 0xFF, ..]
+        | [b'G', b'I', b'F', b'8', ..]
+        | [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..]
+        | [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f', ..] => Some(FileType::Image),
+        [b'w', b'O', b'F', b'F', ..]
+        | [b'w', b'O', b'F', b'2', ..]
+        | [0x00, 0x01, 0x00, 0x00, ..]
+        | [b'O', b'T', b'T', b'O', ..] => Some(FileType::Font),
+        [0x00, b'a', b's', b'm', ..] => Some(FileType::Wasm),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..]
+        | [0x1A, 0x45, 0xDF, 0xA3, ..]
+        | [b'O', b'g', b'g', b'S', ..] => Some(FileType::Video),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// The log records emitted on the current thread.
+        static LOG_RECORDS: RefCell<Vec<log::Level>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Collects the records logged on the current thread, so parallel tests don't see each other's.
+    struct ThreadLogger;
+
+    /// Installs [`ThreadLogger`] for the whole test binary the first time it's called. The global
+    /// logger is only set up once, so tests never reconfigure it under each other.
+    fn capture_logs() {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&ThreadLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    impl log::Log for ThreadLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOG_RECORDS.with(|records| records.borrow_mut().push(record.level()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_detect_file_type() {
         assert!(detect_file_type("css") == FileType::CSS);
         assert!(detect_file_type("js") == FileType::JS);
+        assert!(detect_file_type("html") == FileType::HTML);
+        assert!(detect_file_type("md") == FileType::Markdown);
+        assert!(detect_file_type("markdown") == FileType::Markdown);
+        assert!(detect_file_type("json") == FileType::JSON);
+        assert!(detect_file_type("svg") == FileType::SVG);
+        assert!(detect_file_type("woff2") == FileType::Font);
+        assert!(detect_file_type("wasm") == FileType::Wasm);
         let img_types = Vec::from(["webp", "jpg", "jpeg", "png", "avif"]);
         for img_type in img_types {
             assert!(detect_file_type(img_type) == FileType::Image);
@@ -349,229 +3900,2837 @@ mod tests {
     }
 
     #[test]
-    fn test_detect_file_type_negative() {
-        let non_recognized_types = Vec::from(["gif", "tiff", "docx", "thing", "stl", "a", "file", "txt"]);
-        for non_type in non_recognized_types {
-            assert!(detect_file_type(non_type) == FileType::Other);
-        }
+    fn test_detect_file_type_video() {
+        for video_type in ["mp4", "webm", "ogg"] {
+            assert_eq!(detect_file_type(video_type), FileType::Video);
+        }
+
+        // Videos are already compressed, so they are skipped even when listed explicitly
+        let options = ProcessOptions {
+            compression: true,
+            compress_types: Some(HashSet::from([FileType::Video, FileType::CSS])),
+            ..ProcessOptions::default()
+        };
+        assert!(!options.compresses(FileType::Video, Path::new("movie.mp4")));
+        assert!(!ProcessOptions::default().compresses(FileType::Video, Path::new("movie.mp4")));
+    }
+
+    #[test]
+    fn test_process_directory_scss() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("_colors.scss"), "$accent: #ff0000;").unwrap();
+        fs::write(
+            input_dir.path().join("main.scss"),
+            "@use 'colors';\n$gap: 4px;\nnav {\n  a { color: colors.$accent; margin: $gap; }\n}\n"
+        ).unwrap();
+
+        let results = process_directory(input_dir.path(), output_dir.path()).unwrap();
+
+        // The partial is only compiled into the stylesheet using it
+        assert_eq!(results.len(), 1);
+        let output = results[0].output.as_ref().unwrap();
+        assert!(output.ends_with(".css"));
+        assert_eq!(results[0].file_type, FileType::CSS);
+        assert_eq!(fs::read_to_string(output_dir.path().join(output)).unwrap(), "nav a{color:red;margin:4px}");
+    }
+
+    #[test]
+    fn test_compile_scss_negative() {
+        let file = File {
+            filename: "broken.scss".into(),
+            file_type: FileType::Scss,
+            contents: b"a { color: $missing; }".to_vec(),
+            hash: None,
+        };
+
+        let err = compile_scss(file, None).unwrap_err();
+        assert!(matches!(&err, LibError::ParsingError(message) if message.contains("broken.scss") && message.contains("1:12")), "{err}");
+    }
+
+    #[test]
+    fn test_type_detector() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        /// Classifies `.data` files holding a JSON object as JSON, counting the files it was asked about.
+        #[derive(Debug, Default)]
+        struct JsonData(Mutex<Vec<PathBuf>>);
+
+        impl TypeDetector for JsonData {
+            fn detect(&self, path: &Path, head: &[u8]) -> Option<FileType> {
+                self.0.lock().unwrap().push(path.to_path_buf());
+                (path.extension()? == "data" && head.starts_with(b"{")).then_some(FileType::JSON)
+            }
+        }
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let json_path = input_dir.path().join("config.data");
+        let other_path = input_dir.path().join("blob.data");
+        fs::write(&json_path, r#"{ "a": 1 }"#).unwrap();
+        fs::write(&other_path, b"\x00\x01").unwrap();
+
+        let detector = Arc::new(JsonData::default());
+        let options = ProcessOptions {
+            type_detector: Some(detector.clone()),
+            rewrite_css_urls: true,
+            type_output_dirs: HashMap::from([(FileType::Image, output_dir.path().join("images"))]),
+            ..ProcessOptions::default()
+        };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // The detector defers on the binary file, which falls back to its extension
+        let file_type = |path: &Path| results.iter().find(|result| result.input == path).unwrap().file_type;
+        assert_eq!(file_type(&json_path), FileType::JSON);
+        assert_eq!(file_type(&other_path), FileType::Other);
+
+        // Each file is detected once, however many steps depend on its type
+        let mut asked = detector.0.lock().unwrap().clone();
+        asked.sort();
+        assert_eq!(asked, [other_path, json_path]);
+    }
+
+    #[test]
+    fn test_supported_types() {
+        let types = supported_types();
+        let info = |extension: &str| *types.iter().find(|info| info.extension == extension).unwrap();
+
+        assert_eq!(info("css"), TypeInfo { extension: "css", file_type: FileType::CSS, transformed: true });
+        assert!(info("scss").transformed);
+        // Images are only processed when options ask for it, so by default they're copied as they are
+        assert_eq!(info("png"), TypeInfo { extension: "png", file_type: FileType::Image, transformed: false });
+
+        // The list agrees with detection
+        assert!(types.iter().all(|info| detect_file_type(info.extension) == info.file_type));
+    }
+
+    #[test]
+    fn test_detect_file_type_negative() {
+        let non_recognized_types = Vec::from(["gif", "tiff", "docx", "thing", "stl", "a", "file", "txt"]);
+        for non_type in non_recognized_types {
+            assert!(detect_file_type(non_type) == FileType::Other);
+        }
+    }
+
+    #[test]
+    fn test_load_file() {
+        use std::fs::File as FsFile;
+        use std::io::Write;
+        use tempfile::tempdir;
+        
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.css");
+        let mut file = FsFile::create(&path).unwrap();
+        writeln!(file, "body {{ background: #fff; }}").unwrap();
+
+        let loaded = load_file(&path).unwrap();
+        assert_eq!(loaded.filename, path.file_name().unwrap().to_string_lossy());
+        assert!(loaded.file_type == FileType::CSS);
+        assert!(loaded.contents == b"body { background: #fff; }\n");
+    }
+
+    #[test]
+    fn test_load_file_negative() {
+        let res = load_file(Path::new("non/existant/thispathdefinatelyhouldnevereverexistanywhere/path.file"));
+        if res.is_ok() {
+            panic!("Invalid file loaded");
+        }
+    }
+
+    #[test]
+    fn test_save_file() {
+        use std::fs;
+        use tempfile::tempdir;
+        
+        let dir = tempdir().unwrap();
+        let file = File {
+            filename: "hello.txt".into(),
+            file_type: FileType::Other,
+            contents: b"Hello, world!".to_vec(),
+            hash: None,
+        };
+        save_file(dir.path(), &file).unwrap();
+        let written = fs::read_to_string(dir.path().join("hello.txt")).unwrap();
+        assert_eq!(written, "Hello, world!");
+    }
+
+    #[test]
+    fn test_save_file_unchanged() {
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        let file = |contents: &[u8]| File {
+            filename: "hello.txt".into(),
+            file_type: FileType::Other,
+            contents: contents.to_vec(),
+            hash: None,
+        };
+        let mtime = || FileTime::from_last_modification_time(&fs::metadata(&path).unwrap());
+
+        save_file(dir.path(), &file(b"Hello, world!")).unwrap();
+        let old = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&path, old).unwrap();
+
+        // Identical contents leave the file alone
+        save_file(dir.path(), &file(b"Hello, world!")).unwrap();
+        assert_eq!(mtime(), old);
+
+        // Different contents of the same size are still written
+        save_file(dir.path(), &file(b"Hello, there!")).unwrap();
+        assert_ne!(mtime(), old);
+        assert_eq!(fs::read(&path).unwrap(), b"Hello, there!");
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let output_dir = dir.path();
+
+        // Create a sample manifest
+        let mut manifest = HashMap::new();
+        manifest.insert(
+            "/input/example.css".to_string(),
+            ManifestEntry { hashed: "example-hashed.css".to_string(), ..ManifestEntry::default() },
+        );
+        manifest.insert(
+            "/input/script.js".to_string(),
+            ManifestEntry { hashed: "script-hashed.js".to_string(), ..ManifestEntry::default() },
+        );
+
+        // Write the manifest to the output directory
+        write_manifest(output_dir, &ProcessOptions::default(), &manifest).unwrap();
+
+        // Read the manifest file back
+        let manifest_path = output_dir.join("manifest.json");
+        let written_manifest = fs::read_to_string(manifest_path).unwrap();
+
+        // Verify the contents of the manifest file
+        let written_manifest: HashMap<String, String> = serde_json::from_str(&written_manifest).unwrap();
+        let expected_manifest = HashMap::from([
+            ("/input/example.css".to_string(), "example-hashed.css".to_string()),
+            ("/input/script.js".to_string(), "script-hashed.js".to_string()),
+        ]);
+        assert_eq!(written_manifest, expected_manifest);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_for_each_file_skips_special_files() {
+        use std::fs;
+        use std::process::Command;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("main.css"), "").unwrap();
+        let fifo = dir.path().join("pipe.css");
+        assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+        // Reading the pipe would block, as nothing ever writes to it
+        let output_dir = tempdir().unwrap();
+        let results = process_directory(dir.path(), output_dir.path()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].input.ends_with("main.css"));
+    }
+
+    #[test]
+    fn test_for_each_file() {
+        use std::fs::{self, File as FsFile};
+        use std::io::Write;
+        use tempfile::tempdir;
+
+        // Create a temporary directory with some files and subdirectories
+        let temp = tempdir().unwrap();
+        let file1_path = temp.path().join("file1.txt");
+        let file2_path = temp.path().join("subdir").join("file2.txt");
+
+        // Create the files
+        fs::create_dir(temp.path().join("subdir")).unwrap();
+        let mut file1 = FsFile::create(&file1_path).unwrap();
+        writeln!(file1, "File 1 contents").unwrap();
+        let mut file2 = FsFile::create(&file2_path).unwrap();
+        writeln!(file2, "File 2 contents").unwrap();
+
+        // Use for_each_file to count the files
+        let mut count = 0;
+        for_each_file(temp.path(), &mut |path| {
+            if path.is_file() {
+                count += 1;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        // Verify that both files were counted
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_for_each_file_deep_tree() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let mut deepest = temp.path().to_path_buf();
+        for _ in 0..1000 {
+            deepest.push("d");
+        }
+        fs::create_dir_all(&deepest).unwrap();
+        fs::write(deepest.join("leaf.txt"), "leaf").unwrap();
+
+        // A stack this small would overflow when recursing once per level
+        let root = temp.path().to_path_buf();
+        let visited = thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || {
+                let mut visited = Vec::new();
+                for_each_file(&root, &mut |path| {
+                    visited.push(path.to_path_buf());
+                    Ok(())
+                }).map(|_| visited)
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(visited, vec![deepest.join("leaf.txt")]);
+    }
+
+    #[test]
+    fn test_minify_css() {
+        use std::str;
+
+        let input_file = File {
+            filename: "example.css".into(),
+            file_type: FileType::CSS,
+            contents: b"body { color: red; }  /* comment */".to_vec(),
+            hash: None,
+        };
+
+        let result = minify_css(input_file, &ProcessOptions::default()).unwrap();
+
+        assert_eq!(result.file_type, FileType::CSS);
+        assert_eq!(result.filename, "example.css");
+        assert!(str::from_utf8(&result.contents).unwrap().contains("body{color:red}"));
+    }
+
+    #[test]
+    fn test_minify_css_license_comments() {
+        let input_file = || File {
+            filename: "example.css".into(),
+            file_type: FileType::CSS,
+            contents: b"/*! license */\nbody { color: red; } /* comment */\na::after { content: \"/*!\"; }\n/*! vendor */ p { margin: 0; }".to_vec(),
+            hash: None,
+        };
+
+        let result = minify_css(input_file(), &ProcessOptions::default()).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&result.contents).unwrap(),
+            "/*! license */\n/*! vendor */\nbody{color:red}a:after{content:\"/*!\"}p{margin:0}"
+        );
+
+        let options = ProcessOptions { preserve_license_comments: false, ..ProcessOptions::default() };
+        let result = minify_css(input_file(), &options).unwrap();
+        assert!(!std::str::from_utf8(&result.contents).unwrap().contains("license"));
+    }
+
+    #[test]
+    fn test_remove_unused_custom_properties() {
+        let input_file = File {
+            filename: "example.css".into(),
+            file_type: FileType::CSS,
+            contents: b":root { --used: red; --nested: 1px; --unused: blue; } p { color: var(--used); margin: var(--missing, var(--nested)); }".to_vec(),
+            hash: None,
+        };
+
+        let options = ProcessOptions { remove_unused_custom_properties: true, ..ProcessOptions::default() };
+        let result = minify_css(input_file, &options).unwrap();
+
+        // Properties referenced directly or from a fallback stay
+        assert_eq!(
+            std::str::from_utf8(&result.contents).unwrap(),
+            ":root{--used:red;--nested:1px}p{color:var(--used);margin:var(--missing,var(--nested))}"
+        );
+    }
+
+    #[test]
+    fn test_css_color_targets() {
+        let minify = |css_color_targets| {
+            let input_file = File {
+                filename: "example.css".into(),
+                file_type: FileType::CSS,
+                contents: b"a { color: rgba(255, 0, 0, 0.5); background: lab(50% 0 0); border-color: rgb(0, 0, 255); }".to_vec(),
+                hash: None,
+            };
+            let options = ProcessOptions { css_color_targets, ..ProcessOptions::default() };
+            String::from_utf8(minify_css(input_file, &options).unwrap().contents).unwrap()
+        };
+
+        let modern = minify(CssColorTargets::Modern);
+        assert!(modern.contains("color:#ff000080"));
+        assert!(modern.contains("background:lab(50% 0 0)"));
+        assert!(modern.contains("border-color:#00f"));
+
+        // Opaque colors are hex for every target, as that is already understood everywhere
+        let legacy = minify(CssColorTargets::Legacy);
+        assert!(legacy.contains("color:rgba(255,0,0,.5)"));
+        assert!(legacy.contains("background:#777;background:lab(50% 0 0)"));
+        assert!(legacy.contains("border-color:#00f"));
+    }
+
+    #[test]
+    fn test_css_pseudo_classes() {
+        let input_file = File {
+            filename: "example.css".into(),
+            file_type: FileType::CSS,
+            contents: b"a:hover { color: red; } a:focus { color: blue; }".to_vec(),
+            hash: None,
+        };
+
+        let options = ProcessOptions {
+            css_pseudo_classes: Some(PseudoClassReplacements { hover: Some("is-hovered".to_string()), ..PseudoClassReplacements::default() }),
+            ..ProcessOptions::default()
+        };
+        let result = minify_css(input_file, &options).unwrap();
+
+        // Only the configured pseudo-class is replaced
+        assert_eq!(std::str::from_utf8(&result.contents).unwrap(), "a.is-hovered{color:red}a:focus{color:#00f}");
+    }
+
+    #[test]
+    fn test_minify_css_lenient() {
+        let malformed = || File {
+            filename: "broken.css".into(),
+            file_type: FileType::CSS,
+            contents: b"body { color: red; } }".to_vec(),
+            hash: None,
+        };
+
+        assert!(minify_css(malformed(), &ProcessOptions::default()).is_err());
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let options = ProcessOptions {
+            lenient_css: true,
+            on_warning: Some(WarningHandler::new({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning.clone())
+            })),
+            ..ProcessOptions::default()
+        };
+        let result = minify_css(malformed(), &options).unwrap();
+
+        assert_eq!(result.contents, malformed().contents);
+        assert!(matches!(&warnings.lock().unwrap()[..], [Warning::UnminifiedStylesheet { filename, .. }] if filename == "broken.css"));
+
+        // The unminified fallback isn't cached, so it's reported on every run
+        let cache_dir = tempfile::tempdir().unwrap();
+        let options = ProcessOptions { cache_dir: Some(cache_dir.path().to_path_buf()), ..options };
+        for _ in 0..2 {
+            assert_eq!(minify_css(malformed(), &options).unwrap().contents, malformed().contents);
+        }
+        assert_eq!(warnings.lock().unwrap().len(), 3);
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+
+        // Rewriting the urls of the stylesheet falls back the same way, rather than failing first
+        let input_dir = tempfile::tempdir().unwrap();
+        fs::write(input_dir.path().join("broken.css"), malformed().contents).unwrap();
+        for lenient_css in [false, true] {
+            warnings.lock().unwrap().clear();
+            let output_dir = tempfile::tempdir().unwrap();
+            let options = ProcessOptions { rewrite_css_urls: true, cache_dir: None, lenient_css, ..options.clone() };
+            let result = process_directory_with_options(input_dir.path(), output_dir.path(), &options);
+            if !lenient_css {
+                assert!(result.is_err());
+                continue;
+            }
+            let result = &result.unwrap()[0];
+            assert_eq!(fs::read(output_dir.path().join(result.output.as_ref().unwrap())).unwrap(), malformed().contents);
+            let warnings = warnings.lock().unwrap();
+            assert!(!warnings.is_empty());
+            assert!(warnings.iter().all(|warning| matches!(warning, Warning::UnminifiedStylesheet { filename, .. } if filename == "broken.css")));
+        }
+    }
+
+    #[test]
+    fn test_bundle_css_order() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("components").join("nested")).unwrap();
+        fs::write(dir.path().join("overrides.css"), "/* overrides */").unwrap();
+        fs::write(dir.path().join("reset.css"), "/* reset */").unwrap();
+        fs::write(dir.path().join("components").join("card.css"), "/* card */").unwrap();
+        fs::write(dir.path().join("components").join("button.css"), "/* button */").unwrap();
+        fs::write(dir.path().join("components").join("nested").join("deep.css"), "/* deep */").unwrap();
+
+        let bundled = bundle_css(dir.path(), &["reset.css", "components/*.css", "overrides.css", "*.css"], "bundle.css").unwrap();
+
+        // Pattern order first, sorted within a pattern, each file once and `*` not crossing directories
+        assert_eq!(
+            std::str::from_utf8(&bundled.contents).unwrap(),
+            "/* reset */\n/* button */\n/* card */\n/* overrides */\n"
+        );
+    }
+
+    #[test]
+    fn test_minify_unsupported() {
+        let input_file = File {
+            filename: "data.json".into(),
+            file_type: FileType::JSON,
+            contents: b"{ \"a\": 1 }".to_vec(),
+            hash: None,
+        };
+
+        let err = minify(input_file).unwrap_err();
+
+        assert!(matches!(err, LibError::UnsupportedOperation { file_type: FileType::JSON, .. }));
+    }
+
+    #[test]
+    fn test_minify_css_non_css_file() {
+        let input_file = File {
+            filename: "example.txt".into(),
+            file_type: FileType::Other,
+            contents: b"Some random text".to_vec(),
+            hash: None,
+        };
+
+        let result = minify_css(input_file, &ProcessOptions::default()).unwrap();
+
+        assert_eq!(result.file_type, FileType::Other);
+        assert_eq!(result.filename, "example.txt");
+        assert_eq!(result.contents, b"Some random text");
+    }
+
+    #[test]
+    fn test_minify_css_already_minified() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Contents lightningcss would change if it parsed them
+        let contents = b"body { color: red; }  /* comment */";
+        let input_file_path = input_dir.path().join("app.min.css");
+        fs::write(&input_file_path, contents).unwrap();
+
+        let mut manifest = HashMap::new();
+        process_file(&input_file_path, Some(FileType::CSS), output_dir.path(), &ProcessOptions::default(), &mut manifest, &OutputPaths::default()).unwrap();
+
+        // The file is hashed, but its contents are untouched
+        let hashed_filename = &manifest.get(&input_file_path.to_string_lossy().to_string()).unwrap().hashed;
+        assert_ne!(hashed_filename, "app.min.css");
+        assert!(hashed_filename.ends_with(".css"));
+        assert_eq!(fs::read(output_dir.path().join(hashed_filename)).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_normalize_trailing_newline() {
+        let minified = || minify_css(File {
+            filename: "example.css".into(),
+            file_type: FileType::CSS,
+            contents: b"body { color: red; }\n\n".to_vec(),
+            hash: None,
+        }, &ProcessOptions::default()).unwrap();
+
+        let with_newline = normalize_trailing_newline(minified(), Some(true));
+        assert_eq!(with_newline.contents, b"body{color:red}\n");
+
+        let without_newline = normalize_trailing_newline(minified(), Some(false));
+        assert_eq!(without_newline.contents, b"body{color:red}");
+
+        // Binary files are never touched
+        let image = File {
+            filename: "image.png".into(),
+            file_type: FileType::Image,
+            contents: b"\x89PNG\n".to_vec(),
+            hash: None,
+        };
+        assert_eq!(normalize_trailing_newline(image, Some(false)).contents, b"\x89PNG\n");
+    }
+
+    #[test]
+    fn test_process_bytes() {
+        let (contents, filename) = process_bytes(b"body { color: red; }  /* comment */".to_vec(), FileType::CSS, "css").unwrap();
+
+        // The contents are minified, and the name is the hash of the minified contents
+        assert_eq!(contents, b"body{color:red}");
+        assert_eq!(filename, format!("{}.css", blake3::hash(b"body{color:red}")));
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        use std::str;
+
+        let input_file = File {
+            filename: "post.md".into(),
+            file_type: FileType::Markdown,
+            contents: b"# Title\n\nSome text.".to_vec(),
+            hash: None,
+        };
+
+        let result = render_markdown(input_file).unwrap();
+
+        assert_eq!(result.file_type, FileType::HTML);
+        assert_eq!(result.filename, "post.html");
+        let html = str::from_utf8(&result.contents).unwrap();
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<p>Some text.</p>"));
+    }
+
+    #[test]
+    fn test_process_file() {
+        use std::fs::{self, File as FsFile};
+        use std::io::Write;
+        use tempfile::tempdir;
+
+        // Create temporary directories for input and output
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Create a sample input file
+        let input_file_path = input_dir.path().join("example.css");
+        let mut input_file = FsFile::create(&input_file_path).unwrap();
+        writeln!(input_file, "body {{ color: red; }}").unwrap();
+
+        // Create a manifest to track processed files
+        let mut manifest = HashMap::new();
+
+        // Process the file
+        process_file(&input_file_path, Some(FileType::CSS), output_dir.path(), &ProcessOptions::default(), &mut manifest, &OutputPaths::default()).unwrap();
+
+        // Verify the manifest contains the correct mapping
+        let hashed_filename = &manifest.get(&input_file_path.to_string_lossy().to_string()).unwrap().hashed;
+        let output_file_path = output_dir.path().join(hashed_filename);
+        assert!(output_file_path.exists());
+
+        // Verify the contents of the processed file
+        let processed_contents = fs::read_to_string(output_file_path).unwrap();
+        assert!(processed_contents.contains("body{color:red}"));
+    }
+
+    #[test]
+    fn test_process_files() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Only one of the two files is passed in
+        let processed_path = input_dir.path().join("processed.css");
+        let ignored_path = input_dir.path().join("ignored.css");
+        fs::write(&processed_path, "body { color: red; }").unwrap();
+        fs::write(&ignored_path, "p { color: blue; }").unwrap();
+
+        process_files(std::slice::from_ref(&processed_path), output_dir.path(), &ProcessOptions::default()).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert!(manifest.contains_key(&processed_path.to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn test_process_directory_hash_includes_extension() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("a.css"), "a{color:red}").unwrap();
+        fs::write(input_dir.path().join("a.js"), "a{color:red}").unwrap();
+
+        let stems = |hash_includes_extension| {
+            let output_dir = tempdir().unwrap();
+            let options = ProcessOptions { hash_includes_extension, ..ProcessOptions::default() };
+            let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+            let stems: HashSet<String> = results
+                .iter()
+                .map(|result| result.output.as_ref().unwrap().split('.').next().unwrap().to_string())
+                .collect();
+            // Already minified CSS counts as unchanged either way
+            assert!(results.iter().all(|result| result.outcome == FileOutcome::Copied));
+            stems.len()
+        };
+
+        assert_eq!(stems(false), 1);
+        assert_eq!(stems(true), 2);
+    }
+
+    #[test]
+    fn test_verbosity() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        capture_logs();
+        let levels = || LOG_RECORDS.with(RefCell::take);
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+
+        levels();
+        process_directory_with_options(input_dir.path(), output_dir.path(), &ProcessOptions::default()).unwrap();
+        assert_eq!(levels(), [log::Level::Info]);
+
+        let quiet = ProcessOptions { verbosity: log::LevelFilter::Error, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &quiet).unwrap();
+        assert!(levels().is_empty());
+
+        // Failures are still reported in quiet mode
+        fs::write(input_dir.path().join("notes.txt"), "").unwrap();
+        let quiet = ProcessOptions { unknown_file_policy: UnknownFilePolicy::Error, ..quiet };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &quiet).unwrap_err();
+        assert!(levels().contains(&log::Level::Error));
+
+        // Warnings without a handler are logged, subject to the same verbosity
+        let broken = || File { filename: "broken.css".into(), file_type: FileType::CSS, contents: b"a { } }".to_vec(), hash: None };
+        let lenient = ProcessOptions { lenient_css: true, ..ProcessOptions::default() };
+        minify_css(broken(), &lenient).unwrap();
+        assert_eq!(levels(), [log::Level::Warn]);
+        minify_css(broken(), &ProcessOptions { verbosity: log::LevelFilter::Error, ..lenient }).unwrap();
+        assert!(levels().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_charset() {
+        let normalize = |filename: &str, css: &str| String::from_utf8(normalize_charset(File {
+            filename: filename.to_string(),
+            file_type: FileType::CSS,
+            contents: css.as_bytes().to_vec(),
+            hash: None
+        }).contents).unwrap();
+
+        // Rules inside strings and comments are left alone
+        assert_eq!(
+            normalize("a.min.css", "@charset \"UTF-8\";\n@charset \"iso-8859-1\";\na::before{content:\"@charset;\"}/* @charset */"),
+            "a::before{content:\"@charset;\"}/* @charset */"
+        );
+        assert_eq!(
+            normalize("a.min.css", "@charset \"UTF-8\";a::before{content:\"é\"}@charset \"UTF-8\";"),
+            "@charset \"UTF-8\";a::before{content:\"é\"}"
+        );
+    }
+
+    #[test]
+    fn test_process_directory_normalize_charset() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "@charset \"UTF-8\";\n@charset \"UTF-8\";\na::before { content: \"→\"; }").unwrap();
+        fs::write(input_dir.path().join("vendor.min.css"), "@charset \"UTF-8\";@charset \"UTF-8\";a{color:red}").unwrap();
+
+        let output_dir = tempdir().unwrap();
+        let options = ProcessOptions { normalize_charset: true, ..ProcessOptions::default() };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        for result in results {
+            let output = fs::read_to_string(output_dir.path().join(result.output.unwrap())).unwrap();
+            let expected = if result.input.ends_with("main.css") { 1 } else { 0 };
+            assert_eq!(output.matches("@charset").count(), expected, "{}", output);
+            assert_eq!(output.starts_with("@charset \"UTF-8\";"), expected == 1);
+        }
+    }
+
+    #[test]
+    fn test_manifest_metadata() {
+        use std::collections::BTreeMap;
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let critical_path = input_dir.path().join("critical.css");
+        let app_path = input_dir.path().join("app.js");
+        fs::write(&critical_path, "body { color: red; }").unwrap();
+        fs::write(&app_path, "console.log(1);").unwrap();
+
+        let options = ProcessOptions {
+            manifest_metadata: Some(MetadataProvider::new(|path| match path.file_stem() {
+                Some(stem) if stem == "critical" => BTreeMap::from([("group".to_string(), serde_json::json!("critical"))]),
+                _ => BTreeMap::new()
+            })),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let json = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let json: HashMap<String, serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(json[critical_path.to_str().unwrap()]["group"], "critical");
+        assert!(json[app_path.to_str().unwrap()].get("group").is_none());
+
+        // The metadata survives loading the manifest
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(manifest.entry(critical_path.to_str().unwrap()).unwrap().metadata["group"], "critical");
+        assert!(manifest.entry(app_path.to_str().unwrap()).unwrap().metadata.is_empty());
+
+        // Metadata can't overwrite the entry's own fields
+        let options = ProcessOptions {
+            manifest_metadata: Some(MetadataProvider::new(|_| BTreeMap::from([("size".to_string(), serde_json::json!(0))]))),
+            ..ProcessOptions::default()
+        };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        let LibError::FileError { source, .. } = err else { panic!("expected a file error, got {err:?}") };
+        assert!(matches!(*source, LibError::ReservedMetadataKey { ref key, .. } if key == "size"));
+    }
+
+    #[test]
+    fn test_manifest_sizes() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }\n".repeat(50)).unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let options = ProcessOptions { manifest_sizes: true, compression: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap().into_entries();
+        assert_eq!(manifest.len(), 2);
+        let size = |filename: String| fs::metadata(output_dir.path().join(filename)).unwrap().len();
+        for entry in manifest.values() {
+            assert_eq!(entry.size, Some(size(entry.hashed.clone())));
+            assert_eq!(entry.gzip_size, Some(size(format!("{}.gz", entry.hashed))));
+            assert_eq!(entry.brotli_size, Some(size(format!("{}.br", entry.hashed))));
+        }
+    }
+
+    #[test]
+    fn test_debug_css() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; margin: 0 }\na { color: blue }").unwrap();
+
+        let options = ProcessOptions { debug_css: true, shard_depth: 1, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(css_path.to_str().unwrap()).unwrap();
+        let debug_name = entry.debug.as_ref().unwrap();
+        assert_eq!(*debug_name, entry.hashed.replace(".css", ".debug.css"));
+
+        let minified = fs::read_to_string(output_dir.path().join(&entry.hashed)).unwrap();
+        let debug = fs::read_to_string(output_dir.path().join(debug_name)).unwrap();
+        assert_eq!(minified.lines().count(), 1);
+        assert!(debug.lines().count() > 1);
+        assert!(debug.len() > minified.len());
+    }
+
+    #[test]
+    fn test_file_listing() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let image_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }".repeat(100)).unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+        fs::write(input_dir.path().join("logo.png"), "not really a png").unwrap();
+        fs::write(output_dir.path().join("stale.css"), "a{}").unwrap();
+
+        for listing in ["files.txt", "files.json"] {
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let options = ProcessOptions {
+                compression: true,
+                sha256_sidecars: true,
+                shard_depth: 2,
+                reverse_manifest: Some("reverse.json".to_string()),
+                type_output_dirs: HashMap::from([(FileType::Image, image_dir.path().to_path_buf())]),
+                file_listing: Some(listing.to_string()),
+                on_written: Some(WriteHook::new({
+                    let written = written.clone();
+                    move |file| {
+                        written.lock().unwrap().push(file.path.clone());
+                        Ok(())
+                    }
+                })),
+                ..ProcessOptions::default()
+            };
+            process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+            let contents = fs::read_to_string(output_dir.path().join(listing)).unwrap();
+            let listed: Vec<String> = if listing.ends_with(".json") {
+                serde_json::from_str(&contents).unwrap()
+            } else {
+                contents.lines().map(str::to_string).collect()
+            };
+
+            // Exactly the files of this run are listed: the outputs, their siblings and sidecars, and the manifests
+            let mut expected: Vec<String> = written.lock().unwrap().iter().map(|path| match path.strip_prefix(output_dir.path()) {
+                Ok(relative) => relative.to_str().unwrap().replace('\\', "/"),
+                Err(_) => path.to_str().unwrap().to_string()
+            }).collect();
+            expected.extend(["manifest.json".to_string(), "reverse.json".to_string()]);
+            expected.sort();
+            assert_eq!(listed, expected);
+            assert!(listed.iter().any(|file| file.ends_with(".css.br")));
+            assert!(listed.iter().any(|file| file.ends_with(".css.sha256")));
+            assert!(!listed.contains(&"stale.css".to_string()));
+            assert!(!listed.iter().any(|file| file.starts_with("files.")));
+
+            // Routed files are listed by their full path
+            let image = listed.iter().find(|file| file.ends_with(".png")).unwrap();
+            assert!(Path::new(image).starts_with(image_dir.path()));
+        }
+    }
+
+    #[test]
+    fn test_reverse_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let options = ProcessOptions { reverse_manifest: Some("reverse-manifest.json".to_string()), ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(output_dir.path().join("manifest.json")).unwrap()).unwrap();
+        let reverse: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(output_dir.path().join("reverse-manifest.json")).unwrap()).unwrap();
+
+        // Every hashed name resolves back to its original path
+        let original = css_path.to_string_lossy().to_string();
+        assert_eq!(reverse[&manifest[&original]], original);
+        assert_eq!(reverse.len(), 2);
+        assert!(reverse.iter().all(|(hashed, original)| manifest[original] == *hashed));
+    }
+
+    #[test]
+    fn test_conflicting_outputs() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        for (dir, contents) in [("a", "User-agent: *"), ("b", "Disallow: /")] {
+            fs::create_dir(input_dir.path().join(dir)).unwrap();
+            fs::write(input_dir.path().join(dir).join("robots.txt"), contents).unwrap();
+        }
+
+        // Both files keep their name, so they would be written to the same path
+        let options = ProcessOptions { min_hash_size: 1024, ..ProcessOptions::default() };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        let LibError::FileError { source, .. } = err else { panic!("expected a file error, got {err:?}") };
+        assert!(matches!(*source, LibError::ConflictingOutput(ref path) if path.ends_with("robots.txt")));
+
+        // Identical contents are written once without a conflict
+        fs::write(input_dir.path().join("b/robots.txt"), "User-agent: *").unwrap();
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+    }
+
+    #[test]
+    fn test_process_directory_to_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::create_dir(input_dir.path().join("js")).unwrap();
+        fs::write(input_dir.path().join("js/app.js"), "console.log(1);").unwrap();
+
+        let options = ProcessOptions { manifest_js_global: Some("MANIFEST".to_string()), ..ProcessOptions::default() };
+        let (results, manifest) = process_directory_to_manifest(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            assert_eq!(manifest.get(result.input.to_str().unwrap()), result.output.as_deref());
+            assert!(output_dir.path().join(result.output.as_ref().unwrap()).exists());
+        }
+        assert!(!output_dir.path().join("manifest.json").exists());
+        assert!(!output_dir.path().join("manifest.js").exists());
+    }
+
+    #[test]
+    fn test_process_entrypoints() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let dir = input_dir.path();
+        fs::create_dir(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.js"), "import { helper } from './lib/helper.js';\nhelper();").unwrap();
+        fs::write(dir.join("lib/helper.js"), "export function helper() {}").unwrap();
+        fs::write(dir.join("orphan.js"), "console.log('unused');").unwrap();
+
+        let results = process_entrypoints(&[dir.join("main.js")], output_dir.path(), &ProcessOptions::default()).unwrap();
+
+        let inputs: Vec<_> = results.iter().map(|result| result.input.clone()).collect();
+        assert_eq!(inputs, [dir.join("main.js"), dir.join("lib/helper.js")]);
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert!(manifest.get(dir.join("orphan.js").to_str().unwrap()).is_none());
+        // The entry, its dependency and the manifest
+        assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_processor_finalize() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["main.css", "app.js", "notes.txt"]
+            .iter()
+            .map(|name| {
+                let path = input_dir.path().join(name);
+                fs::write(&path, format!("/* {} */", name)).unwrap();
+                path
+            })
+            .collect();
+
+        let mut processor = Processor::new(output_dir.path(), &ProcessOptions::default()).unwrap();
+        let mut outputs = Vec::new();
+        for path in &paths {
+            outputs.push(processor.process(path).unwrap().output.unwrap());
+            // Nothing is written until the processor is finalized
+            assert!(!output_dir.path().join("manifest.json").exists());
+        }
+        let manifest = processor.finalize().unwrap();
+
+        for (path, output) in paths.iter().zip(&outputs) {
+            assert_eq!(manifest.get(path.to_str().unwrap()), Some(output.as_str()));
+        }
+        assert_eq!(Manifest::load(&output_dir.path().join("manifest.json")).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_split_manifest_by_type() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let other_css_path = input_dir.path().join("print.css");
+        let js_path = input_dir.path().join("app.js");
+        let png_path = input_dir.path().join("logo.png");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&other_css_path, "body { color: black; }").unwrap();
+        fs::write(&js_path, "console.log(1);").unwrap();
+        fs::write(&png_path, b"not really a png").unwrap();
+
+        let options = ProcessOptions { split_manifest_by_type: true, write_manifest: false, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let load = |filename: &str| -> HashMap<String, String> {
+            serde_json::from_str(&fs::read_to_string(output_dir.path().join(filename)).unwrap()).unwrap()
+        };
+        let css_manifest = load("manifest.css.json");
+        assert_eq!(css_manifest.len(), 2);
+        assert!(css_manifest.contains_key(css_path.to_str().unwrap()));
+        assert!(css_manifest.contains_key(other_css_path.to_str().unwrap()));
+        assert_eq!(load("manifest.js.json").keys().collect::<Vec<_>>(), vec![js_path.to_str().unwrap()]);
+        assert_eq!(load("manifest.image.json").keys().collect::<Vec<_>>(), vec![png_path.to_str().unwrap()]);
+        assert!(!output_dir.path().join("manifest.json").exists());
+
+        // Fed back as input, the split manifests aren't processed
+        let results = process_directory_with_options(output_dir.path(), input_dir.path().join("again").as_path(), &options).unwrap();
+        assert_eq!(results.len(), 4);
+    }
+
+    #[test]
+    fn test_process_directory_hash_encoding() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("logo.png"), b"not really a png").unwrap();
+
+        let outputs = |hash_encoding| {
+            let output_dir = tempdir().unwrap();
+            let options = ProcessOptions { hash_encoding, fast_passthrough: true, ..ProcessOptions::default() };
+            let mut results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+            results.sort_by(|a, b| a.input.cmp(&b.input));
+            for result in &results {
+                assert!(output_dir.path().join(result.output.as_ref().unwrap()).exists());
+            }
+            results.into_iter().map(|result| result.output.unwrap()).collect::<Vec<_>>()
+        };
+
+        // Transformed and streamed files alike get the shorter names
+        let base62 = outputs(HashEncoding::Base62);
+        assert_eq!(base62, outputs(HashEncoding::Base62));
+        assert_ne!(base62, outputs(HashEncoding::Hex));
+        assert!(base62.iter().all(|output| output.split('.').next().unwrap().len() == 43));
+    }
+
+    #[test]
+    fn test_process_directory_modified_since() {
+        use std::fs;
+        use std::time::SystemTime;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let touched_path = input_dir.path().join("touched.css");
+        let untouched_path = input_dir.path().join("untouched.css");
+        fs::write(&touched_path, "body { color: red; }").unwrap();
+        fs::write(&untouched_path, "p { color: blue; }").unwrap();
+        process_directory(input_dir.path(), output_dir.path()).unwrap();
+
+        let deployed_at = SystemTime::now();
+        let later = FileTime::from_system_time(deployed_at + Duration::from_secs(60));
+        fs::write(&touched_path, "body { color: green; }").unwrap();
+        filetime::set_file_mtime(&touched_path, later).unwrap();
+        filetime::set_file_mtime(&untouched_path, FileTime::from_system_time(deployed_at - Duration::from_secs(60))).unwrap();
+
+        let options = ProcessOptions { modified_since: Some(deployed_at), ..ProcessOptions::default() };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Only the touched file is reprocessed, the manifest still has both
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].input, touched_path);
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(Some(&manifest[touched_path.to_str().unwrap()]), results[0].output.as_ref());
+        assert!(manifest.contains_key(untouched_path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_process_files_keeps_manifest_entries() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let first_path = input_dir.path().join("first.css");
+        let second_path = input_dir.path().join("second.css");
+        fs::write(&first_path, "body { color: red; }").unwrap();
+        fs::write(&second_path, "p { color: blue; }").unwrap();
+        fs::write(output_dir.path().join("manifest.json"), r#"{ "unrelated.js": "abc.js" }"#).unwrap();
+
+        process_files(std::slice::from_ref(&first_path), output_dir.path(), &ProcessOptions::default()).unwrap();
+        fs::write(&first_path, "body { color: green; }").unwrap();
+        let results = process_files(&[first_path.clone(), second_path.clone()], output_dir.path(), &ProcessOptions::default()).unwrap();
+
+        // The unrelated entry survives both runs, the reprocessed one is updated
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest.len(), 3);
+        assert_eq!(manifest["unrelated.js"], "abc.js");
+        assert_eq!(Some(&manifest[first_path.to_str().unwrap()]), results[0].output.as_ref());
+
+        // A detailed entry from an earlier build keeps its fields, even if this run doesn't ask for any
+        fs::write(output_dir.path().join("manifest.json"), r#"{ "unrelated.png": { "hashed": "abc.png", "height": 4 } }"#).unwrap();
+        process_files(std::slice::from_ref(&first_path), output_dir.path(), &ProcessOptions::default()).unwrap();
+        let manifest = crate::manifest::Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(manifest.entry("unrelated.png").unwrap().height, Some(4));
+        assert!(manifest.get(first_path.to_str().unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_process_directory() {
+        use std::fs::{self, File as FsFile};
+        use std::io::Write;
+        use tempfile::tempdir;
+
+        // Create temporary directories for input and output
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Create sample input files
+        let input_file1_path = input_dir.path().join("example1.css");
+        let input_file2_path = input_dir.path().join("example2.js");
+        let mut input_file1 = FsFile::create(&input_file1_path).unwrap();
+        let mut input_file2 = FsFile::create(&input_file2_path).unwrap();
+        writeln!(input_file1, "body {{ color: red; }}").unwrap();
+        writeln!(input_file2, "console.log('Hello, world!');").unwrap();
+
+        // Process the directory
+        process_directory(input_dir.path(), output_dir.path()).unwrap();
+
+        // Verify the output directory contains processed files
+        let output_files: Vec<_> = fs::read_dir(output_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+
+        assert!(!output_files.is_empty());
+
+        // Verify the manifest file exists
+        let manifest_path = output_dir.path().join("manifest.json");
+        assert!(manifest_path.exists());
+
+        // Verify the manifest file contents
+        let manifest_contents = fs::read_to_string(manifest_path).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+        assert!(manifest.contains_key(&input_file1_path.to_string_lossy().to_string()));
+        assert!(manifest.contains_key(&input_file2_path.to_string_lossy().to_string()));
+
+        // Verify the processed CSS file is minified
+        let hashed_filename = manifest.get(&input_file1_path.to_string_lossy().to_string()).unwrap();
+        let processed_css_path = output_dir.path().join(hashed_filename);
+        let processed_css_contents = fs::read_to_string(processed_css_path).unwrap();
+        assert!(processed_css_contents.contains("body{color:red}"));
+
+        // Verify the JS file is unchanged
+        let hashed_filename = manifest.get(&input_file2_path.to_string_lossy().to_string()).unwrap();
+        let processed_js_path = output_dir.path().join(hashed_filename);
+        let processed_js_contents = fs::read_to_string(processed_js_path).unwrap();
+        assert!(processed_js_contents.contains("console.log('Hello, world!');"));
+    }
+
+    #[test]
+    fn test_process_directory_sharded() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        let css_path = input_dir.path().join("main.css");
+        let js_path = input_dir.path().join("main.js");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&js_path, "console.log('Hello, world!');").unwrap();
+
+        let options = ProcessOptions { shard_depth: 2, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+
+        for path in [&css_path, &js_path] {
+            // The manifest value is the sharded relative path
+            let relative = manifest.get(&path.to_string_lossy().to_string()).unwrap();
+            let (prefix, filename) = relative.split_once('/').unwrap();
+            assert_eq!(prefix.len(), 2);
+            assert!(filename.starts_with(prefix));
+
+            // The file lives in the two-character prefix directory
+            assert!(output_dir.path().join(prefix).is_dir());
+            assert!(output_dir.path().join(prefix).join(filename).is_file());
+        }
+    }
+
+    #[test]
+    fn test_process_directory_flatten_to_depth() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let nested_dir = input_dir.path().join("styles/vendor/css");
+        fs::create_dir_all(&nested_dir).unwrap();
+        fs::write(nested_dir.join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log('Hello, world!');").unwrap();
+
+        let options = ProcessOptions { flatten_to_depth: Some(1), ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+
+        // Only the immediate parent directory is kept
+        let css = &manifest[&nested_dir.join("main.css").to_string_lossy().to_string()];
+        let (dir, filename) = css.split_once('/').unwrap();
+        assert_eq!(dir, "css");
+        assert!(!filename.contains('/'));
+        assert!(output_dir.path().join(css).is_file());
+
+        // Files at the top of the input directory stay at the top of the output directory
+        let js = &manifest[&input_dir.path().join("app.js").to_string_lossy().to_string()];
+        assert!(!js.contains('/'));
+    }
+
+    #[test]
+    fn test_process_directory_without_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { write_manifest: false, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // The file is processed, but no manifest is written
+        let output_files: Vec<_> = fs::read_dir(output_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(output_files.len(), 1);
+        assert!(!output_dir.path().join("manifest.json").exists());
+    }
+
+    #[test]
+    fn test_process_directory_skips_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // The input is the output of a previous run, custom manifest name included
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("manifest.json"), "{}").unwrap();
+        fs::write(input_dir.path().join("assets.json"), "{}").unwrap();
+
+        let options = ProcessOptions { manifest_filename: "assets.json".to_string(), ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Only the stylesheet is processed, and the manifest doesn't reference the old ones
+        let manifest_contents = fs::read_to_string(output_dir.path().join("assets.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert!(manifest.values().all(|hashed| hashed.ends_with(".css")));
+
+        let output_files: Vec<_> = fs::read_dir(output_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(output_files.len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_file_policy() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let unknown_path = input_dir.path().join("notes.txt");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&unknown_path, "Some notes").unwrap();
+
+        let process = |policy| {
+            let output_dir = tempdir().unwrap();
+            let options = ProcessOptions { unknown_file_policy: policy, ..ProcessOptions::default() };
+            let result = process_directory_with_options(input_dir.path(), output_dir.path(), &options);
+            let manifest = fs::read_to_string(output_dir.path().join("manifest.json"))
+                .map(|contents| serde_json::from_str::<HashMap<String, String>>(&contents).unwrap());
+            (result, manifest)
+        };
+
+        // Copy processes both files
+        let (result, manifest) = process(UnknownFilePolicy::Copy);
+        assert!(result.is_ok());
+        assert!(manifest.unwrap().contains_key(&unknown_path.to_string_lossy().to_string()));
+
+        // Skip leaves the unknown file out of the manifest
+        let (result, manifest) = process(UnknownFilePolicy::Skip);
+        assert!(result.is_ok());
+        let manifest = manifest.unwrap();
+        assert!(manifest.contains_key(&css_path.to_string_lossy().to_string()));
+        assert!(!manifest.contains_key(&unknown_path.to_string_lossy().to_string()));
+
+        // Error names the offending file
+        let (result, _) = process(UnknownFilePolicy::Error);
+        match result {
+            Err(LibError::FileError { path, source }) => {
+                assert_eq!(path, unknown_path);
+                assert!(matches!(*source, LibError::UnknownFileType(_)));
+                assert!(source.to_string().contains("notes.txt"));
+            },
+            other => panic!("Expected an unknown file type error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_process_directory_batch_size() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for dir in ["b", "a", "a/nested"] {
+            fs::create_dir_all(input_dir.path().join(dir)).unwrap();
+            for i in 0..15 {
+                let path = input_dir.path().join(dir).join(format!("{}.css", i));
+                fs::write(&path, format!(".c{} {{ margin: {}px; }}", i, i)).unwrap();
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let options = ProcessOptions { batch_size: Some(7), ..ProcessOptions::default() };
+        let process = || {
+            let output_dir = tempdir().unwrap();
+            let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+            let manifest: HashMap<String, String> = serde_json::from_str(&fs::read_to_string(output_dir.path().join("manifest.json")).unwrap()).unwrap();
+            (results.into_iter().map(|result| result.input).collect::<Vec<_>>(), manifest)
+        };
+
+        // Every file makes it into the manifest, processed in sorted order on every run
+        let (order, manifest) = process();
+        assert_eq!(manifest.len(), paths.len());
+        assert!(paths.iter().all(|path| manifest.contains_key(path.to_str().unwrap())));
+        assert_eq!(order, paths);
+        assert_eq!(process().0, order);
+
+        // A stylesheet can't refer to the hashed name of a file in a later batch, and says so
+        let input_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("a.css"), "body { background: url(z.png); }").unwrap();
+        fs::write(input_dir.path().join("z.png"), "not really a png").unwrap();
+        for (batch_size, expected) in [(Some(1), 1), (None, 0)] {
+            let warnings = Arc::new(Mutex::new(Vec::new()));
+            let options = ProcessOptions {
+                batch_size,
+                rewrite_css_urls: true,
+                on_warning: Some(WarningHandler::new({
+                    let warnings = warnings.clone();
+                    move |warning| warnings.lock().unwrap().push(warning.clone())
+                })),
+                ..ProcessOptions::default()
+            };
+            let output_dir = tempdir().unwrap();
+            process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+            let warnings = warnings.lock().unwrap();
+            assert_eq!(warnings.len(), expected);
+            assert!(warnings.iter().all(|warning| matches!(warning, Warning::UnprocessedReference { url, .. } if url == "z.png")));
+        }
+    }
+
+    #[test]
+    fn test_process_directory_image_threads() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Several copies of each sample image, plus a stylesheet
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("static-test-files");
+        let mut image_paths = Vec::new();
+        for sample in ["sample-png.png", "sample-image.jpeg", "sample-webp.webp", "sample-avif.avif"] {
+            for copy in 0..3 {
+                let path = input_dir.path().join(format!("{}-{}", copy, sample));
+                fs::copy(samples.join(sample), &path).unwrap();
+                image_paths.push(path);
+            }
+        }
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { image_threads: 2, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+        assert_eq!(manifest.len(), image_paths.len() + 1);
+
+        // Every image made it to the output intact
+        for path in &image_paths {
+            let hashed_filename = manifest.get(&path.to_string_lossy().to_string()).unwrap();
+            let output = fs::read(output_dir.path().join(hashed_filename)).unwrap();
+            assert_eq!(output, fs::read(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_process_file_preserve_mtime() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let input_file_path = input_dir.path().join("main.css");
+        fs::write(&input_file_path, "body { color: red; }").unwrap();
+
+        // Backdate the source file
+        let mtime = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&input_file_path, mtime).unwrap();
+
+        let options = ProcessOptions { preserve_mtime: true, ..ProcessOptions::default() };
+        let mut manifest = HashMap::new();
+        process_file(&input_file_path, Some(FileType::CSS), output_dir.path(), &options, &mut manifest, &OutputPaths::default()).unwrap();
+
+        let hashed_filename = &manifest.get(&input_file_path.to_string_lossy().to_string()).unwrap().hashed;
+        let metadata = fs::metadata(output_dir.path().join(hashed_filename)).unwrap();
+        assert_eq!(FileTime::from_last_modification_time(&metadata), mtime);
+    }
+
+    #[test]
+    fn test_process_directory_error_path() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // A valid stylesheet next to a broken one a few directories down
+        let broken_dir = input_dir.path().join("deeply").join("nested");
+        let broken_path = broken_dir.join("broken.css");
+        fs::create_dir_all(&broken_dir).unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(&broken_path, [0xff, 0xfe, 0xfd]).unwrap();
+
+        let err = process_directory(input_dir.path(), output_dir.path()).unwrap_err();
+
+        match &err {
+            LibError::FileError { path, source } => {
+                assert_eq!(path, &broken_path);
+                assert!(matches!(**source, LibError::ParsingError(_)));
+            },
+            other => panic!("Expected a file error, got {:?}", other)
+        }
+        assert!(err.to_string().contains(&broken_path.display().to_string()));
+    }
+
+    #[test]
+    fn test_manifest_original_name() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { manifest_original_name: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, serde_json::Value> = serde_json::from_str(&manifest).unwrap();
+        let entry = &manifest[css_path.to_str().unwrap()];
+        assert!(entry["hashed"].as_str().unwrap().ends_with(".css"));
+        assert_eq!(entry["original"], "main.css");
+    }
+
+    #[test]
+    fn test_manifest_cache_control() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::{Manifest, IMMUTABLE_CACHE_CONTROL, SHORT_CACHE_CONTROL};
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let robots_path = input_dir.path().join("robots.txt");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&robots_path, "").unwrap();
+
+        // Keeps the empty robots.txt under its own name
+        let options = ProcessOptions { manifest_cache_control: true, min_hash_size: 1, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let css = manifest.entry(css_path.to_str().unwrap()).unwrap();
+        assert_ne!(css.hashed, "main.css");
+        assert_eq!(css.cache_control.as_deref(), Some(IMMUTABLE_CACHE_CONTROL));
+        let robots = manifest.entry(robots_path.to_str().unwrap()).unwrap();
+        assert_eq!(robots.hashed, "robots.txt");
+        assert_eq!(robots.cache_control.as_deref(), Some(SHORT_CACHE_CONTROL));
+    }
+
+    #[test]
+    fn test_mime_overrides() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let wasm_path = input_dir.path().join("module.wasm");
+        let data_path = input_dir.path().join("level.dat");
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&wasm_path, b"\0asm\x01\0\0\0").unwrap();
+        fs::write(&data_path, b"level 1").unwrap();
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions {
+            manifest_content_type: true,
+            mime_overrides: HashMap::from([
+                ("wasm".to_string(), "application/wasm".to_string()),
+                ("DAT".to_string(), "application/x-level".to_string())
+            ]),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let content_type = |path: &std::path::Path| manifest.entry(path.to_str().unwrap()).unwrap().content_type.clone();
+        assert_eq!(content_type(&wasm_path).as_deref(), Some("application/wasm"));
+        assert_eq!(content_type(&data_path).as_deref(), Some("application/x-level"));
+        assert_eq!(content_type(&css_path).as_deref(), Some("text/css; charset=utf-8"));
+    }
+
+    #[test]
+    fn test_normalize_orientation() {
+        use std::fs;
+        use std::io::Cursor;
+        use image::{codecs::png::PngEncoder, ExtendedColorType, GenericImageView, ImageDecoder, ImageEncoder, ImageReader};
+        use image::metadata::Orientation;
+        use tempfile::tempdir;
+
+        // A 2x1 PNG of a black and a white pixel, tagged to be rotated a quarter turn clockwise
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(Cursor::new(&mut png));
+        encoder.set_exif_metadata(b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0".to_vec()).unwrap();
+        encoder.write_image(&[0, 0, 0, 255, 255, 255], 2, 1, ExtendedColorType::Rgb8).unwrap();
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let png_path = input_dir.path().join("photo.png");
+        fs::write(&png_path, &png).unwrap();
+
+        let options = ProcessOptions { normalize_orientation: true, fast_passthrough: true, ..ProcessOptions::default() };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+        assert_eq!(results[0].outcome, FileOutcome::Transformed);
+
+        let output = fs::read(output_dir.path().join(results[0].output.as_ref().unwrap())).unwrap();
+        let mut decoder = ImageReader::new(Cursor::new(&output)).with_guessed_format().unwrap().into_decoder().unwrap();
+        assert_eq!(decoder.orientation().unwrap(), Orientation::NoTransforms);
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+
+        // Rotating clockwise puts the left pixel on top
+        let oriented = image::load_from_memory(&output).unwrap();
+        assert_eq!(oriented.dimensions(), (1, 2));
+        assert_eq!(oriented.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(oriented.get_pixel(0, 1).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_image_transparency() {
+        use std::fs;
+        use std::io::Cursor;
+        use image::{codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        // A 2x1 PNG with an opaque and a fully transparent pixel
+        let mut png = Vec::new();
+        PngEncoder::new(Cursor::new(&mut png)).write_image(&[0, 0, 0, 255, 0, 0, 0, 0], 2, 1, ExtendedColorType::Rgba8).unwrap();
+
+        let input_dir = tempdir().unwrap();
+        let png_path = input_dir.path().join("icon.png");
+        fs::write(&png_path, &png).unwrap();
+
+        // By default the JPEG is replaced by a WebP, with a warning
+        let output_dir = tempdir().unwrap();
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let options = ProcessOptions {
+            image_variants: vec![ImageVariant::Jpeg],
+            on_warning: Some(WarningHandler::new({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning.clone())
+            })),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(png_path.to_str().unwrap()).unwrap();
+        assert_eq!(entry.formats.as_deref(), Some(&["png".to_string(), "webp".to_string()][..]));
+        let webp = image::open(output_dir.path().join(entry.hashed.replace(".png", ".webp"))).unwrap();
+        assert_eq!(webp.to_rgba8().get_pixel(1, 0)[3], 0);
+        assert!(matches!(&warnings.lock().unwrap()[..], [Warning::TransparencyFallback { format, .. }] if format == "JPEG"));
+
+        // Or the conversion is refused
+        let options = ProcessOptions { image_transparency: TransparencyPolicy::Error, ..options };
+        let err = process_directory_with_options(input_dir.path(), tempdir().unwrap().path(), &options).unwrap_err();
+        let LibError::FileError { source, .. } = err else { panic!("expected a FileError, got {err:?}") };
+        assert!(matches!(*source, LibError::TransparencyLoss { .. }));
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let css: String = (0..200).map(|i| format!(".c{i} {{ margin: {i}px; content: \"}};\"; }}\n")).collect();
+        fs::write(&css_path, &css).unwrap();
+
+        let options = ProcessOptions { chunk_size: Some(1000), ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(css_path.to_str().unwrap()).unwrap();
+        let chunks = entry.chunks.as_ref().unwrap();
+        assert!(chunks.len() > 1);
+        let stem = entry.hashed.strip_suffix(".css").unwrap();
+        assert_eq!(chunks[0], format!("{}.0.css", stem));
+
+        // Each chunk is valid on its own, and together they are the whole stylesheet
+        let mut joined = String::new();
+        for chunk in chunks {
+            let contents = fs::read_to_string(output_dir.path().join(chunk)).unwrap();
+            assert!(!StyleSheet::parse(&contents, ParserOptions::default()).unwrap().rules.0.is_empty());
+            joined.push_str(&contents);
+        }
+        let whole = fs::read_to_string(output_dir.path().join(&entry.hashed)).unwrap();
+        assert_eq!(joined, whole);
+        let reparsed = StyleSheet::parse(&joined, ParserOptions::default()).unwrap();
+        assert_eq!(reparsed.rules.0.len(), 200);
+    }
+
+    #[test]
+    fn test_chunk_size_js() {
+        let js = concat!(
+            "if (a) {\n  run(\"}\");\n}\nelse {\n  stop();\n}\n",
+            "try {\n  load();\n} catch (error) {\n  report(error);\n}\nfinally {\n  done();\n}\n",
+            "do {\n  step();\n}\nwhile (more());\n",
+            "function elsewhere() {\n  return 1;\n}\n",
+            "elsewhere();\n"
+        );
+        let chunks = split_chunks(js, FileType::JS, 1);
+        assert_eq!(chunks, [
+            "if (a) {\n  run(\"}\");\n}\nelse {\n  stop();\n}",
+            "\ntry {\n  load();\n} catch (error) {\n  report(error);\n}\nfinally {\n  done();\n}",
+            "\ndo {\n  step();\n}\nwhile (more());",
+            "\nfunction elsewhere() {\n  return 1;\n}",
+            "\nelsewhere();\n"
+        ]);
+
+        // Every chunk is a whole statement: its brackets balance and it isn't the tail of another one
+        for chunk in &chunks {
+            let code = chunk.replace("\"}\"", "\"\"");
+            assert_eq!(code.matches('{').count(), code.matches('}').count(), "{chunk}");
+            assert_eq!(code.matches('(').count(), code.matches(')').count(), "{chunk}");
+            let first = chunk.split_whitespace().next().unwrap();
+            assert!(!["else", "catch", "finally", "while"].contains(&first), "{chunk}");
+        }
+        assert_eq!(chunks.concat(), js);
+    }
+
+    #[test]
+    fn test_image_variants() {
+        use std::fs;
+        use std::io::Cursor;
+        use image::{codecs::png::PngEncoder, ExtendedColorType, ImageEncoder, ImageFormat};
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let mut png = Vec::new();
+        PngEncoder::new(Cursor::new(&mut png)).write_image(&[0, 0, 0, 255, 255, 255], 2, 1, ExtendedColorType::Rgb8).unwrap();
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let png_path = input_dir.path().join("photo.png");
+        fs::write(&png_path, &png).unwrap();
+
+        let options = ProcessOptions { image_variants: vec![ImageVariant::WebP, ImageVariant::Jpeg], ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // One entry lists every format, each stored under the same stem
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(png_path.to_str().unwrap()).unwrap();
+        assert_eq!(entry.formats.as_deref(), Some(&["png".to_string(), "webp".to_string(), "jpg".to_string()][..]));
+        let stem = entry.hashed.strip_suffix(".png").unwrap();
+        for (ext, format) in [("webp", ImageFormat::WebP), ("jpg", ImageFormat::Jpeg)] {
+            let variant = fs::read(output_dir.path().join(format!("{}.{}", stem, ext))).unwrap();
+            assert_eq!(image::guess_format(&variant).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn test_csp_inline() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let inline_path = input_dir.path().join("inline.js");
+        let app_path = input_dir.path().join("app.js");
+        fs::write(&inline_path, "alert('Hello, world.');").unwrap();
+        fs::write(&app_path, "console.log(1);").unwrap();
+
+        let options = ProcessOptions {
+            csp_inline: Some(InlineSelector::new(|path| path.file_stem().is_some_and(|stem| stem == "inline"))),
+            ..ProcessOptions::default()
+        };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let inlined = results.iter().find(|result| result.input == inline_path).unwrap();
+        assert_eq!(inlined.outcome, FileOutcome::Inlined);
+        assert_eq!(inlined.output, None);
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(inline_path.to_str().unwrap()).unwrap();
+        assert_eq!(entry.csp.as_deref(), Some("sha256-qznLcsROx4GACP2dm0UCKCzCG+HiZ1guq6ZZDob/Tng="));
+        assert_eq!(entry.hashed, "");
+        // Only the other script and the manifest are written
+        assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 2);
+        assert!(manifest.entry(app_path.to_str().unwrap()).unwrap().csp.is_none());
+    }
+
+    #[test]
+    fn test_manifest_image_dimensions() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::manifest::Manifest;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let png_path = input_dir.path().join("pixel.png");
+        let css_path = input_dir.path().join("main.css");
+        // The signature and IHDR chunk of a 3x2 PNG, which is all the header parser reads
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&3u32.to_be_bytes());
+        png.extend_from_slice(&2u32.to_be_bytes());
+        png.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        fs::write(&png_path, png).unwrap();
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { manifest_image_dimensions: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let png_entry = manifest.entry(png_path.to_str().unwrap()).unwrap();
+        assert_eq!((png_entry.width, png_entry.height), (Some(3), Some(2)));
+
+        // Only images get dimensions
+        let css_entry = manifest.entry(css_path.to_str().unwrap()).unwrap();
+        assert_eq!((css_entry.width, css_entry.height), (None, None));
+    }
+
+    #[test]
+    fn test_process_directory_record_timings() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let results = process_directory(input_dir.path(), output_dir.path()).unwrap();
+        assert!(results.iter().all(|result| result.duration.is_none()));
+
+        let options = ProcessOptions { record_timings: true, ..ProcessOptions::default() };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        assert!(results.iter().all(|result| result.duration.is_some_and(|duration| !duration.is_zero())));
+        let durations = durations_by_type(&results);
+        assert_eq!(durations.len(), 2);
+        assert!(!durations[&FileType::CSS].is_zero());
+        assert!(!durations[&FileType::JS].is_zero());
+    }
+
+    #[test]
+    fn test_process_directory_allowed_output_extensions() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let psd_path = input_dir.path().join("mockup.psd");
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("post.md"), "# Hello").unwrap();
+        fs::write(&psd_path, b"8BPS").unwrap();
+
+        let options = ProcessOptions {
+            allowed_output_extensions: Some(HashSet::from(["css".to_string(), "HTML".to_string()])),
+            ..ProcessOptions::default()
+        };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        assert!(matches!(
+            err,
+            LibError::FileError { path, source } if path == psd_path
+                && matches!(*source, LibError::DisallowedOutputExtension { ref extension, .. } if extension == "psd")
+        ));
+
+        let options = ProcessOptions { disallowed_output_policy: DisallowedOutputPolicy::Skip, ..options };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Markdown is checked by its rendered extension
+        let psd_result = results.iter().find(|result| result.input == psd_path).unwrap();
+        assert_eq!(psd_result.outcome, FileOutcome::Skipped);
+        assert_eq!(results.iter().filter(|result| result.outcome != FileOutcome::Skipped).count(), 2);
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        assert!(!manifest.contains("mockup.psd"));
+    }
+
+    #[test]
+    fn test_process_directory_error_on_empty() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        process_directory(input_dir.path(), output_dir.path()).unwrap();
+
+        let options = ProcessOptions { error_on_empty: true, ..ProcessOptions::default() };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        assert!(matches!(err, LibError::EmptyManifest));
+        assert!(err.to_string().contains("check the input path"));
+    }
+
+    #[test]
+    fn test_process_directory_empty_directories() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::create_dir_all(input_dir.path().join("css/vendor")).unwrap();
+        fs::create_dir(input_dir.path().join("js")).unwrap();
+
+        let results = process_directory(input_dir.path(), output_dir.path()).unwrap();
+        assert!(results.is_empty());
+
+        let options = ProcessOptions { error_on_empty: true, ..ProcessOptions::default() };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        assert!(matches!(err, LibError::EmptyDirectories { directories: 3, .. }));
+        assert!(err.to_string().contains("3 directories but no files"));
+    }
+
+    #[test]
+    fn test_process_directory_missing_output_dir() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        let missing_dir = output_dir.path().join("typo");
+
+        let options = ProcessOptions { create_output: false, ..ProcessOptions::default() };
+        let err = process_directory_with_options(input_dir.path(), &missing_dir, &options).unwrap_err();
+
+        assert!(matches!(&err, LibError::MissingOutputDir(path) if *path == missing_dir));
+        assert!(err.to_string().contains("does not exist"));
+        assert!(!missing_dir.exists());
+
+        // An existing directory is used as it is
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+        assert!(output_dir.path().join("manifest.json").exists());
+    }
+
+    #[test]
+    fn test_process_directory_reproducible() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        fs::create_dir(input_dir.path().join("css")).unwrap();
+        fs::write(input_dir.path().join("css").join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+        fs::write(input_dir.path().join("logo.png"), b"not really a png").unwrap();
+
+        let options = ProcessOptions {
+            reproducible: true,
+            preserve_mtime: true,
+            compression: true,
+            manifest_js_global: Some("__ASSETS__".to_string()),
+            ..ProcessOptions::default()
+        };
+
+        // Snapshot every output file by its path relative to the output directory
+        let build = || {
+            let output_dir = tempdir().unwrap();
+            process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+            let mut files = Vec::new();
+            for_each_file(output_dir.path(), &mut |path| {
+                files.push((path.strip_prefix(output_dir.path()).unwrap().to_path_buf(), fs::read(path).unwrap()));
+                Ok(())
+            }).unwrap();
+            files.sort();
+            files
+        };
+
+        let first = build();
+        assert_eq!(first, build());
+
+        // Keys are relative, with forward slashes
+        let manifest = &first.iter().find(|(path, _)| path == Path::new("manifest.json")).unwrap().1;
+        let manifest: HashMap<String, String> = serde_json::from_slice(manifest).unwrap();
+        assert!(manifest.contains_key("css/main.css"));
+        assert!(manifest.contains_key("app.js"));
+    }
+
+    #[test]
+    fn test_process_directory_hard_link_passthrough() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let image_path = input_dir.path().join("logo.png");
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&image_path, b"not really a png").unwrap();
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { hard_link_passthrough: true, ..ProcessOptions::default() };
+        let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let output_path = |input: &Path| {
+            let result = results.iter().find(|result| result.input == input).unwrap();
+            output_dir.path().join(result.output.as_ref().unwrap())
+        };
+        assert_eq!(fs::read(output_path(&image_path)).unwrap(), b"not really a png");
+
+        // Both directories are on the same filesystem, so the image is linked and the stylesheet written
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+            assert_eq!(inode(&output_path(&image_path)), inode(&image_path));
+            assert_ne!(inode(&output_path(&css_path)), inode(&css_path));
+        }
+    }
+
+    #[test]
+    fn test_process_directory_fast_passthrough() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let binary_path = input_dir.path().join("data.bin");
+        let contents: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(&binary_path, &contents).unwrap();
+
+        let process = |fast_passthrough| {
+            let output_dir = tempdir().unwrap();
+            let options = ProcessOptions { fast_passthrough, shard_depth: 2, ..ProcessOptions::default() };
+            process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+            let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+            let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+            let hashed_filename = manifest.get(&binary_path.to_string_lossy().to_string()).unwrap().clone();
+            let output = fs::read(output_dir.path().join(&hashed_filename)).unwrap();
+            let file_count = fs::read_dir(output_dir.path()).unwrap().count();
+            (hashed_filename, output, file_count)
+        };
+
+        // Both paths produce the same name and contents, and the fast path leaves no temporary files behind
+        let (normal_name, normal_output, normal_count) = process(false);
+        let (fast_name, fast_output, fast_count) = process(true);
+        assert_eq!(fast_name, normal_name);
+        assert_eq!(fast_output, normal_output);
+        assert_eq!(fast_output, contents);
+        assert_eq!(fast_count, normal_count);
+    }
+
+    #[test]
+    fn test_process_directory_sampled_passthrough() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        for (fast_passthrough, hard_link_passthrough) in [(true, false), (false, true)] {
+            let input_dir = tempdir().unwrap();
+            let output_dir = tempdir().unwrap();
+            let binary_path = input_dir.path().join("data.bin");
+            let mut contents: Vec<u8> = (0..=255).cycle().take(256 * 1024).collect();
+            fs::write(&binary_path, &contents).unwrap();
+
+            let options = ProcessOptions {
+                fast_passthrough,
+                hard_link_passthrough,
+                hash_sample_threshold: Some(1024),
+                ..ProcessOptions::default()
+            };
+            let process = || {
+                let results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+                output_dir.path().join(results[0].output.as_ref().unwrap())
+            };
+            let first = process();
+
+            // A byte between the samples keeps the name, but the output still gets the new contents.
+            // The source is replaced rather than edited so a hard link doesn't update the output by itself
+            contents[64 * 1024 + 1] ^= 1;
+            fs::remove_file(&binary_path).unwrap();
+            fs::write(&binary_path, &contents).unwrap();
+            let second = process();
+            assert_eq!(second, first);
+            assert_eq!(fs::read(&second).unwrap(), contents);
+
+            // Two such files in one run would share the output, so the second one fails instead of replacing the first
+            let mut other = contents.clone();
+            other[64 * 1024 + 2] ^= 1;
+            fs::write(input_dir.path().join("other.bin"), &other).unwrap();
+            let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+            let LibError::FileError { source, .. } = err else { panic!("expected a file error, got {err:?}") };
+            assert!(matches!(*source, LibError::ConflictingOutput(ref path) if *path == first));
+            assert!([&contents, &other].contains(&&fs::read(&first).unwrap()));
+            assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_process_directory_manifest_js() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("main.js"), "console.log('Hello, world!');").unwrap();
+
+        let options = ProcessOptions { manifest_js_global: Some("__ASSETS__".to_string()), ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // The script assigns the same object as the JSON manifest
+        let script = fs::read_to_string(output_dir.path().join("manifest.js")).unwrap();
+        let json = script
+            .strip_prefix("window.__ASSETS__=")
+            .and_then(|rest| rest.strip_suffix(';'))
+            .unwrap();
+        let assigned: HashMap<String, String> = serde_json::from_str(json).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+        assert_eq!(assigned, manifest);
+        assert_eq!(assigned.len(), 2);
+    }
+
+    #[test]
+    fn test_process_directory_short_hash() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { manifest_short_hash: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = manifest::Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        let entry = manifest.entry(&css_path.to_string_lossy()).unwrap();
+
+        // The alias is the start of the full hash, and the physical filename stays full-length
+        let short = entry.short.as_deref().unwrap();
+        assert_eq!(short.len(), 7);
+        assert!(entry.hashed.starts_with(short));
+        assert_eq!(entry.hashed.len(), 64 + ".css".len());
+        assert!(output_dir.path().join(&entry.hashed).exists());
+    }
+
+    #[test]
+    fn test_process_directory_default_compression() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let image_path = input_dir.path().join("sample.png");
+        let text_path = input_dir.path().join("notes.txt");
+        let binary_path = input_dir.path().join("data.bin");
+        fs::write(&css_path, "body { color: red; } p { color: blue; }").unwrap();
+        fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("static-test-files/sample-png.png"), &image_path).unwrap();
+        fs::write(&text_path, "Plain text compresses well. ".repeat(20)).unwrap();
+        fs::write(&binary_path, [0u8; 512]).unwrap();
+
+        let options = ProcessOptions { compression: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest_contents = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
+
+        // The stylesheet gets both siblings
+        let css_filename = manifest.get(&css_path.to_string_lossy().to_string()).unwrap();
+        assert!(output_dir.path().join(format!("{}.gz", css_filename)).exists());
+        assert!(output_dir.path().join(format!("{}.br", css_filename)).exists());
+
+        // The image gets none
+        let image_filename = manifest.get(&image_path.to_string_lossy().to_string()).unwrap();
+        assert!(!output_dir.path().join(format!("{}.gz", image_filename)).exists());
+        assert!(!output_dir.path().join(format!("{}.br", image_filename)).exists());
+
+        // Plain text without a type of its own is compressed too, unlike other unrecognized files
+        let text_filename = manifest.get(&text_path.to_string_lossy().to_string()).unwrap();
+        assert!(output_dir.path().join(format!("{}.gz", text_filename)).exists());
+        assert!(output_dir.path().join(format!("{}.br", text_filename)).exists());
+        let binary_filename = manifest.get(&binary_path.to_string_lossy().to_string()).unwrap();
+        assert!(!output_dir.path().join(format!("{}.gz", binary_filename)).exists());
+    }
+
+    #[test]
+    fn test_strict_file_types() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let path = input_dir.path().join("evil.css");
+        fs::write(&path, b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR").unwrap();
+
+        let options = ProcessOptions { strict_file_types: true, ..ProcessOptions::default() };
+        let err = process_files(std::slice::from_ref(&path), output_dir.path(), &options).unwrap_err();
+
+        let LibError::FileError { source, .. } = err else { panic!("expected a FileError, got {err:?}") };
+        assert!(matches!(*source, LibError::FileTypeMismatch { expected: FileType::CSS, found: FileType::Image }));
+    }
+
+    #[test]
+    fn test_strict_extensions() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let path = input_dir.path().join("data.xyz");
+        fs::write(&path, "unexpected").unwrap();
+
+        // By default the file is copied like any other
+        let results = process_files(std::slice::from_ref(&path), output_dir.path(), &ProcessOptions::default()).unwrap();
+        assert_eq!(results[0].outcome, FileOutcome::Copied);
+
+        let options = ProcessOptions { strict_extensions: true, ..ProcessOptions::default() };
+        let err = process_files(std::slice::from_ref(&path), output_dir.path(), &options).unwrap_err();
+
+        let LibError::FileError { source, .. } = err else { panic!("expected a FileError, got {err:?}") };
+        assert!(matches!(&*source, LibError::UnrecognizedExtension { path: err_path, extension } if *err_path == path && extension == "xyz"));
+        assert!(source.to_string().contains("data.xyz"));
+    }
+
+    #[test]
+    fn test_font_subsetter() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("font.woff2"), b"wOF2 font").unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let subsetter = {
+            let calls = calls.clone();
+            FontSubsetter::new(HashSet::from(['a', 'b']), move |f, glyphs| {
+                assert_eq!(f.file_type, FileType::Font);
+                assert_eq!(glyphs.len(), 2);
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(f)
+            })
+        };
+        let options = ProcessOptions {
+            font_subsetter: Some(subsetter),
+            fast_passthrough: true,
+            ..ProcessOptions::default()
+        };
+
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Only the font went through the hook, even with fast passthrough enabled
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_on_written() {
+        use std::collections::BTreeSet;
+        use std::io::Cursor;
+        use base64::Engine;
+        use image::{codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
+        use tempfile::tempdir;
+
+        let mut png = Vec::new();
+        PngEncoder::new(Cursor::new(&mut png)).write_image(&[0, 0, 0, 255, 255, 255], 2, 1, ExtendedColorType::Rgb8).unwrap();
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.resize(256, 0);
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::create_dir(input_dir.path().join("vendor")).unwrap();
+        fs::write(input_dir.path().join("main.css"), format!(
+            "a {{ background: url(data:image/png;base64,{}); }} b {{ color: red; }} i {{ color: blue; }}",
+            base64::engine::general_purpose::STANDARD.encode(&data)
+        )).unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+        fs::write(input_dir.path().join("photo.png"), &png).unwrap();
+        fs::write(input_dir.path().join("vendor").join("lib.js"), "var lib = 1;").unwrap();
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let on_written = {
+            let written = written.clone();
+            WriteHook::new(move |file| {
+                assert!(file.path.is_file());
+                assert_eq!(file.path.file_name().unwrap().to_str(), Some(file.filename.as_str()));
+                written.lock().unwrap().push(file.clone());
+                Ok(())
+            })
+        };
+        let options = ProcessOptions {
+            on_written: Some(on_written),
+            verbatim_dirs: vec!["vendor".to_string()],
+            extract_data_uris: Some(128),
+            debug_css: true,
+            image_variants: vec![ImageVariant::WebP],
+            chunk_size: Some(16),
+            compression: true,
+            sha256_sidecars: true,
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Every output but the manifest was passed to the hook, once
+        let written = written.lock().unwrap();
+        let passed: BTreeSet<_> = written.iter().map(|file| file.path.clone()).collect();
+        let mut outputs = BTreeSet::new();
+        for_each_file(output_dir.path(), &mut |path| {
+            outputs.insert(path.to_path_buf());
+            Ok(())
+        }).unwrap();
+        outputs.remove(&output_dir.path().join(DEFAULT_MANIFEST_FILENAME));
+        assert_eq!(passed, outputs);
+        assert_eq!(written.len(), outputs.len());
+        for ext in [".debug.css", ".0.css", ".webp", ".css.gz", ".css.sha256"] {
+            assert!(written.iter().any(|file| file.filename.ends_with(ext)), "no {ext} was passed");
+        }
+        assert!(written.iter().any(|file| file.path == output_dir.path().join("vendor").join("lib.js") && file.file_type == FileType::JS));
+
+        // A failing hook aborts processing
+        let options = ProcessOptions {
+            on_written: Some(WriteHook::new(|_| Err(LibError::HashError("upload failed".to_string())))),
+            ..ProcessOptions::default()
+        };
+        let err = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap_err();
+        assert!(err.to_string().contains("upload failed"));
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let cache_dir = tempdir().unwrap();
+        let first_output = tempdir().unwrap();
+        let second_output = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+
+        let options = ProcessOptions {
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            ..ProcessOptions::default()
+        };
+
+        process_directory_with_options(input_dir.path(), first_output.path(), &options).unwrap();
+        let entries: Vec<_> = fs::read_dir(cache_dir.path()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(entries.len(), 1);
+
+        // The warm cache produces the same output
+        process_directory_with_options(input_dir.path(), second_output.path(), &options).unwrap();
+
+        let first_manifest = fs::read_to_string(first_output.path().join("manifest.json")).unwrap();
+        let second_manifest = fs::read_to_string(second_output.path().join("manifest.json")).unwrap();
+        assert_eq!(first_manifest, second_manifest);
+
+        let manifest: HashMap<String, String> = serde_json::from_str(&first_manifest).unwrap();
+        let hashed_filename = manifest.values().next().unwrap();
+        assert_eq!(
+            fs::read(first_output.path().join(hashed_filename)).unwrap(),
+            fs::read(second_output.path().join(hashed_filename)).unwrap()
+        );
+
+        // A cache hit is used without minifying again, so a doctored entry shows up in the output
+        fs::write(&entries[0], "body{color:#00f}").unwrap();
+        let third_output = tempdir().unwrap();
+        let results = process_directory_with_options(input_dir.path(), third_output.path(), &options).unwrap();
+        let output = fs::read_to_string(third_output.path().join(results[0].output.as_ref().unwrap())).unwrap();
+        assert_eq!(output, "body{color:#00f}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let path = input_dir.path().join(OsStr::from_bytes(b"bad\xff.css"));
+        fs::write(&path, "body { color: red; }").unwrap();
+
+        // Every run fails the same way instead of writing a lossy manifest key
+        for _ in 0..2 {
+            let err = process_directory(input_dir.path(), output_dir.path()).unwrap_err();
+            let LibError::FileError { source, .. } = err else { panic!("expected a FileError, got {err:?}") };
+            assert!(matches!(*source, LibError::NonUtf8Path(ref bad) if *bad == path));
+        }
+        assert!(!output_dir.path().join(DEFAULT_MANIFEST_FILENAME).exists());
+    }
+
+    #[test]
+    fn test_process_bytes_as() {
+        let css = b"/* Layout */\nbody {\n    margin: 0;\n}\n".to_vec();
+        let (contents, filename) = process_bytes_as(css.clone(), FileType::CSS, "layout.css", &ProcessOptions::default()).unwrap();
+        assert_eq!(filename, "layout.css");
+        assert_eq!(contents, b"body{margin:0}");
+
+        // A name marking the output as minified doesn't keep it from being minified
+        let (contents, filename) = process_bytes_as(css, FileType::CSS, "x.min.css", &ProcessOptions::default()).unwrap();
+        assert_eq!(filename, "x.min.css");
+        assert_eq!(contents, b"body{margin:0}");
+
+        // The name is kept even when the extension would change
+        let options = ProcessOptions { trailing_newline: Some(false), ..ProcessOptions::default() };
+        let (contents, filename) = process_bytes_as(b"# Title".to_vec(), FileType::Markdown, "index.md", &options).unwrap();
+        assert_eq!(filename, "index.md");
+        assert_eq!(contents, b"<h1>Title</h1>");
     }
 
     #[test]
-    fn test_load_file() {
-        use std::fs::File as FsFile;
-        use std::io::Write;
-        use tempfile::tempdir;
-        
-        let dir = tempdir().unwrap();
-        let path = dir.path().join("example.css");
-        let mut file = FsFile::create(&path).unwrap();
-        writeln!(file, "body {{ background: #fff; }}").unwrap();
+    fn test_process_stdin() {
+        use std::io::Cursor;
 
-        let loaded = load_file(&path).unwrap();
-        assert_eq!(loaded.filename, path.file_name().unwrap().to_string_lossy());
-        assert!(loaded.file_type == FileType::CSS);
-        assert!(loaded.contents == b"body { background: #fff; }\n");
+        let reader = Cursor::new(b"body {\n    color: red;\n}\n".to_vec());
+        let mut writer = Vec::new();
+
+        let filename = process_stdin(FileType::CSS, "css", reader, &mut writer).unwrap();
+
+        assert_eq!(writer, b"body{color:red}");
+        assert_eq!(filename, format!("{}.css", blake3::hash(b"body{color:red}")));
     }
 
     #[test]
-    fn test_load_file_negative() {
-        let res = load_file(Path::new("non/existant/thispathdefinatelyhouldnevereverexistanywhere/path.file"));
-        if let Ok(_) = res {
-            panic!("Invalid file loaded");
-        }
+    fn test_sha256_sidecars() {
+        use sha2::{Digest, Sha256};
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { sha256_sidecars: true, ..ProcessOptions::default() };
+        let mut manifest = HashMap::new();
+        process_file(&css_path, Some(FileType::CSS), output_dir.path(), &options, &mut manifest, &OutputPaths::default()).unwrap();
+
+        let hashed_filename = &manifest[css_path.to_str().unwrap()].hashed;
+        let written = fs::read(output_dir.path().join(hashed_filename)).unwrap();
+        let sidecar = fs::read_to_string(output_dir.path().join(format!("{}.sha256", hashed_filename))).unwrap();
+
+        assert_eq!(sidecar, format!("{:x}  {}\n", Sha256::digest(&written), hashed_filename));
     }
 
     #[test]
-    fn test_save_file() {
-        use std::fs;
+    fn test_manifest_key() {
         use tempfile::tempdir;
-        
-        let dir = tempdir().unwrap();
-        let file = File {
-            filename: "hello.txt".into(),
-            file_type: FileType::Other,
-            contents: b"Hello, world!".to_vec(),
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::create_dir(input_dir.path().join("css")).unwrap();
+        fs::write(input_dir.path().join("css").join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let root = input_dir.path().to_path_buf();
+        let options = ProcessOptions {
+            manifest_key: Some(ManifestKey::new(move |path| {
+                path.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/")
+            })),
+            ..ProcessOptions::default()
         };
-        save_file(dir.path(), &file).unwrap();
-        let written = fs::read_to_string(dir.path().join("hello.txt")).unwrap();
-        assert_eq!(written, "Hello, world!");
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let mut keys: Vec<&str> = manifest.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["app.js", "css/main.css"]);
     }
 
     #[test]
-    fn test_write_manifest() {
-        use std::fs;
+    fn test_bundle_css_imports() {
         use tempfile::tempdir;
 
-        let dir = tempdir().unwrap();
-        let output_dir = dir.path();
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let main_path = input_dir.path().join("main.css");
+        fs::create_dir(input_dir.path().join("partials")).unwrap();
+        fs::write(input_dir.path().join("partials").join("base.css"), "body { margin: 0; }").unwrap();
+        fs::write(&main_path, "@import \"partials/base.css\";\np { color: red; }").unwrap();
 
-        // Create a sample manifest
+        let options = ProcessOptions { bundle_css_imports: true, ..ProcessOptions::default() };
         let mut manifest = HashMap::new();
-        manifest.insert(
-            "/input/example.css".to_string(),
-            "example-hashed.css".to_string(),
-        );
-        manifest.insert(
-            "/input/script.js".to_string(),
-            "script-hashed.js".to_string(),
-        );
+        process_file(&main_path, Some(FileType::CSS), output_dir.path(), &options, &mut manifest, &OutputPaths::default()).unwrap();
 
-        // Write the manifest to the output directory
-        write_manifest(output_dir, &manifest).unwrap();
+        // The imported rules end up in the single, minified output
+        let hashed_filename = &manifest[main_path.to_str().unwrap()].hashed;
+        let contents = fs::read_to_string(output_dir.path().join(hashed_filename)).unwrap();
+        assert_eq!(contents, "body{margin:0}p{color:red}");
+    }
 
-        // Read the manifest file back
-        let manifest_path = output_dir.join("manifest.json");
-        let written_manifest = fs::read_to_string(manifest_path).unwrap();
+    #[test]
+    fn test_only_extensions() {
+        use tempfile::tempdir;
 
-        // Verify the contents of the manifest file
-        let expected_manifest = serde_json::to_string_pretty(&manifest).unwrap();
-        assert_eq!(written_manifest, expected_manifest);
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let upper_css_path = input_dir.path().join("OLD.CSS");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&upper_css_path, "p { color: blue; }").unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let options = ProcessOptions {
+            only_extensions: Some(HashSet::from(["css".to_string()])),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // Both stylesheets are processed, the script is neither output nor in the manifest
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert!(manifest.contains_key(css_path.to_str().unwrap()));
+        assert!(manifest.contains_key(upper_css_path.to_str().unwrap()));
+        assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 3);
     }
 
     #[test]
-    fn test_for_each_file() {
-        use std::fs::{self, File as FsFile};
-        use std::io::Write;
+    fn test_ignore_files() {
         use tempfile::tempdir;
 
-        // Create a temporary directory with some files and subdirectories
-        let temp = tempdir().unwrap();
-        let file1_path = temp.path().join("file1.txt");
-        let file2_path = temp.path().join("subdir").join("file2.txt");
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        for dir in ["drafts", "css"] {
+            fs::create_dir(input_dir.path().join(dir)).unwrap();
+        }
+        fs::write(input_dir.path().join(".spignore"), "drafts/\n*.log\n").unwrap();
+        fs::write(input_dir.path().join("css/.spignore"), "old.css\n!keep.log\n").unwrap();
+        let files = ["app.js", "debug.log", "drafts/post.md", "drafts/style.css", "css/main.css", "css/old.css", "css/keep.log"];
+        for file in files {
+            fs::write(input_dir.path().join(file), "body { color: red; }").unwrap();
+        }
 
-        // Create the files
-        fs::create_dir(temp.path().join("subdir")).unwrap();
-        let mut file1 = FsFile::create(&file1_path).unwrap();
-        writeln!(file1, "File 1 contents").unwrap();
-        let mut file2 = FsFile::create(&file2_path).unwrap();
-        writeln!(file2, "File 2 contents").unwrap();
+        let results = process_directory(input_dir.path(), output_dir.path()).unwrap();
 
-        // Use for_each_file to count the files
-        let mut count = 0;
-        for_each_file(temp.path(), &mut |path| {
-            if path.is_file() {
-                count += 1;
-            }
-            Ok(())
-        })
-        .unwrap();
+        // The excluded subdirectory, the ignore files themselves and the nested exclusion are
+        // skipped, while the nested negation re-includes a file excluded from above
+        let mut processed: Vec<_> = results
+            .iter()
+            .map(|result| result.input.strip_prefix(input_dir.path()).unwrap().to_str().unwrap().replace('\\', "/"))
+            .collect();
+        processed.sort();
+        assert_eq!(processed, ["app.js", "css/keep.log", "css/main.css"]);
 
-        // Verify that both files were counted
-        assert_eq!(count, 2);
+        // Without ignore files every file with an extension is processed
+        let options = ProcessOptions {
+            ignore_filename: None,
+            only_extensions: Some(["js", "log", "md", "css"].map(str::to_string).into()),
+            ..ProcessOptions::default()
+        };
+        let results = process_directory_with_options(input_dir.path(), tempdir().unwrap().path(), &options).unwrap();
+        assert_eq!(results.len(), files.len());
     }
 
     #[test]
-    fn test_minify_css() {
-        use std::str;
+    fn test_process_directory_results() {
+        use tempfile::tempdir;
 
-        let input_file = File {
-            filename: "example.css".into(),
-            file_type: FileType::CSS,
-            contents: b"body { color: red; }  /* comment */".to_vec(),
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let md_path = input_dir.path().join("index.md");
+        let js_path = input_dir.path().join("app.js");
+        let unknown_path = input_dir.path().join("notes.txt");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&md_path, "# Title").unwrap();
+        fs::write(&js_path, "console.log(1);").unwrap();
+        fs::write(&unknown_path, "notes").unwrap();
+
+        let options = ProcessOptions { unknown_file_policy: UnknownFilePolicy::Skip, ..ProcessOptions::default() };
+        let mut results = process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+        results.sort_by(|a, b| a.input.cmp(&b.input));
+
+        let summary: Vec<_> = results.iter().map(|result| (&result.input, result.file_type, result.outcome)).collect();
+        assert_eq!(summary, vec![
+            (&js_path, FileType::JS, FileOutcome::Copied),
+            (&md_path, FileType::HTML, FileOutcome::Transformed),
+            (&css_path, FileType::CSS, FileOutcome::Transformed),
+            (&unknown_path, FileType::Other, FileOutcome::Skipped),
+        ]);
+
+        // Outputs and sizes describe the files on disk
+        let css = &results[2];
+        assert_eq!(css.input_size, 20);
+        assert_eq!(css.output_size, "body{color:red}".len() as u64);
+        assert!(output_dir.path().join(css.output.as_ref().unwrap()).exists());
+        assert_eq!(results[3].output, None);
+    }
+
+    #[test]
+    fn test_verbatim_dirs() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let vendor_dir = input_dir.path().join("vendor").join("lib");
+        fs::create_dir_all(&vendor_dir).unwrap();
+        let vendor_path = vendor_dir.join("lib.css");
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&vendor_path, "body { color: red; }").unwrap();
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions { verbatim_dirs: vec!["vendor".to_string()], ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+
+        // The vendored file keeps its name and contents, the other one is processed as usual
+        assert_eq!(manifest[vendor_path.to_str().unwrap()], "vendor/lib/lib.css");
+        assert_eq!(fs::read_to_string(output_dir.path().join("vendor/lib/lib.css")).unwrap(), "body { color: red; }");
+        let hashed_filename = &manifest[css_path.to_str().unwrap()];
+        assert_eq!(fs::read_to_string(output_dir.path().join(hashed_filename)).unwrap(), "body{color:red}");
+    }
+
+    #[test]
+    fn test_ambiguous_basename_warning() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let first = input_dir.path().join("a").join("logo.png");
+        let second = input_dir.path().join("b").join("logo.png");
+        let identical = input_dir.path().join("c").join("logo.png");
+        for path in [&first, &second, &identical] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+        }
+        fs::write(&first, b"first logo").unwrap();
+        fs::write(&second, b"second logo").unwrap();
+        fs::write(&identical, b"first logo").unwrap();
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let options = {
+            let warnings = warnings.clone();
+            ProcessOptions {
+                on_warning: Some(WarningHandler::new(move |warning| warnings.lock().unwrap().push(warning.clone()))),
+                ..ProcessOptions::default()
+            }
         };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 
-        let result = minify_css(input_file).unwrap();
+        // Only the copy with different contents is ambiguous
+        assert_eq!(*warnings.lock().unwrap(), vec![Warning::AmbiguousBasename { first, second }]);
+    }
 
-        assert_eq!(result.file_type, FileType::CSS);
-        assert_eq!(result.filename, "example.css");
-        assert!(str::from_utf8(&result.contents).unwrap().contains("body{color:red}"));
+    #[test]
+    fn test_expected_output_name() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let md_path = input_dir.path().join("index.md");
+        let image_path = input_dir.path().join("logo.png");
+        fs::write(&md_path, "# Title").unwrap();
+        fs::write(&image_path, b"not really a png").unwrap();
+
+        let options = ProcessOptions { shard_depth: 2, fast_passthrough: true, ..ProcessOptions::default() };
+        let expected_md = expected_output_name(&md_path, &options).unwrap();
+        let expected_image = expected_output_name(&image_path, &options).unwrap();
+
+        // Nothing is written until the files are actually processed
+        assert_eq!(fs::read_dir(output_dir.path()).unwrap().count(), 0);
+
+        let mut manifest = HashMap::new();
+        process_file(&md_path, Some(FileType::Markdown), output_dir.path(), &options, &mut manifest, &OutputPaths::default()).unwrap();
+        process_file(&image_path, Some(FileType::Image), output_dir.path(), &options, &mut manifest, &OutputPaths::default()).unwrap();
+        assert_eq!(manifest[md_path.to_str().unwrap()].hashed, expected_md);
+        assert_eq!(manifest[image_path.to_str().unwrap()].hashed, expected_image);
+        assert!(expected_md.ends_with(".html"));
     }
 
     #[test]
-    fn test_minify_css_non_css_file() {
-        let input_file = File {
-            filename: "example.txt".into(),
-            file_type: FileType::Other,
-            contents: b"Some random text".to_vec(),
+    fn test_type_output_dirs() {
+        use manifest::Manifest;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let image_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let image_path = input_dir.path().join("logo.png");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&image_path, b"not really a png").unwrap();
+
+        let options = ProcessOptions {
+            type_output_dirs: HashMap::from([(FileType::Image, image_dir.path().to_path_buf())]),
+            ..ProcessOptions::default()
         };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 
-        let result = minify_css(input_file).unwrap();
+        let manifest = Manifest::load(&output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
 
-        assert_eq!(result.file_type, FileType::Other);
-        assert_eq!(result.filename, "example.txt");
-        assert_eq!(result.contents, b"Some random text");
+        // The image only exists in its own directory, which the manifest records
+        let image = manifest.entry(image_path.to_str().unwrap()).unwrap();
+        assert_eq!(image.dir.as_deref(), Some(image_dir.path().to_str().unwrap()));
+        assert!(image_dir.path().join(&image.hashed).exists());
+        assert!(!output_dir.path().join(&image.hashed).exists());
+
+        // Everything else stays in the regular output directory
+        let css = manifest.entry(css_path.to_str().unwrap()).unwrap();
+        assert_eq!(css.dir, None);
+        assert!(output_dir.path().join(&css.hashed).exists());
     }
 
     #[test]
-    fn test_process_file() {
-        use std::fs::{self, File as FsFile};
-        use std::io::Write;
+    fn test_rewrite_css_urls() {
         use tempfile::tempdir;
 
-        // Create temporary directories for input and output
         let input_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("css").join("main.css");
+        let image_path = input_dir.path().join("img").join("logo.png");
+        fs::create_dir_all(css_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+        fs::write(&css_path, "body { background: url(../img/logo.png); } p { background: url(https://example.com/a.png); }").unwrap();
+        fs::write(&image_path, b"not really a png").unwrap();
 
-        // Create a sample input file
-        let input_file_path = input_dir.path().join("example.css");
-        let mut input_file = FsFile::create(&input_file_path).unwrap();
-        writeln!(input_file, "body {{ color: red; }}").unwrap();
+        let options = ProcessOptions { rewrite_css_urls: true, shard_depth: 2, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 
-        // Create a manifest to track processed files
-        let mut manifest = HashMap::new();
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let css_output = output_dir.path().join(&manifest[css_path.to_str().unwrap()]);
+        let image_output = output_dir.path().join(&manifest[image_path.to_str().unwrap()]);
 
-        // Process the file
-        process_file(&input_file_path, output_dir.path(), &mut manifest).unwrap();
+        // The rewritten URL resolves from the stylesheet's shard to the image's, absolute URLs are untouched
+        let css = fs::read_to_string(&css_output).unwrap();
+        let url = css.split("url(").nth(1).unwrap().split(')').next().unwrap();
+        assert!(url.starts_with("../"));
+        assert_eq!(fs::read(css_output.parent().unwrap().join(url)).unwrap(), fs::read(image_output).unwrap());
+        assert!(css.contains("url(https://example.com/a.png)"));
+    }
 
-        // Verify the manifest contains the correct mapping
-        let hashed_filename = manifest.get(&input_file_path.to_string_lossy().to_string()).unwrap();
-        let output_file_path = output_dir.path().join(hashed_filename);
-        assert!(output_file_path.exists());
+    #[test]
+    fn test_rewrite_css_urls_query_and_fragment() {
+        use tempfile::tempdir;
 
-        // Verify the contents of the processed file
-        let processed_contents = fs::read_to_string(output_file_path).unwrap();
-        assert!(processed_contents.contains("body{color:red}"));
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let sprite_path = input_dir.path().join("sprite.svg");
+        fs::write(&css_path, "a { background: url(sprite.svg?v=1#icon); } b { background: url(sprite.svg#other); }").unwrap();
+        fs::write(&sprite_path, "<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>").unwrap();
+
+        let options = ProcessOptions { rewrite_css_urls: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let css = fs::read_to_string(output_dir.path().join(&manifest[css_path.to_str().unwrap()])).unwrap();
+        let sprite_filename = &manifest[sprite_path.to_str().unwrap()];
+
+        assert!(css.contains(&format!("{}?v=1#icon", sprite_filename)), "{css}");
+        assert!(css.contains(&format!("{}#other", sprite_filename)), "{css}");
+        assert!(!css.contains("sprite.svg"));
     }
 
     #[test]
-    fn test_process_directory() {
-        use std::fs::{self, File as FsFile};
-        use std::io::Write;
+    fn test_rewrite_css_urls_minified() {
         use tempfile::tempdir;
 
-        // Create temporary directories for input and output
         let input_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("vendor.min.css");
+        let image_path = input_dir.path().join("logo.png");
+        fs::write(&css_path, "a{color:red}b{background:url(logo.png)}").unwrap();
+        fs::write(&image_path, b"not really a png").unwrap();
 
-        // Create sample input files
-        let input_file1_path = input_dir.path().join("example1.css");
-        let input_file2_path = input_dir.path().join("example2.js");
-        let mut input_file1 = FsFile::create(&input_file1_path).unwrap();
-        let mut input_file2 = FsFile::create(&input_file2_path).unwrap();
-        writeln!(input_file1, "body {{ color: red; }}").unwrap();
-        writeln!(input_file2, "console.log('Hello, world!');").unwrap();
+        let options = ProcessOptions { rewrite_css_urls: true, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 
-        // Process the directory
-        process_directory(input_dir.path(), output_dir.path()).unwrap();
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let css = fs::read_to_string(output_dir.path().join(&manifest[css_path.to_str().unwrap()])).unwrap();
 
-        // Verify the output directory contains processed files
-        let output_files: Vec<_> = fs::read_dir(output_dir.path())
-            .unwrap()
-            .map(|entry| entry.unwrap().path())
-            .collect();
+        // An already minified stylesheet isn't pretty-printed by the rewrite
+        assert_eq!(css, format!("a{{color:red}}b{{background:url({})}}", manifest[image_path.to_str().unwrap()]));
+    }
 
-        assert!(!output_files.is_empty());
+    #[test]
+    fn test_extract_data_uris() {
+        use base64::Engine;
+        use tempfile::tempdir;
 
-        // Verify the manifest file exists
-        let manifest_path = output_dir.path().join("manifest.json");
-        assert!(manifest_path.exists());
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let mut large = b"\x89PNG\r\n\x1a\n".to_vec();
+        large.resize(256, 0);
+        let encode = |contents: &[u8]| base64::engine::general_purpose::STANDARD.encode(contents);
+        fs::write(&css_path, format!(
+            "a {{ background: url(data:image/png;base64,{}); }} b {{ background: url(data:image/png;base64,{}); }}",
+            encode(&large),
+            encode(b"tiny")
+        )).unwrap();
 
-        // Verify the manifest file contents
-        let manifest_contents = fs::read_to_string(manifest_path).unwrap();
-        let manifest: HashMap<String, String> = serde_json::from_str(&manifest_contents).unwrap();
-        assert!(manifest.contains_key(&input_file1_path.to_string_lossy().to_string()));
-        assert!(manifest.contains_key(&input_file2_path.to_string_lossy().to_string()));
+        let options = ProcessOptions { extract_data_uris: Some(128), shard_depth: 2, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
 
-        // Verify the processed CSS file is minified
-        let hashed_filename = manifest.get(&input_file1_path.to_string_lossy().to_string()).unwrap();
-        let processed_css_path = output_dir.path().join(hashed_filename);
-        let processed_css_contents = fs::read_to_string(processed_css_path).unwrap();
-        assert!(processed_css_contents.contains("body{color:red}"));
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let css_output = output_dir.path().join(&manifest[css_path.to_str().unwrap()]);
+        let css = fs::read_to_string(&css_output).unwrap();
 
-        // Verify the JS file is unchanged
-        let hashed_filename = manifest.get(&input_file2_path.to_string_lossy().to_string()).unwrap();
-        let processed_js_path = output_dir.path().join(hashed_filename);
-        let processed_js_contents = fs::read_to_string(processed_js_path).unwrap();
-        assert!(processed_js_contents.contains("console.log('Hello, world!');"));
+        // The large image is referenced from its own file, the small one stays inline
+        let url = css.split("url(").nth(1).unwrap().split(')').next().unwrap();
+        assert!(url.ends_with(".png"), "{css}");
+        assert_eq!(fs::read(css_output.parent().unwrap().join(url)).unwrap(), large);
+        assert_eq!(css.matches("data:image/png").count(), 1);
+    }
+
+    #[test]
+    fn test_single_file_input_filtered() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let js_path = input_dir.path().join("app.js");
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&js_path, "console.log(1);").unwrap();
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = ProcessOptions {
+            only_extensions: Some(HashSet::from(["css".to_string()])),
+            ..ProcessOptions::default()
+        };
+
+        // An excluded file given directly is skipped, like it would be inside a directory
+        let results = process_directory_with_options(&js_path, output_dir.path(), &options).unwrap();
+        assert!(results.is_empty());
+
+        let results = process_directory_with_options(&css_path, output_dir.path(), &options).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].input, css_path);
+    }
+
+    #[test]
+    fn test_min_hash_size() {
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let small_path = input_dir.path().join("tiny.js");
+        let large_path = input_dir.path().join("large.js");
+        fs::write(&small_path, b"let a = 1;").unwrap();
+        fs::write(&large_path, "console.log(\"a somewhat longer script that is hashed\");").unwrap();
+
+        let options = ProcessOptions { min_hash_size: 50, ..ProcessOptions::default() };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join(DEFAULT_MANIFEST_FILENAME)).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+
+        // The 10-byte file keeps its name, the larger one is hashed
+        assert_eq!(manifest[small_path.to_str().unwrap()], "tiny.js");
+        assert_eq!(fs::read(output_dir.path().join("tiny.js")).unwrap(), b"let a = 1;");
+        assert_ne!(manifest[large_path.to_str().unwrap()], "large.js");
+        assert!(output_dir.path().join(&manifest[large_path.to_str().unwrap()]).exists());
     }
 }
\ No newline at end of file
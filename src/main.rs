@@ -1,5 +1,31 @@
-use std::path::Path;
+use std::{io, path::Path, process};
+use static_preprocessing::{detect_file_type, process_stdin, watch::{watch_directory, WatchOptions}};
 
 fn main() {
-    let _ = static_preprocessing::process_directory(Path::new("static-test-files"), Path::new("dest"));
+    let input_dir = Path::new("static-test-files");
+    let output_dir = Path::new("dest");
+    let args: Vec<String> = std::env::args().collect();
+
+    if let Some(ext) = args.iter().position(|arg| arg == "--type").and_then(|i| args.get(i + 1)) {
+        // Process stdin to stdout, reporting the hashed name on stderr
+        match process_stdin(detect_file_type(ext), ext, io::stdin().lock(), io::stdout().lock()) {
+            Ok(filename) => eprintln!("{}", filename),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    } else if args.iter().any(|arg| arg == "--watch") {
+        let watched = watch_directory(input_dir, output_dir, &WatchOptions::default(), |paths| {
+            for path in paths {
+                println!("Processed {}", path.display());
+            }
+        });
+        if let Err(err) = watched {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    } else {
+        let _ = static_preprocessing::process_directory(input_dir, output_dir);
+    }
 }
\ No newline at end of file
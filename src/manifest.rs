@@ -0,0 +1,720 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use crate::{for_each_file, normalize_path, LibError};
+
+/// The `Cache-Control` value suggested for files with hashed names, which never change.
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The `Cache-Control` value suggested for files that keep their name, which may change between builds.
+pub const SHORT_CACHE_CONTROL: &str = "public, max-age=300, must-revalidate";
+
+/// The reserved manifest key holding the [`BuildMetadata`], if recorded.
+pub const META_KEY: &str = "__meta__";
+
+/// The fields of a [`ManifestEntry`], which its custom `metadata` must not reuse as keys.
+pub const RESERVED_KEYS: &[&str] = &[
+    "hashed",
+    "short",
+    "dir",
+    "width",
+    "height",
+    "original",
+    "cache_control",
+    "content_type",
+    "csp",
+    "debug",
+    "size",
+    "gzip_size",
+    "brotli_size",
+    "formats",
+    "chunks"
+];
+
+/// The provenance of a build, recorded in the manifest under [`META_KEY`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildMetadata {
+    /// The version of this crate that wrote the manifest.
+    pub version: String,
+    /// When the manifest was written, in UTC, e.g. `2024-05-01T12:30:00Z`. Reproducible builds
+    /// record `SOURCE_DATE_EPOCH` instead, or leave this out when it isn't set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// The build id supplied by the caller.
+    pub build_id: String
+}
+
+impl BuildMetadata {
+    /// Creates the metadata of a build happening now.
+    pub fn new(build_id: &str) -> Self {
+        BuildMetadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: Some(utc_timestamp(SystemTime::now())),
+            build_id: build_id.to_string()
+        }
+    }
+
+    /// Creates the metadata of a reproducible build, timestamped with the `SOURCE_DATE_EPOCH`
+    /// environment variable if it holds a valid time, and untimestamped otherwise.
+    pub fn reproducible(build_id: &str) -> Self {
+        Self::at_source_date_epoch(build_id, std::env::var("SOURCE_DATE_EPOCH").ok().as_deref())
+    }
+
+    /// Creates the metadata of a build timestamped with the given `SOURCE_DATE_EPOCH` value.
+    fn at_source_date_epoch(build_id: &str, source_date_epoch: Option<&str>) -> Self {
+        let seconds = source_date_epoch.and_then(|epoch| epoch.trim().parse::<u64>().ok());
+        BuildMetadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: seconds.map(|seconds| utc_timestamp(UNIX_EPOCH + std::time::Duration::from_secs(seconds))),
+            build_id: build_id.to_string()
+        }
+    }
+}
+
+/// The manifest entry of a single processed file.
+///
+/// Unless extra fields are enabled in the [`ProcessOptions`](crate::ProcessOptions), entries are
+/// written to the manifest as just the hashed filename.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The hashed filename, relative to the output directory.
+    pub hashed: String,
+    /// The first 7 characters of the hash, for use in logs and dashboards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short: Option<String>,
+    /// The output directory the file was routed to, when it differs from the one holding the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// The intrinsic width of an image, in pixels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// The intrinsic height of an image, in pixels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// The original filename of the file, without its directory, e.g. for display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+    /// The suggested `Cache-Control` header for serving the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
+    /// The MIME type to serve the file with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The Content Security Policy hash of a file meant to be inlined, as `sha256-<base64>`.
+    /// No file is written for inlined files, so their `hashed` filename is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csp: Option<String>,
+    /// The pretty-printed debug copy of a stylesheet, relative to the output directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug: Option<String>,
+    /// The size of the output file, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The size of the gzip compressed sibling, in bytes, if one was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gzip_size: Option<u64>,
+    /// The size of the brotli compressed sibling, in bytes, if one was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brotli_size: Option<u64>,
+    /// The extensions an image is available in, the original's first. Every format shares the
+    /// stem of `hashed`, so a server can negotiate between them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formats: Option<Vec<String>>,
+    /// The numbered chunks a large stylesheet or script was split into, relative to the output
+    /// directory, in load order. Together they hold the same code as `hashed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<String>>,
+    /// Custom metadata for downstream tooling, written alongside the other fields. Keys must not
+    /// reuse the names of the other fields, listed in [`RESERVED_KEYS`].
+    #[serde(flatten)]
+    pub metadata: BTreeMap<String, serde_json::Value>
+}
+
+impl ManifestEntry {
+    /// Returns whether the entry records anything besides its hashed filename, so it must be
+    /// written as an object rather than a plain string to lose nothing.
+    pub fn is_detailed(&self) -> bool {
+        // Destructured so a new field can't be forgotten here
+        let ManifestEntry {
+            hashed: _,
+            short,
+            dir,
+            width,
+            height,
+            original,
+            cache_control,
+            content_type,
+            csp,
+            debug,
+            size,
+            gzip_size,
+            brotli_size,
+            formats,
+            chunks,
+            metadata
+        } = self;
+        short.is_some()
+            || dir.is_some()
+            || width.is_some()
+            || height.is_some()
+            || original.is_some()
+            || cache_control.is_some()
+            || content_type.is_some()
+            || csp.is_some()
+            || debug.is_some()
+            || size.is_some()
+            || gzip_size.is_some()
+            || brotli_size.is_some()
+            || formats.is_some()
+            || chunks.is_some()
+            || !metadata.is_empty()
+    }
+}
+
+/// A manifest entry as read from a manifest file, in either of its forms.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EntryRepr {
+    Flat(String),
+    Detailed(Box<ManifestEntry>)
+}
+
+/// A manifest entry as written to a manifest file.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum EntryRef<'a> {
+    Flat(&'a str),
+    Detailed(&'a ManifestEntry),
+    Meta(&'a BuildMetadata)
+}
+
+/// A manifest mapping original file paths to their hashed filenames.
+///
+/// This is the runtime counterpart of the `manifest.json` written by
+/// [`process_directory`](crate::process_directory), e.g. for a web server resolving asset names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    entries: HashMap<String, ManifestEntry>,
+    meta: Option<BuildMetadata>
+}
+
+impl Manifest {
+    /// Loads a manifest from a `manifest.json` file.
+    ///
+    /// Both the plain form, mapping paths to hashed filenames, and the detailed form, mapping
+    /// paths to [`ManifestEntry`] objects, are supported.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: The path to the manifest file.
+    ///
+    /// # Returns
+    ///
+    /// [`Ok`] containing the loaded [`Manifest`], or an error if the file could not be read or
+    /// is not a valid manifest.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::fs;
+    /// # use tempfile::tempdir;
+    /// # use static_preprocessing::manifest::Manifest;
+    /// #
+    /// let dir = tempdir().unwrap();
+    /// let path = dir.path().join("manifest.json");
+    /// fs::write(&path, r#"{ "css/main.css": "ab12.css" }"#).unwrap();
+    ///
+    /// let manifest = Manifest::load(&path).unwrap();
+    /// assert_eq!(manifest.get("css/main.css"), Some("ab12.css"));
+    /// assert_eq!(manifest.get("css/other.css"), None);
+    /// ```
+    pub fn load(path: &Path) -> Result<Manifest, LibError> {
+        let contents = fs::read_to_string(path)?;
+        let mut entries: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+            .map_err(|err| LibError::ParsingError(err.to_string()))?;
+        let meta = entries.remove(META_KEY).map(serde_json::from_value::<BuildMetadata>).transpose();
+        let entries = serde_json::from_value::<HashMap<String, EntryRepr>>(serde_json::Value::Object(entries));
+
+        Ok(Manifest {
+            entries: entries
+                .map_err(|err| LibError::ParsingError(err.to_string()))?
+                .into_iter()
+                .map(|(original, entry)| match entry {
+                    EntryRepr::Flat(hashed) => (original, ManifestEntry { hashed, ..ManifestEntry::default() }),
+                    EntryRepr::Detailed(entry) => (original, *entry)
+                })
+                .collect(),
+            meta: meta.map_err(|err| LibError::ParsingError(err.to_string()))?
+        })
+    }
+
+    /// Returns the hashed filename for an original file path, if it is in the manifest.
+    pub fn get(&self, original: &str) -> Option<&str> {
+        self.entries.get(original).map(|entry| entry.hashed.as_str())
+    }
+
+    /// Returns the full [`ManifestEntry`] for an original file path, if it is in the manifest.
+    pub fn entry(&self, original: &str) -> Option<&ManifestEntry> {
+        self.entries.get(original)
+    }
+
+    /// Returns the provenance of the build, if it was recorded.
+    pub fn meta(&self) -> Option<&BuildMetadata> {
+        self.meta.as_ref()
+    }
+
+    /// Creates a manifest from entries by original file path.
+    pub(crate) fn from_entries(entries: HashMap<String, ManifestEntry>) -> Manifest {
+        Manifest { entries, meta: None }
+    }
+
+    /// Returns the entries of the manifest, by original file path.
+    pub(crate) fn into_entries(self) -> HashMap<String, ManifestEntry> {
+        self.entries
+    }
+}
+
+/// Merges entries into a manifest file shared between several processes.
+///
+/// The manifest is locked for the whole read-merge-write cycle, through a `<manifest>.lock` file
+/// next to it, and replaced atomically, so workers finishing at the same time never lose each
+/// other's entries. New entries replace existing ones with the same key. The manifest is
+/// written in detailed form if any entry has extra fields.
+///
+/// # Parameters
+///
+/// - `existing_path`: The path of the manifest file, which is created if it doesn't exist yet.
+/// - `new_entries`: The entries to merge in.
+///
+/// # Returns
+///
+/// [`Ok`] once the merged manifest has been written, or an error if the lock can't be taken or
+/// the manifest can't be read or written.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::manifest::{merge_into_manifest, Manifest, ManifestEntry};
+/// #
+/// let dir = tempdir().unwrap();
+/// let path = dir.path().join("manifest.json");
+///
+/// let entry = |hashed: &str| ManifestEntry { hashed: hashed.to_string(), ..ManifestEntry::default() };
+/// merge_into_manifest(&path, &HashMap::from([("main.css".to_string(), entry("ab12.css"))])).unwrap();
+/// merge_into_manifest(&path, &HashMap::from([("app.js".to_string(), entry("cd34.js"))])).unwrap();
+///
+/// let manifest = Manifest::load(&path).unwrap();
+/// assert_eq!(manifest.get("main.css"), Some("ab12.css"));
+/// assert_eq!(manifest.get("app.js"), Some("cd34.js"));
+/// ```
+pub fn merge_into_manifest(existing_path: &Path, new_entries: &HashMap<String, ManifestEntry>) -> Result<(), LibError> {
+    let lock = fs::File::create(sibling(existing_path, ".lock"))?;
+    lock.lock_exclusive()?;
+
+    // The build metadata of the existing manifest is kept, as merging isn't a new build
+    let (mut entries, meta) = if existing_path.exists() {
+        let manifest = Manifest::load(existing_path)?;
+        (manifest.entries, manifest.meta)
+    } else {
+        (HashMap::new(), None)
+    };
+    entries.extend(new_entries.iter().map(|(original, entry)| (original.clone(), entry.clone())));
+
+    let detailed = entries.values().any(ManifestEntry::is_detailed);
+    let json = to_json(&entries, detailed, meta.as_ref(), true).map_err(io::Error::other)?;
+
+    // Replace the manifest atomically, so readers never see a partial file
+    let temp_path = sibling(existing_path, &format!(".{}.tmp", std::process::id()));
+    if let Err(err) = fs::write(&temp_path, json).and_then(|()| fs::rename(&temp_path, existing_path)) {
+        // Don't leave a partial file behind; the original error is the one worth reporting
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+
+    FileExt::unlock(&lock)?;
+    Ok(())
+}
+
+/// A disagreement between a manifest and the output directory, found by [`validate_manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestIssue {
+    /// The hashed file of a manifest entry doesn't exist.
+    Missing {
+        original: String,
+        path: PathBuf
+    },
+    /// A file in the output directory isn't referenced by any manifest entry.
+    Stray(PathBuf),
+    /// The manifest or the output directory couldn't be read.
+    Unreadable(String)
+}
+
+impl fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestIssue::Missing { original, path } => write!(f, "{} of {} is missing", path.display(), original),
+            ManifestIssue::Stray(path) => write!(f, "{} is not referenced by the manifest", path.display()),
+            ManifestIssue::Unreadable(error) => write!(f, "the manifest couldn't be checked: {}", error)
+        }
+    }
+}
+
+/// Checks that a manifest and the output directory it describes agree, e.g. before deploying.
+///
+/// Every hashed file referenced by the manifest must exist, in the directory it was routed to if
+/// any, and every file in `output_dir` must be referenced. Besides the hashed files themselves,
+/// their compressed siblings and `.sha256` sidecars, debug stylesheets, image variants, chunks and the
+/// manifest count as referenced, as do the other files generated next to the manifest under the
+/// names configured in `options`, like a file listing or an import map.
+///
+/// # Parameters
+///
+/// - `manifest_path`: The path of the manifest file.
+/// - `output_dir`: The output directory the manifest describes.
+/// - `options`: The [`ProcessOptions`](crate::ProcessOptions) the output was built with.
+///
+/// # Returns
+///
+/// [`Ok`] if they agree, or every [`ManifestIssue`] found, sorted.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::manifest::{validate_manifest, ManifestIssue};
+/// # use static_preprocessing::ProcessOptions;
+/// #
+/// let dir = tempdir().unwrap();
+/// let options = ProcessOptions::default();
+/// fs::write(dir.path().join("manifest.json"), r#"{ "main.css": "ab12.css" }"#).unwrap();
+/// fs::write(dir.path().join("ab12.css"), "body{margin:0}").unwrap();
+/// assert_eq!(validate_manifest(&dir.path().join("manifest.json"), dir.path(), &options), Ok(()));
+///
+/// fs::write(dir.path().join("old.css"), "").unwrap();
+/// assert_eq!(
+///     validate_manifest(&dir.path().join("manifest.json"), dir.path(), &options),
+///     Err(vec![ManifestIssue::Stray(dir.path().join("old.css"))])
+/// );
+/// ```
+pub fn validate_manifest(manifest_path: &Path, output_dir: &Path, options: &crate::ProcessOptions) -> Result<(), Vec<ManifestIssue>> {
+    let unreadable = |err: LibError| vec![ManifestIssue::Unreadable(err.to_string())];
+    let manifest = Manifest::load(manifest_path).map_err(unreadable)?;
+
+    let mut issues = Vec::new();
+    let mut referenced = HashSet::from([normalize_path(manifest_path), normalize_path(&sibling(manifest_path, ".lock"))]);
+    let mut entries: Vec<_> = manifest.entries.iter().collect();
+    entries.sort_by_key(|(original, _)| *original);
+    for (original, entry) in entries {
+        // Inlined files aren't written
+        if entry.hashed.is_empty() {
+            continue;
+        }
+        let dir = entry.dir.as_deref().map_or(output_dir, Path::new);
+        let path = dir.join(&entry.hashed);
+        if !path.is_file() {
+            issues.push(ManifestIssue::Missing { original: original.clone(), path: path.clone() });
+        }
+
+        let mut derived = vec![sibling(&path, ".gz"), sibling(&path, ".br"), sibling(&path, ".sha256")];
+        derived.extend(entry.debug.iter().map(|debug| dir.join(debug)));
+        derived.extend(entry.formats.iter().flatten().map(|format| path.with_extension(format)));
+        derived.extend(entry.chunks.iter().flatten().map(|chunk| dir.join(chunk)));
+        referenced.extend(derived.iter().chain([&path]).map(|path| normalize_path(path)));
+    }
+
+    for_each_file(output_dir, &mut |path| {
+        if !referenced.contains(&normalize_path(path)) && !crate::is_manifest(output_dir, path, options) {
+            issues.push(ManifestIssue::Stray(path.to_path_buf()));
+        }
+        Ok(())
+    }).map_err(unreadable)?;
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        issues.sort_by_key(|issue| issue.to_string());
+        Err(issues)
+    }
+}
+
+/// Returns `path` with `suffix` appended to its filename.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+/// Serializes manifest entries to JSON sorted by key, either as plain hashed filenames or as detailed entries, with the build metadata if given.
+pub(crate) fn to_json(entries: &HashMap<String, ManifestEntry>, detailed: bool, meta: Option<&BuildMetadata>, pretty: bool) -> Result<String, serde_json::Error> {
+    let mut entries: BTreeMap<&str, EntryRef> = entries
+        .iter()
+        .map(|(original, entry)| {
+            let entry = if detailed { EntryRef::Detailed(entry) } else { EntryRef::Flat(&entry.hashed) };
+            (original.as_str(), entry)
+        })
+        .collect();
+    if let Some(meta) = meta {
+        entries.insert(META_KEY, EntryRef::Meta(meta));
+    }
+
+    if pretty {
+        serde_json::to_string_pretty(&entries)
+    } else {
+        serde_json::to_string(&entries)
+    }
+}
+
+/// Serializes the reverse of manifest entries, mapping hashed filenames to original paths, to pretty JSON sorted by hashed filename.
+///
+/// Inlined entries, which have no hashed filename, are left out, and of several originals with
+/// the same output the first in sort order is kept.
+pub(crate) fn to_reverse_json(entries: &HashMap<String, ManifestEntry>) -> Result<String, serde_json::Error> {
+    let sorted: BTreeMap<&str, &ManifestEntry> = entries.iter().map(|(original, entry)| (original.as_str(), entry)).collect();
+    let mut reverse: BTreeMap<&str, &str> = BTreeMap::new();
+    for (original, entry) in sorted {
+        if !entry.hashed.is_empty() {
+            reverse.entry(&entry.hashed).or_insert(original);
+        }
+    }
+    serde_json::to_string_pretty(&reverse)
+}
+
+/// Formats a time as an ISO 8601 UTC timestamp with second precision.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since the epoch to a proleptic Gregorian date, counting from 0000-03-01
+    let z = days + 719468;
+    let (era, day_of_era) = (z / 146097, z % 146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_load() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        crate::process_directory(input_dir.path(), output_dir.path()).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+
+        // The entry resolves to the file that was written
+        let hashed_filename = manifest.get(&css_path.to_string_lossy()).unwrap();
+        assert!(hashed_filename.ends_with(".css"));
+        assert!(output_dir.path().join(hashed_filename).exists());
+    }
+
+    #[test]
+    fn test_manifest_load_detailed() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::write(&path, r#"{ "main.css": { "hashed": "abcdef123.css", "short": "abcdef1" } }"#).unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+
+        assert_eq!(manifest.get("main.css"), Some("abcdef123.css"));
+        assert_eq!(manifest.entry("main.css").unwrap().short.as_deref(), Some("abcdef1"));
+    }
+
+    #[test]
+    fn test_manifest_entry_fields() {
+        let text = || Some(String::new());
+        let entry = ManifestEntry {
+            hashed: String::new(),
+            short: text(),
+            dir: text(),
+            width: Some(1),
+            height: Some(1),
+            original: text(),
+            cache_control: text(),
+            content_type: text(),
+            csp: text(),
+            debug: text(),
+            size: Some(1),
+            gzip_size: Some(1),
+            brotli_size: Some(1),
+            formats: Some(Vec::new()),
+            chunks: Some(Vec::new()),
+            metadata: BTreeMap::new()
+        };
+
+        // Any one field is enough to make an entry detailed
+        assert!(entry.is_detailed());
+        assert!(!ManifestEntry { hashed: "abc.css".to_string(), ..ManifestEntry::default() }.is_detailed());
+        assert!(ManifestEntry { gzip_size: Some(1), ..ManifestEntry::default() }.is_detailed());
+        assert!(ManifestEntry { height: Some(1), ..ManifestEntry::default() }.is_detailed());
+
+        // Every field an entry can be written with is reserved, and nothing else
+        let serde_json::Value::Object(fields) = serde_json::to_value(&entry).unwrap() else { panic!("entries are objects") };
+        let mut fields: Vec<_> = fields.keys().map(String::as_str).collect();
+        let mut reserved = RESERVED_KEYS.to_vec();
+        fields.sort();
+        reserved.sort();
+        assert_eq!(fields, reserved);
+    }
+
+    #[test]
+    fn test_manifest_load_negative() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::write(&path, "not json").unwrap();
+
+        assert!(matches!(Manifest::load(&path), Err(LibError::ParsingError(_))));
+    }
+
+    #[test]
+    fn test_manifest_build_metadata() {
+        use std::fs;
+        use std::time::Duration;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+
+        let options = crate::ProcessOptions { manifest_build_id: Some("ci-1234".to_string()), ..crate::ProcessOptions::default() };
+        crate::process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        // The metadata block is plain JSON next to the entries
+        let manifest_path = output_dir.path().join("manifest.json");
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(json[META_KEY]["build_id"], "ci-1234");
+        assert_eq!(json[META_KEY]["version"], env!("CARGO_PKG_VERSION"));
+
+        let manifest = Manifest::load(&manifest_path).unwrap();
+        let meta = manifest.meta().unwrap();
+        assert_eq!(meta.build_id, "ci-1234");
+        assert_eq!(meta.timestamp.as_ref().unwrap().len(), "2000-01-01T00:00:00Z".len());
+        assert!(manifest.get(&css_path.to_string_lossy()).unwrap().ends_with(".css"));
+        assert_eq!(manifest.get(META_KEY), None);
+
+        assert_eq!(utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)), "2000-02-29T12:34:56Z");
+
+        // Merging more entries keeps the metadata of the build
+        let entry = ManifestEntry { hashed: "cd34.js".to_string(), ..ManifestEntry::default() };
+        merge_into_manifest(&manifest_path, &HashMap::from([("app.js".to_string(), entry)])).unwrap();
+        let merged = Manifest::load(&manifest_path).unwrap();
+        assert_eq!(merged.meta(), Some(meta));
+        assert_eq!(merged.get("app.js"), Some("cd34.js"));
+
+        // Reproducible builds take their time from SOURCE_DATE_EPOCH, or record none
+        let pinned = BuildMetadata::at_source_date_epoch("ci-1234", Some("951827696"));
+        assert_eq!(pinned.timestamp.as_deref(), Some("2000-02-29T12:34:56Z"));
+        for epoch in [None, Some("yesterday")] {
+            let untimed = BuildMetadata::at_source_date_epoch("ci-1234", epoch);
+            assert_eq!(untimed.timestamp, None);
+            let json = serde_json::to_value(&untimed).unwrap();
+            assert!(json.get("timestamp").is_none());
+            assert_eq!(serde_json::from_value::<BuildMetadata>(json).unwrap(), untimed);
+        }
+    }
+
+    #[test]
+    fn test_validate_manifest() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        fs::write(&css_path, "body { color: red; }".repeat(100)).unwrap();
+        fs::write(input_dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        // The generated files next to the manifest aren't strays
+        let options = crate::ProcessOptions {
+            compression: true,
+            file_listing: Some("files.txt".to_string()),
+            reverse_manifest: Some("reverse.json".to_string()),
+            ..crate::ProcessOptions::default()
+        };
+        crate::process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+        let manifest_path = output_dir.path().join("manifest.json");
+        assert_eq!(validate_manifest(&manifest_path, output_dir.path(), &options), Ok(()));
+        let issues = validate_manifest(&manifest_path, output_dir.path(), &crate::ProcessOptions::default()).unwrap_err();
+        assert_eq!(issues, [
+            ManifestIssue::Stray(output_dir.path().join("files.txt")),
+            ManifestIssue::Stray(output_dir.path().join("reverse.json"))
+        ]);
+
+        // Delete a referenced file and add a stray one
+        let hashed = Manifest::load(&manifest_path).unwrap().get(&css_path.to_string_lossy()).unwrap().to_string();
+        fs::remove_file(output_dir.path().join(&hashed)).unwrap();
+        fs::write(output_dir.path().join("stray.txt"), "left over").unwrap();
+
+        let issues = validate_manifest(&manifest_path, output_dir.path(), &options).unwrap_err();
+        assert_eq!(issues.len(), 2);
+        assert!(issues.contains(&ManifestIssue::Missing {
+            original: css_path.to_string_lossy().to_string(),
+            path: output_dir.path().join(&hashed)
+        }));
+        assert!(issues.contains(&ManifestIssue::Stray(output_dir.path().join("stray.txt"))));
+    }
+
+    #[test]
+    fn test_merge_into_manifest_concurrently() {
+        use std::thread;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+
+        let workers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|worker| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for i in 0..20 {
+                        let entry = ManifestEntry { hashed: format!("{worker}{i}.css"), ..ManifestEntry::default() };
+                        merge_into_manifest(&path, &HashMap::from([(format!("{worker}/{i}.css"), entry)])).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // No update was lost
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.entries.len(), 40);
+        assert_eq!(manifest.get("a/19.css"), Some("a19.css"));
+        assert_eq!(manifest.get("b/0.css"), Some("b0.css"));
+    }
+}
\ No newline at end of file
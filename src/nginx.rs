@@ -0,0 +1,83 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::manifest::ManifestEntry;
+
+/// Options controlling the nginx `map` file.
+#[derive(Debug, Clone)]
+pub struct NginxMapOptions {
+    /// The filename of the map file within the output directory.
+    pub filename: String,
+    /// The variable matched against the original paths, e.g. `$uri`.
+    pub source: String,
+    /// The variable set to the hashed URL of the matching asset.
+    pub variable: String,
+    /// The URL the output directory is served from, prepended to every hashed filename.
+    pub base_url: String
+}
+
+impl Default for NginxMapOptions {
+    fn default() -> Self {
+        NginxMapOptions {
+            filename: "manifest.map".to_string(),
+            source: "$uri".to_string(),
+            variable: "$hashed_asset".to_string(),
+            base_url: "/".to_string()
+        }
+    }
+}
+
+/// Renders the manifest as an nginx `map` block, sorted by original path, for inclusion in an nginx configuration.
+pub(crate) fn render_map(manifest: &HashMap<String, ManifestEntry>, options: &NginxMapOptions) -> String {
+    // Inlined files have no output to map to
+    let entries: BTreeMap<&str, &str> = manifest
+        .iter()
+        .filter(|(_, entry)| !entry.hashed.is_empty())
+        .map(|(original, entry)| (original.as_str(), entry.hashed.as_str()))
+        .collect();
+
+    let mut map = format!("map {} {} {{\n", options.source, options.variable);
+    for (original, hashed) in entries {
+        map.push_str(&format!("    {} {};\n", quote(original), quote(&format!("{}{}", options.base_url, hashed))));
+    }
+    map.push_str("}\n");
+    map
+}
+
+/// Quotes a string for an nginx configuration, escaping quotes and backslashes.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nginx_map() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::{process_directory_with_options, ProcessOptions};
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(input_dir.path().join("main.css"), "body { color: red; }").unwrap();
+        fs::write(input_dir.path().join("say \"hi\".js"), "console.log('hi');").unwrap();
+
+        let options = ProcessOptions {
+            nginx_map: Some(NginxMapOptions { base_url: "/static/".to_string(), ..NginxMapOptions::default() }),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: BTreeMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let map = fs::read_to_string(output_dir.path().join("manifest.map")).unwrap();
+
+        let mut expected = "map $uri $hashed_asset {\n".to_string();
+        for (original, hashed) in &manifest {
+            expected.push_str(&format!("    \"{}\" \"/static/{}\";\n", original.replace('"', "\\\""), hashed));
+        }
+        expected.push_str("}\n");
+        assert_eq!(map, expected);
+        assert!(map.contains("say \\\"hi\\\".js\" \"/static/"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf}
+};
+use crate::{detect_file_type, manifest::ManifestEntry, FileType};
+
+/// Options controlling the `<link rel="preload">` hints file.
+#[derive(Debug, Clone)]
+pub struct PreloadOptions {
+    /// The types of files to preload.
+    pub types: HashSet<FileType>,
+    /// Source paths to preload regardless of their type, as they appear in the manifest.
+    pub paths: HashSet<PathBuf>,
+    /// The filename of the hints file within the output directory.
+    pub filename: String,
+    /// The URL the output directory is served from, prepended to every hashed filename.
+    pub base_url: String
+}
+
+impl Default for PreloadOptions {
+    fn default() -> Self {
+        PreloadOptions {
+            types: HashSet::from([FileType::CSS]),
+            paths: HashSet::new(),
+            filename: "preload.html".to_string(),
+            base_url: "/".to_string()
+        }
+    }
+}
+
+/// Returns the `as` attribute of a preload hint for a processed file of the given type.
+///
+/// # Parameters
+///
+/// - `file_type`: The [`FileType`] of the processed file.
+///
+/// # Returns
+///
+/// The destination to use for the `as` attribute, or `None` if files of this type can't be preloaded.
+///
+/// # Examples
+///
+/// ```
+/// # use static_preprocessing::{preload::preload_as, FileType};
+/// assert_eq!(preload_as(FileType::CSS), Some("style"));
+/// assert_eq!(preload_as(FileType::Other), None);
+/// ```
+pub fn preload_as(file_type: FileType) -> Option<&'static str> {
+    match file_type {
+        FileType::CSS => Some("style"),
+        FileType::JS => Some("script"),
+        FileType::Image | FileType::SVG => Some("image"),
+        FileType::Font => Some("font"),
+        FileType::JSON | FileType::Wasm => Some("fetch"),
+        FileType::HTML | FileType::Markdown | FileType::Video | FileType::Scss | FileType::Other => None
+    }
+}
+
+/// Renders the preload hints for the selected manifest entries, one `<link>` per line, sorted by URL.
+pub(crate) fn render_hints(manifest: &HashMap<String, ManifestEntry>, options: &PreloadOptions) -> String {
+    let mut hints: Vec<(String, &str)> = manifest
+        .iter()
+        .filter_map(|(original, entry)| {
+            let file_type = Path::new(&entry.hashed)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map_or(FileType::Other, detect_file_type);
+            let selected = options.types.contains(&file_type) || options.paths.contains(Path::new(original));
+            let destination = preload_as(file_type).filter(|_| selected)?;
+            Some((format!("{}{}", options.base_url, entry.hashed), destination))
+        })
+        .collect();
+    hints.sort();
+
+    hints
+        .into_iter()
+        .map(|(href, destination)| {
+            // Fonts and fetches are always requested in CORS mode, so the hint must match
+            let crossorigin = if matches!(destination, "font" | "fetch") { " crossorigin" } else { "" };
+            format!(
+                "<link rel=\"preload\" href=\"{}\" as=\"{}\"{}>\n",
+                href.replace('&', "&amp;").replace('"', "&quot;"),
+                destination,
+                crossorigin
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preload_hints() {
+        use std::fs;
+        use tempfile::tempdir;
+        use crate::{process_directory_with_options, ProcessOptions};
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let css_path = input_dir.path().join("main.css");
+        let js_path = input_dir.path().join("app.js");
+        let other_js_path = input_dir.path().join("lazy.js");
+        fs::write(&css_path, "body { color: red; }").unwrap();
+        fs::write(&js_path, "console.log(1);").unwrap();
+        fs::write(&other_js_path, "console.log(2);").unwrap();
+
+        let options = ProcessOptions {
+            preload: Some(PreloadOptions {
+                paths: HashSet::from([js_path.clone()]),
+                base_url: "/static/".to_string(),
+                ..PreloadOptions::default()
+            }),
+            ..ProcessOptions::default()
+        };
+        process_directory_with_options(input_dir.path(), output_dir.path(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let hints = fs::read_to_string(output_dir.path().join("preload.html")).unwrap();
+
+        // The stylesheet is selected by type, the script explicitly, the other script not at all
+        let css_filename = &manifest[css_path.to_str().unwrap()];
+        let js_filename = &manifest[js_path.to_str().unwrap()];
+        let other_js_filename = &manifest[other_js_path.to_str().unwrap()];
+        assert!(hints.contains(&format!("<link rel=\"preload\" href=\"/static/{}\" as=\"style\">", css_filename)));
+        assert!(hints.contains(&format!("<link rel=\"preload\" href=\"/static/{}\" as=\"script\">", js_filename)));
+        assert!(!hints.contains(other_js_filename.as_str()));
+        assert_eq!(hints.lines().count(), 2);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError}
+    },
+    time::Duration
+};
+use notify_debouncer_full::{
+    new_debouncer,
+    notify::{EventKind, RecursiveMode},
+    DebounceEventResult
+};
+use crate::{collect_files, emit_manifests, is_input, process_tree, IgnoreRules, OutputPaths, warn_ambiguous_basenames, LibError, ProcessOptions};
+
+/// Options controlling [`watch_directory`].
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// How long to wait for a file to settle before reprocessing it.
+    pub debounce: Duration,
+    /// When set to `true`, the watcher stops and [`watch_directory`] returns.
+    pub stop: Arc<AtomicBool>,
+    /// The [`ProcessOptions`] used for every build.
+    pub process: ProcessOptions
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            debounce: Duration::from_millis(200),
+            stop: Arc::new(AtomicBool::new(false)),
+            process: ProcessOptions::default()
+        }
+    }
+}
+
+/// Watches a directory and reprocesses files as they change.
+///
+/// The whole of `input_dir` is processed once up front, after which every changed file is
+/// reprocessed into `output_dir` and the `manifest.json` is rewritten (unless disabled in the
+/// [`ProcessOptions`]). Events are debounced, so a burst of writes to the same file results in
+/// a single reprocess.
+///
+/// This function blocks until `options.stop` is set.
+///
+/// # Parameters
+///
+/// - `input_dir`: The root input directory to watch recursively.
+/// - `output_dir`: The root output directory where processed files are saved.
+/// - `options`: The [`WatchOptions`] to use.
+/// - `on_rebuild`: A closure called with the processed paths after the initial build and
+///   after every successful rebuild.
+///
+/// # Returns
+///
+/// [`Ok`] once the watcher has been stopped, or the error if the initial build or the watcher
+/// itself fails. Errors in later rebuilds are logged and don't stop the watcher.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::atomic::Ordering;
+/// # use tempfile::tempdir;
+/// # use static_preprocessing::watch::{watch_directory, WatchOptions};
+/// #
+/// let input_dir = tempdir().unwrap();
+/// let output_dir = tempdir().unwrap();
+///
+/// let options = WatchOptions::default();
+/// let stop = options.stop.clone();
+///
+/// // Stop as soon as the initial build is done.
+/// watch_directory(input_dir.path(), output_dir.path(), &options, |_| {
+///     stop.store(true, Ordering::SeqCst);
+/// }).unwrap();
+///
+/// assert!(output_dir.path().join("manifest.json").exists());
+/// ```
+pub fn watch_directory<F: FnMut(&[PathBuf])>(
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &WatchOptions,
+    mut on_rebuild: F
+) -> Result<(), LibError> {
+    options.process.prepare_output_dir(output_dir)?;
+
+    let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+    let mut debouncer = new_debouncer(options.debounce, None, tx)
+        .map_err(|err| LibError::WatchError(err.to_string()))?;
+    debouncer
+        .watch(input_dir, RecursiveMode::Recursive)
+        .map_err(|err| LibError::WatchError(err.to_string()))?;
+
+    let process = &*options.process.resolve(Some(input_dir));
+    let mut manifest = HashMap::new();
+    let mut outputs = OutputPaths::with_root(input_dir);
+    let paths = collect_files(input_dir, process)?;
+    let results = process_tree(input_dir, &paths, output_dir, process, &mut manifest, &mut outputs)?;
+    warn_ambiguous_basenames(&results, process);
+    emit_manifests(output_dir, process, &manifest, &outputs)?;
+    on_rebuild(&paths);
+
+    while !options.stop.load(Ordering::SeqCst) {
+        let events = match rx.recv_timeout(options.debounce) {
+            Ok(Ok(events)) => events,
+            Ok(Err(errors)) => return Err(LibError::WatchError(
+                errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join(", ")
+            )),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+
+        // Reading a file while processing it produces access events, so only
+        // creations and modifications trigger a rebuild.
+        // Ignore files are reloaded for every rebuild, so changes to them apply right away
+        let mut ignore_rules = IgnoreRules::new(input_dir, process);
+        let mut changed: Vec<PathBuf> = events
+            .into_iter()
+            .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+            .flat_map(|event| event.event.paths)
+            .filter(|path| path.is_file() && is_input(input_dir, path, process) && !ignore_rules.is_ignored(path))
+            .collect();
+        changed.sort();
+        changed.dedup();
+
+        if changed.is_empty() {
+            continue;
+        }
+
+        // A failed rebuild is reported and the watcher carries on, so the next save can fix it
+        outputs.start_run();
+        let rebuilt = process_tree(input_dir, &changed, output_dir, process, &mut manifest, &mut outputs)
+            .and_then(|_| emit_manifests(output_dir, process, &manifest, &outputs));
+        match rebuilt {
+            Ok(()) => on_rebuild(&changed),
+            Err(err) => process.log(log::Level::Error, format_args!("Rebuild failed: {}", err))
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_directory_debounces_changes() {
+        use std::fs;
+        use std::sync::Mutex;
+        use std::thread;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let watched_path = input_dir.path().join("watched.css");
+        let other_path = input_dir.path().join("other.css");
+        fs::write(&watched_path, "body { color: red; }").unwrap();
+        fs::write(&other_path, "p { color: blue; }").unwrap();
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(100),
+            ..WatchOptions::default()
+        };
+        let stop = options.stop.clone();
+        let rebuilds = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = {
+            let input_dir = input_dir.path().to_path_buf();
+            let output_dir = output_dir.path().to_path_buf();
+            let rebuilds = rebuilds.clone();
+            thread::spawn(move || {
+                watch_directory(&input_dir, &output_dir, &options, |paths| {
+                    rebuilds.lock().unwrap().push(paths.to_vec());
+                })
+            })
+        };
+
+        // Wait for the initial build
+        while rebuilds.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Write to the same file several times in quick succession
+        for _ in 0..3 {
+            fs::write(&watched_path, "body { color: green; }").unwrap();
+        }
+        thread::sleep(Duration::from_millis(1000));
+
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap().unwrap();
+
+        // The initial build processed both files, the burst of writes exactly one
+        let rebuilds = rebuilds.lock().unwrap();
+        assert_eq!(rebuilds.len(), 2);
+        assert_eq!(rebuilds[0].len(), 2);
+        assert_eq!(rebuilds[1], vec![watched_path.clone()]);
+
+        // The manifest points at the reprocessed contents
+        let manifest = fs::read_to_string(output_dir.path().join("manifest.json")).unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        let hashed_filename = manifest.get(&watched_path.to_string_lossy().to_string()).unwrap();
+        let contents = fs::read_to_string(output_dir.path().join(hashed_filename)).unwrap();
+        assert!(contents.contains("color:green"));
+    }
+
+    #[test]
+    fn test_watch_directory_survives_failed_rebuild() {
+        use std::fs;
+        use std::sync::Mutex;
+        use std::thread;
+        use tempfile::tempdir;
+
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let watched_path = input_dir.path().join("watched.css");
+        fs::write(&watched_path, "body { color: red; }").unwrap();
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(100),
+            ..WatchOptions::default()
+        };
+        let stop = options.stop.clone();
+        let rebuilds = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = {
+            let input_dir = input_dir.path().to_path_buf();
+            let output_dir = output_dir.path().to_path_buf();
+            let rebuilds = rebuilds.clone();
+            thread::spawn(move || {
+                watch_directory(&input_dir, &output_dir, &options, |paths| {
+                    rebuilds.lock().unwrap().push(paths.to_vec());
+                })
+            })
+        };
+
+        while rebuilds.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // A stylesheet that fails to parse doesn't stop the watcher, fixing it rebuilds as usual
+        fs::write(&watched_path, "body { color: red; } }").unwrap();
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(rebuilds.lock().unwrap().len(), 1);
+        assert!(!handle.is_finished());
+
+        fs::write(&watched_path, "body { color: green; }").unwrap();
+        thread::sleep(Duration::from_millis(1000));
+
+        stop.store(true, Ordering::SeqCst);
+        handle.join().unwrap().unwrap();
+        assert_eq!(rebuilds.lock().unwrap().len(), 2);
+    }
+}